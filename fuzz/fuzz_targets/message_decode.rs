@@ -0,0 +1,15 @@
+//! Feeds arbitrary bytes to the message decoders, which must return an error rather than panic on invalid input
+//!
+//! Run with `cargo fuzz run message_decode` from the repository root (requires a nightly toolchain).
+
+#![no_main]
+
+use bitcoin_p2p::messages::{codec::Decode, types::version::VersionMessage, Message, RawMessage};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Message::<VersionMessage>::decode_bytes(data);
+    if let Ok(raw_message) = RawMessage::decode_bytes(data) {
+        let _ = raw_message.into_message::<VersionMessage>();
+    }
+});
@@ -1,177 +1,2182 @@
-use anyhow::anyhow;
 use bitcoin_p2p::{
-    constants::{MAINNET_PORT_NUMBER, PROTOCOL_VERSION},
+    connection::PeerConnection,
+    constants::{MAX_USER_AGENT_LENGTH, PROTOCOL_VERSION},
+    handshake::{
+        build_user_agent, Handshake, HandshakeConfig, HandshakeResult, NonceSource, TimestampSource,
+    },
     messages::{
-        codec::{Decode, Encode},
-        types::{
-            verack::VerackMessage,
-            version::{Services, VersionMessage},
-        },
-        Chain, Message,
+        capture::{decode_capture, Direction},
+        codec::CodecError,
+        framed::DEFAULT_READ_BUFFER_SIZE,
+        types::version::{NetworkAddress, Services, VersionMessage},
+        Chain,
     },
 };
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
 use futures::{stream::FuturesUnordered, StreamExt};
+use rand::{rngs::StdRng, seq::SliceRandom, SeedableRng};
+use schemars::{schema_for, JsonSchema};
+use serde::Serialize;
 use std::str::FromStr;
 use std::{
-    net::SocketAddr,
-    time::{Duration, SystemTime},
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    future::Future,
+    io::Write,
+    net::{IpAddr, SocketAddr},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
 };
 use tokio::{
-    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
-    net::{lookup_host, TcpStream},
-    time::timeout,
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{lookup_host, TcpListener, TcpStream},
+    time::{error::Elapsed, sleep, timeout},
 };
 
-fn parse_services(services_bits: &str) -> anyhow::Result<Services> {
-    let services_bits: u64 = services_bits.parse()?;
-    Ok(Services::from_bits_truncate(services_bits))
+fn parse_services(services: &str) -> anyhow::Result<Services> {
+    Ok(Services::from_str(services)?)
 }
 fn parse_timeout(seconds: &str) -> anyhow::Result<Duration> {
     Ok(Duration::from_secs(seconds.parse()?))
 }
 fn parse_chain(chain: &str) -> anyhow::Result<Chain> {
-    Chain::from_str(chain)
+    Ok(Chain::from_str(chain)?)
+}
+fn parse_timestamp_source(source: &str) -> anyhow::Result<TimestampSource> {
+    Ok(TimestampSource::from_str(source)?)
+}
+fn parse_nonce_source(source: &str) -> anyhow::Result<NonceSource> {
+    Ok(NonceSource::from_str(source)?)
 }
 
+/// Performs the Bitcoin P2P handshake with peers and inspects the recorded traffic
 #[derive(Debug, Parser)]
-#[command(version, about, long_about = None)]
-struct HandshakeCli {
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
+struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Options of the `handshake` command, which is run when no command is given
+    #[command(flatten)]
+    pub handshake: HandshakeArgs,
+}
+
+impl Cli {
+    /// Command to run, falling back to `handshake` with the top-level options
+    fn into_command(self) -> Command {
+        self.command.unwrap_or(Command::Handshake(self.handshake))
+    }
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    /// Perform the handshake with every peer and report the outcome (the default)
+    Handshake(HandshakeArgs),
+    /// Perform the handshake with every peer and list the ones that look like listening nodes
+    Crawl(ConnectArgs),
+    /// Keep each connection open after the handshake and measure ping round trips over it
+    Keepalive(KeepaliveArgs),
+    /// Print the messages recorded in a capture file
+    Decode(DecodeArgs),
+}
+
+impl Command {
+    /// Options of commands that connect to peers
+    fn connect_args(&self) -> Option<&ConnectArgs> {
+        match self {
+            Command::Handshake(args) => Some(&args.connect),
+            Command::Crawl(args) => Some(args),
+            Command::Keepalive(args) => Some(&args.connect),
+            Command::Decode(_) => None,
+        }
+    }
+}
+
+/// Targets and handshake options shared by every command that connects to peers
+#[derive(Debug, Args)]
+struct ConnectArgs {
     /// Bitcoin DNS Seed that is queried
-    pub dns_seed: String,
-    /// The Bitcoin Network to connect to
+    pub dns_seed: Option<String>,
+    /// Address of a peer to connect to directly (may be repeated)
+    #[arg(long)]
+    pub addr: Vec<SocketAddr>,
+    /// File listing `host:port` or `ip:port` targets, one per line (blank lines and `#` comments are ignored)
+    #[arg(long)]
+    pub seed_file: Option<PathBuf>,
+    /// File listing `ip`, `ip:port` or CIDR range (e.g. `10.0.0.0/8`) entries never to connect to, one per line
+    #[arg(long)]
+    pub exclude_file: Option<PathBuf>,
+    /// Scan at most this many of the resolved peers, the first ones unless `--sample` is given
+    #[arg(long)]
+    pub count: Option<usize>,
+    /// Pick the `--count` peers at random instead of taking the first ones
+    #[arg(long, requires = "count")]
+    pub sample: bool,
+    /// Seed of the random sampling, making it pick the same peers on every run given the same targets
+    #[arg(long, requires = "sample")]
+    pub sample_seed: Option<u64>,
+    /// The Bitcoin Network to connect to: `mainnet`, `regtest`, `testnet3`, `signet` or `namecoin`
     #[arg(short, long, value_parser = parse_chain, default_value = "mainnet")]
     pub chain: Chain,
-    /// Port Number of the Receiving Node
-    #[arg(short, long, default_value_t = MAINNET_PORT_NUMBER)]
-    pub port: u16,
-    /// Services supported by the transmitting node encoded as a 64-bit bitfield
-    #[arg(short, long, value_parser = parse_services, default_value = "0")]
-    pub services: Services,
-    /// Services supported by the receiving node encoded as a 64-bit bitfield
+    /// Protocol version advertised in our version message, e.g. to test how peers react to older or newer versions
+    #[arg(long, value_parser = clap::value_parser!(i32).range(0..), default_value_t = PROTOCOL_VERSION)]
+    pub protocol_version: i32,
+    /// Port Number of the Receiving Node [default: the default port of the chain, e.g. 8333 on mainnet]
+    #[arg(short, long)]
+    pub port: Option<u16>,
+    /// Local address (e.g. `192.0.2.1:0`) that connections to peers are opened from [default: chosen by the OS]
+    #[arg(long)]
+    pub bind: Option<SocketAddr>,
+    /// Services supported by the transmitting node as a 64-bit bitfield or flag names (e.g. `NODE_NETWORK|NODE_WITNESS`) [default: none, as suggested for a non-serving client]
+    #[arg(short, long, value_parser = parse_services)]
+    pub services: Option<Services>,
+    /// Services supported by the receiving node as a 64-bit bitfield or flag names (e.g. `NODE_NETWORK|NODE_WITNESS`)
     #[arg(short, long, value_parser = parse_services, default_value = "0")]
     pub receiving_services: Services,
     /// Maximum duration (in seconds) to perform the handshake in
     #[arg(short, long, value_parser = parse_timeout, default_value = "10")]
     pub timeout: Duration,
+    /// Maximum duration (in seconds) to resolve a DNS seed or seed file host in
+    #[arg(long, value_parser = parse_timeout, default_value = "10")]
+    pub dns_timeout: Duration,
+    /// Once enough handshakes succeeded, give up on peers after a multiple of the median handshake latency instead, never
+    /// waiting longer than `--timeout`
+    #[arg(long)]
+    pub adaptive_timeout: bool,
+    /// Maximum duration (in seconds) that a single read from or write to a peer may make no progress [default: unlimited]
+    #[arg(long, value_parser = parse_timeout)]
+    pub io_timeout: Option<Duration>,
+    /// Print a JSON line with the details of every successful handshake
+    #[arg(long)]
+    pub json: bool,
+    /// Serve counters of the handshakes in the Prometheus text format over HTTP on this address, e.g. to monitor
+    /// `--listen` or `keepalive` running as a service
+    #[arg(long)]
+    pub metrics_addr: Option<SocketAddr>,
+    /// Print the JSON Schema of the `--json` lines and of the `--summary-json` file, then exit without connecting
+    #[arg(long)]
+    pub json_schema: bool,
+    /// Capacity (in bytes) of the buffer that messages from each peer are read through
+    #[arg(long, default_value_t = DEFAULT_READ_BUFFER_SIZE)]
+    pub read_buffer_bytes: usize,
+    /// Consider a handshake successful once version messages were exchanged and our verack sent, without waiting for the peer's
+    #[arg(long)]
+    pub skip_verack: bool,
+    /// Consider a handshake successful as soon as the peer's version was received, without even sending our verack
+    #[arg(long)]
+    pub no_verack_send: bool,
+    /// Fail a handshake when the peer hangs up instead of sending its verack, rather than tolerating it
+    #[arg(long)]
+    pub require_verack: bool,
+    /// Fail a handshake when the peer sends any other message before its version, rather than skipping it
+    #[arg(long)]
+    pub strict_message_order: bool,
+    /// Timestamp advertised in our version message: `now`, `zero` or `fixed:<unix timestamp>`
+    #[arg(
+        long = "timestamp",
+        visible_alias = "timestamp-source",
+        value_name = "MODE",
+        value_parser = parse_timestamp_source,
+        default_value = "now"
+    )]
+    pub timestamp_source: TimestampSource,
+    /// Nonce advertised in our version message: `per-connection`, `per-run[:<u64>]` or `fixed:<u64>`, where a nonce shared by every handshake makes peers advertising it count as ourselves
+    #[arg(long, value_parser = parse_nonce_source, default_value = "per-connection")]
+    pub nonce_strategy: NonceSource,
+    /// File that the aggregate summary of the scan is written to as JSON once all handshakes finished
+    #[arg(long)]
+    pub summary_json: Option<PathBuf>,
+    /// Attempt a handshake that failed with a transport error (e.g. a reset connection or a timeout) up to this many more times
+    #[arg(long, default_value_t = 0)]
+    pub retries: u32,
+    /// Number of handshakes performed one after the other with each peer, over fresh connections, to measure how
+    /// reliably it answers; the success ratio and latencies of every peer are added to the summary when above 1
+    #[arg(long, value_parser = clap::value_parser!(u32).range(1..), default_value_t = 1)]
+    pub repeat: u32,
+    /// Race a connection to an IPv4 and an IPv6 address of each hostname resolved to both, and give the handshakes with the addresses of the family that connected faster a head start
+    #[arg(long)]
+    pub prefer_faster_family: bool,
+    /// Maximum duration (in seconds) of the whole scan, after which the handshakes still running are abandoned [default: unlimited]
+    #[arg(long, value_parser = parse_timeout)]
+    pub max_duration: Option<Duration>,
+    /// User agent advertised in our version message [default: `/<UA_NAME>:<UA_VERSION>/`]
+    #[arg(long, conflicts_with_all = ["ua_name", "ua_version"])]
+    pub user_agent: Option<String>,
+    /// Software name that the user agent is built from
+    #[arg(long, default_value = "bitcoin-p2p")]
+    pub ua_name: String,
+    /// Software version that the user agent is built from
+    #[arg(long, default_value = env!("CARGO_PKG_VERSION"))]
+    pub ua_version: String,
 }
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    tracing_subscriber::fmt()
-        .compact()
-        .with_file(true)
-        .with_line_number(true)
-        .init();
+impl ConnectArgs {
+    /// Port of targets given without one
+    fn port(&self) -> u16 {
+        self.port.unwrap_or_else(|| self.chain.default_port())
+    }
+
+    /// Hint pointing out that `--port` is the default port of a chain other than `--chain`
+    fn port_hint(&self) -> Option<String> {
+        let port = self.port?;
+        let port_chain = Chain::from_default_port(port).filter(|chain| *chain != self.chain)?;
+        Some(format!(
+            "Port {port} is the default port of {port_chain}, but the chain is {}",
+            self.chain
+        ))
+    }
+}
+
+#[derive(Debug, Args)]
+struct HandshakeArgs {
+    #[command(flatten)]
+    pub connect: ConnectArgs,
+    /// File that every byte exchanged with the peers is appended to, tagged with its direction
+    #[arg(long)]
+    pub capture_file: Option<PathBuf>,
+    /// Print every field of the version message of each peer a handshake succeeded with
+    #[arg(long)]
+    pub verbose_peer: bool,
+    /// Instead of connecting to peers, accept inbound connections on this address and answer their handshakes
+    #[arg(long)]
+    pub listen: Option<SocketAddr>,
+}
+
+#[derive(Debug, Args)]
+struct KeepaliveArgs {
+    #[command(flatten)]
+    pub connect: ConnectArgs,
+    /// Number of ping round trips measured over each connection
+    #[arg(long, default_value_t = 3)]
+    pub pings: u32,
+}
+
+#[derive(Debug, Args)]
+struct DecodeArgs {
+    /// Capture file written via `--capture-file`
+    pub capture_file: PathBuf,
+}
+
+/// What a scan does with each peer besides the handshake
+#[derive(Debug, Default)]
+struct ScanOptions<'a> {
+    /// File that every byte exchanged with the peers is appended to
+    capture_file: Option<&'a Path>,
+    /// Whether to print every field of the version message of each peer
+    verbose_peer: bool,
+    /// Number of ping round trips to measure over each connection after the handshake
+    keepalive_pings: u32,
+    /// Whether to only report peers that look like listening nodes
+    listening_only: bool,
+    /// Counters updated with the outcome of every handshake, see `--metrics-addr`
+    metrics: Option<Arc<Metrics>>,
+}
+
+/// Target listed in a seed file
+#[derive(Debug, PartialEq)]
+enum SeedTarget {
+    /// IP literal that can be connected to without resolution
+    Address(SocketAddr),
+    /// Hostname that has to be resolved via DNS
+    Host(String, u16),
+}
+
+impl SeedTarget {
+    /// Parses a `host[:port]` or `ip[:port]` target, falling back to `default_port` when no port is given
+    fn parse(target: &str, default_port: u16) -> anyhow::Result<Self> {
+        if let Ok(socket_address) = target.parse::<SocketAddr>() {
+            return Ok(SeedTarget::Address(socket_address));
+        }
+        if let Ok(ip_address) = target.parse::<IpAddr>() {
+            return Ok(SeedTarget::Address(SocketAddr::new(
+                ip_address,
+                default_port,
+            )));
+        }
+        match target.rsplit_once(':') {
+            Some((host, port)) => Ok(SeedTarget::Host(host.to_string(), port.parse()?)),
+            None => Ok(SeedTarget::Host(target.to_string(), default_port)),
+        }
+    }
+}
+
+/// Entry of an exclude file, matching the addresses that are never connected to
+#[derive(Debug, PartialEq)]
+enum ExcludeRule {
+    /// Every port of an IP address
+    Ip(IpAddr),
+    /// A single IP address and port
+    Address(SocketAddr),
+    /// Every IP address in a CIDR range
+    Range { network: IpAddr, prefix_len: u8 },
+}
+
+impl ExcludeRule {
+    /// Parses an `ip`, `ip:port` or `ip/prefix_len` entry
+    fn parse(rule: &str) -> anyhow::Result<Self> {
+        if let Some((network, prefix_len)) = rule.split_once('/') {
+            let network: IpAddr = network.parse()?;
+            let prefix_len: u8 = prefix_len.parse()?;
+            let max_prefix_len = if network.is_ipv4() { 32 } else { 128 };
+            anyhow::ensure!(
+                prefix_len <= max_prefix_len,
+                "prefix length of {rule} is above {max_prefix_len}"
+            );
+            return Ok(ExcludeRule::Range {
+                network,
+                prefix_len,
+            });
+        }
+        if let Ok(socket_address) = rule.parse::<SocketAddr>() {
+            return Ok(ExcludeRule::Address(socket_address));
+        }
+        Ok(ExcludeRule::Ip(rule.parse()?))
+    }
+
+    fn matches(&self, socket_address: &SocketAddr) -> bool {
+        match self {
+            ExcludeRule::Ip(ip_address) => socket_address.ip() == *ip_address,
+            ExcludeRule::Address(address) => socket_address == address,
+            ExcludeRule::Range {
+                network,
+                prefix_len,
+            } => match (network, socket_address.ip()) {
+                (IpAddr::V4(network), IpAddr::V4(ip_address)) => {
+                    let mask = u32::MAX
+                        .checked_shl(32 - u32::from(*prefix_len))
+                        .unwrap_or(0);
+                    u32::from(*network) & mask == u32::from(ip_address) & mask
+                }
+                (IpAddr::V6(network), IpAddr::V6(ip_address)) => {
+                    let mask = u128::MAX
+                        .checked_shl(128 - u32::from(*prefix_len))
+                        .unwrap_or(0);
+                    u128::from(*network) & mask == u128::from(ip_address) & mask
+                }
+                _ => false,
+            },
+        }
+    }
+}
+
+/// Reads the rules listed in the exclude file at `path`, which has the same layout as a seed file
+fn read_exclude_file(path: &Path) -> anyhow::Result<Vec<ExcludeRule>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(ExcludeRule::parse)
+        .collect()
+}
+
+/// Drops every address matched by one of `rules`
+fn exclude_targets(socket_addresses: &mut Vec<SocketAddr>, rules: &[ExcludeRule]) {
+    socket_addresses.retain(|socket_address| {
+        let excluded = rules.iter().any(|rule| rule.matches(socket_address));
+        if excluded {
+            tracing::debug!(%socket_address, "Skipping excluded peer");
+        }
+        !excluded
+    });
+}
+
+/// Reads the targets listed in the seed file at `path`
+fn read_seed_file(path: &Path, default_port: u16) -> anyhow::Result<Vec<SeedTarget>> {
+    std::fs::read_to_string(path)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| SeedTarget::parse(line, default_port))
+        .collect()
+}
+
+/// Keeps at most `--count` of `socket_addresses`, picked at random if `--sample` is given
+fn limit_targets(socket_addresses: &mut Vec<SocketAddr>, args: &ConnectArgs) {
+    let Some(count) = args.count else {
+        return;
+    };
+    if args.sample {
+        let mut rng = match args.sample_seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        socket_addresses.shuffle(&mut rng);
+    }
+    socket_addresses.truncate(count);
+}
+
+/// Resolving a host took longer than `--dns-timeout`
+#[derive(Debug, thiserror::Error)]
+#[error("Resolving {host} timed out after {timeout:?}")]
+struct ResolveTimeout {
+    host: String,
+    timeout: Duration,
+}
+
+/// Awaits the `lookup` of `host`, failing with [`ResolveTimeout`] if it takes longer than `dns_timeout`
+async fn resolve_within<T>(
+    host: &str,
+    dns_timeout: Duration,
+    lookup: impl Future<Output = std::io::Result<T>>,
+) -> anyhow::Result<T> {
+    match timeout(dns_timeout, lookup).await {
+        Ok(addresses) => Ok(addresses?),
+        Err(_) => Err(ResolveTimeout {
+            host: host.to_string(),
+            timeout: dns_timeout,
+        })?,
+    }
+}
+
+/// Resolves `host` to the addresses it points to, giving up after `dns_timeout`, see [`resolve_or_warn`]
+async fn resolve_host_or_warn(host: &str, port: u16, dns_timeout: Duration) -> Vec<SocketAddr> {
+    resolve_or_warn(host, dns_timeout, lookup_host((host, port))).await
+}
+
+/// Same as [`resolve_within`], but logs a failed `lookup` and treats `host` as pointing nowhere
+async fn resolve_or_warn<I: IntoIterator<Item = SocketAddr>>(
+    host: &str,
+    dns_timeout: Duration,
+    lookup: impl Future<Output = std::io::Result<I>>,
+) -> Vec<SocketAddr> {
+    match resolve_within(host, dns_timeout, lookup).await {
+        Ok(addresses) => addresses.into_iter().collect(),
+        Err(error) => {
+            tracing::warn!("Resolving {host} failed: {error:#}");
+            Vec::new()
+        }
+    }
+}
+
+/// Collects the union of the addresses given via the DNS seed, `--addr` and `--seed-file`, minus the ones matched by
+/// `--exclude-file` and limited to `--count`, along with the hostname that each resolved address came from
+///
+/// A host that fails to resolve is skipped with a warning, so a scan whose hosts all fail has no peers to scan.
+async fn resolve_targets(
+    args: &ConnectArgs,
+) -> anyhow::Result<(Vec<SocketAddr>, HashMap<SocketAddr, String>)> {
+    let mut socket_addresses = args.addr.clone();
+    let mut hosts = HashMap::new();
+    if let Some(dns_seed) = &args.dns_seed {
+        let resolved = resolve_host_or_warn(dns_seed, args.port(), args.dns_timeout).await;
+        hosts.extend(resolved.iter().map(|address| (*address, dns_seed.clone())));
+        socket_addresses.extend(resolved);
+    }
+    if let Some(seed_file) = &args.seed_file {
+        for target in read_seed_file(seed_file, args.port())? {
+            match target {
+                SeedTarget::Address(socket_address) => socket_addresses.push(socket_address),
+                SeedTarget::Host(host, port) => {
+                    let resolved = resolve_host_or_warn(&host, port, args.dns_timeout).await;
+                    hosts.extend(resolved.iter().map(|address| (*address, host.clone())));
+                    socket_addresses.extend(resolved);
+                }
+            }
+        }
+    }
+
+    let mut seen = HashSet::new();
+    socket_addresses.retain(|socket_address| seen.insert(*socket_address));
+    if let Some(exclude_file) = &args.exclude_file {
+        exclude_targets(&mut socket_addresses, &read_exclude_file(exclude_file)?);
+    }
+    limit_targets(&mut socket_addresses, args);
+    Ok((socket_addresses, hosts))
+}
+
+/// Family that connected faster to each hostname resolved to both IPv4 and IPv6 addresses, see `--prefer-faster-family`
+#[derive(Debug, Default)]
+struct FamilyPreference {
+    prefers_ipv6: HashMap<String, bool>,
+}
+
+impl FamilyPreference {
+    /// Delay after which the handshakes with the addresses of the slower family of a host start
+    const HEAD_START: Duration = Duration::from_millis(250);
+
+    /// Races a `connect` to the first IPv4 address of every host against one to its first IPv6 address, preferring the
+    /// family of whichever succeeds first
+    ///
+    /// Hosts resolved to a single family, and those whose two addresses both fail to connect within `connect_timeout`,
+    /// are left without a preference.
+    async fn race<F, Fut>(
+        socket_addresses: &[SocketAddr],
+        hosts: &HashMap<SocketAddr, String>,
+        connect_timeout: Duration,
+        connect: F,
+    ) -> Self
+    where
+        F: Fn(SocketAddr) -> Fut,
+        Fut: Future<Output = std::io::Result<()>>,
+    {
+        let mut first_of_family: HashMap<&String, (Option<SocketAddr>, Option<SocketAddr>)> =
+            HashMap::new();
+        for socket_address in socket_addresses {
+            if let Some(host) = hosts.get(socket_address) {
+                let (ipv4, ipv6) = first_of_family.entry(host).or_default();
+                let first = if socket_address.is_ipv6() { ipv6 } else { ipv4 };
+                first.get_or_insert(*socket_address);
+            }
+        }
+
+        let connect = &connect;
+        let mut races: FuturesUnordered<_> = first_of_family
+            .into_iter()
+            .filter_map(|(host, first)| match first {
+                (Some(ipv4), Some(ipv6)) => Some(async move {
+                    let mut attempts: FuturesUnordered<_> = [ipv4, ipv6]
+                        .into_iter()
+                        .map(|address| async move { connect(address).await.map(|()| address) })
+                        .collect();
+                    let winner = timeout(connect_timeout, async {
+                        while let Some(attempt) = attempts.next().await {
+                            if let Ok(address) = attempt {
+                                return Some(address);
+                            }
+                        }
+                        None
+                    })
+                    .await;
+                    (host, winner.ok().flatten())
+                }),
+                _ => None,
+            })
+            .collect();
+
+        let mut family_preference = Self::default();
+        while let Some((host, winner)) = races.next().await {
+            if let Some(winner) = winner {
+                tracing::debug!(%host, %winner, "Connected faster over the family of this address");
+                family_preference
+                    .prefers_ipv6
+                    .insert(host.clone(), winner.is_ipv6());
+            }
+        }
+        family_preference
+    }
+
+    /// Delay before the handshake with `address` resolved from `host` starts, which is [`Self::HEAD_START`] if it is of
+    /// the slower family of the host
+    fn delay(&self, host: Option<&String>, address: SocketAddr) -> Duration {
+        match host.and_then(|host| self.prefers_ipv6.get(host)) {
+            Some(&prefers_ipv6) if address.is_ipv6() != prefers_ipv6 => Self::HEAD_START,
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// Details of a successful handshake printed when `--json` is set
+#[derive(Debug, Serialize, JsonSchema)]
+struct PeerReport {
+    peer_address: String,
+    peer_version: i32,
+    peer_services: u64,
+    peer_user_agent: String,
+    peer_start_height: i32,
+    verack_sent: bool,
+    verack_received: bool,
+    verack_skipped: bool,
+    peer_supports_addrv2: bool,
+    peer_supports_wtxidrelay: bool,
+    peer_sent_sendheaders: bool,
+    bytes_sent: u64,
+    bytes_received: u64,
+}
+
+impl From<&HandshakeResult> for PeerReport {
+    fn from(result: &HandshakeResult) -> Self {
+        Self {
+            peer_address: result.peer_address.to_string(),
+            peer_version: result.peer_version.version,
+            peer_services: result.peer_version.services.bits(),
+            peer_user_agent: result.peer_version.user_agent.clone(),
+            peer_start_height: result.peer_version.start_height,
+            verack_sent: result.verack_sent,
+            verack_received: result.verack_received,
+            verack_skipped: result.verack_skipped,
+            peer_supports_addrv2: result.peer_supports_addrv2,
+            peer_supports_wtxidrelay: result.peer_supports_wtxidrelay,
+            peer_sent_sendheaders: result.peer_sent_sendheaders,
+            bytes_sent: result.bytes_sent,
+            bytes_received: result.bytes_received,
+        }
+    }
+}
+
+/// Renders every field of the version message received from the peer as indented `name: value` lines
+fn describe_peer_version(result: &HandshakeResult, chain: Chain) -> String {
+    let version = &result.peer_version;
+    let describe_address = |address: &NetworkAddress| {
+        let ip_address = match address.ip_address.to_ipv4_mapped() {
+            Some(ip_address) => IpAddr::V4(ip_address),
+            None => IpAddr::V6(address.ip_address),
+        };
+        format!(
+            "{} (services: {})",
+            SocketAddr::new(ip_address, address.port),
+            address.services
+        )
+    };
+    [
+        format!("Peer {} on {chain}", result.peer_address),
+        format!("  version: {}", version.version),
+        format!("  services: {}", version.services),
+        format!("  timestamp: {}", format_timestamp(version.timestamp)),
+        format!(
+            "  receiving node: {}",
+            describe_address(&version.receiving_node)
+        ),
+        format!(
+            "  transmitting node: {}",
+            describe_address(&version.transmitting_node)
+        ),
+        format!("  nonce: {:#018x}", version.nonce),
+        format!("  user agent: {}", version.user_agent),
+        format!("  start height: {}", version.start_height),
+        format!("  relay: {}", version.relay),
+    ]
+    .join("\n")
+}
+
+/// Formats seconds since the Unix epoch as an RFC 3339 date in UTC, e.g. `2014-11-08T21:48:44Z`
+fn format_timestamp(timestamp: i64) -> String {
+    let (days, seconds) = (timestamp.div_euclid(86400), timestamp.rem_euclid(86400));
+    // Converts days since the epoch to a civil date, see http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}Z",
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// Number of scanned peers per outcome
+#[derive(Debug, Default, Serialize, JsonSchema)]
+struct OutcomeCounts {
+    succeeded: u32,
+    handshake_failed: u32,
+    timed_out: u32,
+}
+
+/// Peers that advertised the same version nonce, which suggests they are a single node reachable at several addresses
+#[derive(Debug, PartialEq, Serialize, JsonSchema)]
+struct NonceGroup {
+    nonce: u64,
+    peer_addresses: Vec<SocketAddr>,
+}
+
+/// Outcome of the handshakes repeated with a single peer via `--repeat`
+#[derive(Debug, PartialEq, Serialize, JsonSchema)]
+struct PeerReliability {
+    peer_address: SocketAddr,
+    attempts: u32,
+    successes: u32,
+    /// Latencies (in seconds) of the successful handshakes, absent if none succeeded
+    min_latency_secs: Option<f64>,
+    median_latency_secs: Option<f64>,
+    max_latency_secs: Option<f64>,
+}
+
+impl PeerReliability {
+    fn new(
+        peer_address: SocketAddr,
+        attempts: &[Result<bitcoin_p2p::Result<HandshakeResult>, Elapsed>],
+    ) -> Self {
+        let mut latencies: Vec<_> = attempts
+            .iter()
+            .filter_map(|attempt| match attempt {
+                Ok(Ok(result)) => Some(result.latency),
+                _ => None,
+            })
+            .collect();
+        latencies.sort();
+        let secs = |latency: Option<&Duration>| latency.map(Duration::as_secs_f64);
+        Self {
+            peer_address,
+            attempts: attempts.len() as u32,
+            successes: latencies.len() as u32,
+            min_latency_secs: secs(latencies.first()),
+            median_latency_secs: secs(latencies.get(latencies.len() / 2)),
+            max_latency_secs: secs(latencies.last()),
+        }
+    }
+}
+
+/// Aggregate results of a scan, written to the file given via `--summary-json`
+#[derive(Debug, Default, Serialize, JsonSchema)]
+struct ScanSummary {
+    peers_scanned: u32,
+    /// Number of successful handshakes, which exceeds the number of peers with `--repeat`
+    success: u32,
+    failure: u32,
+    outcomes: OutcomeCounts,
+    /// Handshakes still running when `--max-duration` elapsed, which are not counted as scanned
+    abandoned: u32,
+    duration_secs: f64,
+    duplicate_nonces: Vec<NonceGroup>,
+    /// Outcome of the handshakes with every peer, ordered by address, if they were repeated via `--repeat`
+    reliability: Vec<PeerReliability>,
+    #[serde(skip)]
+    peer_nonces: HashMap<u64, Vec<SocketAddr>>,
+}
+
+impl ScanSummary {
+    fn record_success(&mut self, result: &HandshakeResult) {
+        self.success += 1;
+        self.outcomes.succeeded += 1;
+        self.peer_nonces
+            .entry(result.peer_version.nonce)
+            .or_default()
+            .push(result.peer_address);
+    }
+
+    fn record_handshake_failure(&mut self) {
+        self.failure += 1;
+        self.outcomes.handshake_failed += 1;
+    }
+
+    fn record_timeout(&mut self) {
+        self.failure += 1;
+        self.outcomes.timed_out += 1;
+    }
+
+    /// Exit code reporting whether the scan achieved anything: 2 if no peer was resolved, 1 if no handshake succeeded
+    fn exit_code(&self) -> ExitCode {
+        if self.peers_scanned == 0 {
+            ExitCode::from(2)
+        } else if self.success == 0 {
+            ExitCode::from(1)
+        } else {
+            ExitCode::SUCCESS
+        }
+    }
+
+    /// Fills [`Self::duplicate_nonces`] with every nonce that more than one peer advertised, ordered by nonce
+    fn group_duplicate_nonces(&mut self) {
+        self.duplicate_nonces = self
+            .peer_nonces
+            .iter()
+            .filter(|(_, peer_addresses)| peer_addresses.len() > 1)
+            .map(|(nonce, peer_addresses)| {
+                let mut peer_addresses = peer_addresses.clone();
+                peer_addresses.sort();
+                NonceGroup {
+                    nonce: *nonce,
+                    peer_addresses,
+                }
+            })
+            .collect();
+        self.duplicate_nonces.sort_by_key(|group| group.nonce);
+    }
+
+    fn write_json(&self, path: &Path) -> anyhow::Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
+
+/// JSON Schemas of the per-peer report printed via `--json` and of the summary written via `--summary-json`, derived
+/// from the structs they are serialized from
+fn json_schema() -> serde_json::Value {
+    serde_json::json!({
+        "peer_report": schema_for!(PeerReport),
+        "summary": schema_for!(ScanSummary),
+    })
+}
+
+/// Counters of the handshakes performed, served in the Prometheus text format via `--metrics-addr`
+#[derive(Debug, Default)]
+struct Metrics {
+    handshakes: AtomicU64,
+    /// Failures by reason, named like the fields of [`OutcomeCounts`]
+    handshake_failed: AtomicU64,
+    timed_out: AtomicU64,
+    current_connections: AtomicU64,
+    bytes_sent: AtomicU64,
+    bytes_received: AtomicU64,
+}
+
+/// Counts a connection in [`Metrics::current_connections`] until dropped
+struct ConnectionGuard(Arc<Metrics>);
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.0.current_connections.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Metrics {
+    fn open_connection(self: &Arc<Self>) -> ConnectionGuard {
+        self.current_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionGuard(self.clone())
+    }
+
+    fn record(&self, outcome: &Result<bitcoin_p2p::Result<HandshakeResult>, Elapsed>) {
+        self.handshakes.fetch_add(1, Ordering::Relaxed);
+        match outcome {
+            Ok(Ok(result)) => {
+                self.bytes_sent
+                    .fetch_add(result.bytes_sent, Ordering::Relaxed);
+                self.bytes_received
+                    .fetch_add(result.bytes_received, Ordering::Relaxed);
+            }
+            Ok(Err(_)) => {
+                self.handshake_failed.fetch_add(1, Ordering::Relaxed);
+            }
+            Err(_) => {
+                self.timed_out.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    /// Renders the counters in the Prometheus text exposition format
+    fn render(&self) -> String {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        [
+            "# HELP bitcoin_p2p_handshakes_total Handshakes that finished, whatever their outcome"
+                .to_string(),
+            "# TYPE bitcoin_p2p_handshakes_total counter".to_string(),
+            format!("bitcoin_p2p_handshakes_total {}", load(&self.handshakes)),
+            "# HELP bitcoin_p2p_handshake_failures_total Handshakes that failed, by reason"
+                .to_string(),
+            "# TYPE bitcoin_p2p_handshake_failures_total counter".to_string(),
+            format!(
+                "bitcoin_p2p_handshake_failures_total{{reason=\"handshake_failed\"}} {}",
+                load(&self.handshake_failed)
+            ),
+            format!(
+                "bitcoin_p2p_handshake_failures_total{{reason=\"timed_out\"}} {}",
+                load(&self.timed_out)
+            ),
+            "# HELP bitcoin_p2p_current_connections Connections to peers currently open"
+                .to_string(),
+            "# TYPE bitcoin_p2p_current_connections gauge".to_string(),
+            format!(
+                "bitcoin_p2p_current_connections {}",
+                load(&self.current_connections)
+            ),
+            "# HELP bitcoin_p2p_bytes_sent_total Bytes sent during successful handshakes"
+                .to_string(),
+            "# TYPE bitcoin_p2p_bytes_sent_total counter".to_string(),
+            format!("bitcoin_p2p_bytes_sent_total {}", load(&self.bytes_sent)),
+            "# HELP bitcoin_p2p_bytes_received_total Bytes received during successful handshakes"
+                .to_string(),
+            "# TYPE bitcoin_p2p_bytes_received_total counter".to_string(),
+            format!(
+                "bitcoin_p2p_bytes_received_total {}",
+                load(&self.bytes_received)
+            ),
+        ]
+        .join("\n")
+            + "\n"
+    }
+}
+
+/// Answers every HTTP request received on `listener` with the rendered `metrics`, whatever the requested path
+async fn serve_metrics(listener: TcpListener, metrics: Arc<Metrics>) {
+    loop {
+        let mut stream = match listener.accept().await {
+            Ok((stream, _)) => stream,
+            Err(e) => {
+                tracing::warn!("Accepting a metrics scrape failed with error: {}", e);
+                continue;
+            }
+        };
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            // Only the start of the request is read, as its content doesn't change the response
+            let mut request = [0u8; 1024];
+            if let Err(e) = stream.read(&mut request).await {
+                tracing::debug!("Reading a metrics scrape failed with error: {}", e);
+                return;
+            }
+            let body = metrics.render();
+            let response = format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                body.len()
+            );
+            if let Err(e) = stream.write_all(response.as_bytes()).await {
+                tracing::debug!("Answering a metrics scrape failed with error: {}", e);
+            }
+        });
+    }
+}
+
+/// Measures `pings` ping round trips over `connection`, reconnecting whenever a ping fails
+async fn keepalive(
+    mut connection: PeerConnection<TcpStream>,
+    pings: u32,
+    timeout_duration: Duration,
+) -> HandshakeResult {
+    let peer_address = connection.handshake_result().peer_address;
+    for _ in 0..pings {
+        let error = match timeout(timeout_duration, connection.ping()).await {
+            Ok(Ok(round_trip_time)) => {
+                tracing::info!(%peer_address, ?round_trip_time, "Ping round trip completed");
+                continue;
+            }
+            Ok(Err(e)) => anyhow::Error::from(e),
+            Err(e) => e.into(),
+        };
+        tracing::info!(%peer_address, "Ping failed with error: {}", error);
+        match timeout(timeout_duration, connection.reconnect()).await {
+            Ok(Ok(())) => tracing::info!(%peer_address, "Reconnected"),
+            Ok(Err(e)) => {
+                tracing::info!(%peer_address, "Reconnect failed with error: {}", e);
+                break;
+            }
+            Err(e) => {
+                tracing::info!(%peer_address, "Reconnect timed out with error: {}", e);
+                break;
+            }
+        }
+    }
+    connection.handshake_result().clone()
+}
+
+/// Timeout derived from the latencies of the handshakes that succeeded so far, see `--adaptive-timeout`
+#[derive(Debug)]
+struct AdaptiveTimeout {
+    /// Timeout used until enough latencies were observed, and upper bound of the adaptive one
+    max: Duration,
+    /// Observed latencies in ascending order
+    latencies: Vec<Duration>,
+}
+
+impl AdaptiveTimeout {
+    /// Number of successful handshakes after which the timeout starts to adapt
+    const MIN_SAMPLES: usize = 10;
+    /// Multiple of the median latency that the timeout adapts to
+    const MEDIAN_MULTIPLIER: u32 = 3;
+    /// Lower bound of the adaptive timeout, so that a run of very fast peers doesn't cut off ordinary ones
+    const MIN: Duration = Duration::from_secs(1);
+    /// How often a pending handshake checks whether the timeout has changed
+    const RECHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+    fn new(max: Duration) -> Self {
+        Self {
+            max,
+            latencies: Vec::new(),
+        }
+    }
+
+    fn record(&mut self, latency: Duration) {
+        let index = self
+            .latencies
+            .partition_point(|observed| *observed <= latency);
+        self.latencies.insert(index, latency);
+    }
+
+    fn current(&self) -> Duration {
+        if self.latencies.len() < Self::MIN_SAMPLES {
+            return self.max;
+        }
+        let median = self.latencies[self.latencies.len() / 2];
+        (median * Self::MEDIAN_MULTIPLIER).clamp(Self::MIN.min(self.max), self.max)
+    }
+}
+
+/// Awaits `future` until it has been running for longer than the current value of `adaptive_timeout`
+async fn with_adaptive_timeout<T>(
+    adaptive_timeout: &RefCell<AdaptiveTimeout>,
+    future: impl Future<Output = T>,
+) -> Result<T, Elapsed> {
+    let started_at = Instant::now();
+    tokio::pin!(future);
+    loop {
+        let remaining = adaptive_timeout
+            .borrow()
+            .current()
+            .saturating_sub(started_at.elapsed());
+        let wait = remaining.min(AdaptiveTimeout::RECHECK_INTERVAL);
+        match timeout(wait, &mut future).await {
+            Ok(output) => return Ok(output),
+            Err(elapsed) if wait == remaining => return Err(elapsed),
+            Err(_) => continue,
+        }
+    }
+}
+
+/// Awaits `future` for at most `timeout_duration`, or for the current value of `adaptive_timeout` if there is one
+async fn with_attempt_timeout<T>(
+    timeout_duration: Duration,
+    adaptive_timeout: Option<&RefCell<AdaptiveTimeout>>,
+    future: impl Future<Output = T>,
+) -> Result<T, Elapsed> {
+    match adaptive_timeout {
+        Some(adaptive_timeout) => with_adaptive_timeout(adaptive_timeout, future).await,
+        None => timeout(timeout_duration, future).await,
+    }
+}
 
-    let cli: HandshakeCli = HandshakeCli::parse();
+/// Performs the handshake with `addr`, attempting it again up to `retries` times after errors that are worth retrying
+///
+/// Every attempt gets its own `timeout_duration`, or the current value of `adaptive_timeout` if there is one.
+async fn handshake_with_retries(
+    handshake: &Handshake,
+    addr: SocketAddr,
+    retries: u32,
+    timeout_duration: Duration,
+    adaptive_timeout: Option<&RefCell<AdaptiveTimeout>>,
+    capture: &mut Vec<u8>,
+) -> Result<bitcoin_p2p::Result<HandshakeResult>, Elapsed> {
+    let mut attempt = 0;
+    loop {
+        let result = with_attempt_timeout(
+            timeout_duration,
+            adaptive_timeout,
+            handshake.connect_with_capture(addr, capture),
+        )
+        .await;
+        let retryable = match &result {
+            Ok(Ok(_)) => false,
+            Ok(Err(e)) => e.is_retryable(),
+            Err(_) => true,
+        };
+        if !retryable || attempt == retries {
+            return result;
+        }
+        attempt += 1;
+        tracing::info!(%addr, attempt, "Retrying handshake");
+    }
+}
+
+/// Performs the responder side of the handshake with the peer at `peer_address` that connected to us over `stream`
+async fn respond(
+    handshake: &Handshake,
+    mut stream: TcpStream,
+    peer_address: SocketAddr,
+    timeout_duration: Duration,
+) -> Result<bitcoin_p2p::Result<HandshakeResult>, Elapsed> {
+    let local_address = match stream.local_addr() {
+        Ok(local_address) => local_address,
+        Err(e) => return Ok(Err(e.into())),
+    };
+    timeout(
+        timeout_duration,
+        handshake.perform_responder(&mut stream, peer_address, local_address, None),
+    )
+    .await
+}
+
+/// Accepts inbound connections on `listen_address` until interrupted, answering the handshake of each initiator
+async fn listen(
+    args: &ConnectArgs,
+    listen_address: SocketAddr,
+    metrics: Option<Arc<Metrics>>,
+) -> anyhow::Result<ExitCode> {
+    let handshake = Handshake::new(handshake_config(args)?);
+    let listener = TcpListener::bind(listen_address).await?;
+    tracing::info!(
+        "Listening for inbound handshakes on {}",
+        listener.local_addr()?
+    );
+    loop {
+        let (stream, peer_address) = listener.accept().await?;
+        let (handshake, timeout_duration) = (handshake.clone(), args.timeout);
+        let metrics = metrics.clone();
+        tokio::spawn(async move {
+            let _connection = metrics.as_ref().map(Metrics::open_connection);
+            let outcome = respond(&handshake, stream, peer_address, timeout_duration).await;
+            if let Some(metrics) = &metrics {
+                metrics.record(&outcome);
+            }
+            match outcome {
+                Ok(Ok(result)) => {
+                    tracing::info!("Inbound handshake succeeded! {}", result.summary_line())
+                }
+                Ok(Err(e)) => {
+                    tracing::info!(%peer_address, "Inbound handshake failed with error: {}", e)
+                }
+                Err(e) => {
+                    tracing::info!(%peer_address, "Inbound handshake timed out with error: {}", e)
+                }
+            }
+        });
+    }
+}
+
+/// Prints the messages recorded in the capture file at `path`
+fn print_capture(path: &Path) -> anyhow::Result<()> {
+    for captured in decode_capture(&std::fs::read_to_string(path)?)? {
+        let peer_address = captured
+            .peer_address
+            .map_or_else(|| "unknown".to_string(), |address| address.to_string());
+        let direction = match captured.direction {
+            Direction::Sent => "->",
+            Direction::Received => "<-",
+        };
+        let command = captured.message.command();
+        if captured.message.is::<VersionMessage>() {
+            let version = captured.message.into_message::<VersionMessage>()?.message;
+            println!("{peer_address} {direction} {command} {version:?}");
+        } else {
+            println!(
+                "{peer_address} {direction} {command} ({} bytes)",
+                captured.message.payload.len()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Builds the handshake configuration from the defaults of the chain and the options given on the command line
+fn handshake_config(args: &ConnectArgs) -> anyhow::Result<HandshakeConfig> {
+    let mut config = HandshakeConfig::for_chain(args.chain);
+    config.protocol_version = args.protocol_version;
+    config.port = args.port();
+    if let Some(services) = args.services {
+        config.services = services;
+    }
+    config.receiving_services = args.receiving_services;
+    config.read_buffer_size = args.read_buffer_bytes;
+    config.skip_verack = args.skip_verack;
+    config.skip_verack_send = args.no_verack_send;
+    config.require_verack = args.require_verack;
+    config.strict_message_order = args.strict_message_order;
+    config.timestamp_source = args.timestamp_source;
+    config.nonce_source = args.nonce_strategy;
+    config.bind_address = args.bind;
+    config.io_timeout = args.io_timeout;
+    config.user_agent = match &args.user_agent {
+        Some(user_agent) => user_agent.clone(),
+        None => build_user_agent(&args.ua_name, &args.ua_version),
+    };
+    let user_agent_len = config.user_agent.len() as u64;
+    if user_agent_len > MAX_USER_AGENT_LENGTH {
+        Err(CodecError::UserAgentTooLong(user_agent_len))?
+    }
+    Ok(config)
+}
+
+/// Performs the handshake with every address in `socket_addresses` concurrently and tallies the outcomes
+///
+/// `hosts` names the hostname that addresses were resolved from, whose faster family `--prefer-faster-family` learns.
+async fn run_scan(
+    args: &ConnectArgs,
+    options: &ScanOptions<'_>,
+    socket_addresses: Vec<SocketAddr>,
+    hosts: &HashMap<SocketAddr, String>,
+) -> anyhow::Result<ScanSummary> {
+    let handshake = Handshake::new(handshake_config(args)?);
 
-    let socket_addresses: Vec<SocketAddr> = lookup_host((cli.dns_seed, cli.port)).await?.collect();
+    let mut capture_file = match options.capture_file {
+        Some(path) => Some(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)?,
+        ),
+        None => None,
+    };
+    let started_at = Instant::now();
+    let mut summary = ScanSummary::default();
+    let adaptive_timeout = args
+        .adaptive_timeout
+        .then(|| RefCell::new(AdaptiveTimeout::new(args.timeout)));
 
-    let (mut success, mut failure) = (0u32, 0u32);
+    // Every connection is captured into its own buffer, so that concurrent handshakes don't interleave in the file
+    let scan_address = |addr: SocketAddr| {
+        let (handshake, adaptive_timeout) = (&handshake, adaptive_timeout.as_ref());
+        async move {
+            let mut capture = Vec::new();
+            let mut results = Vec::new();
+            for _ in 0..args.repeat {
+                let _connection = options.metrics.as_ref().map(Metrics::open_connection);
+                let result = if options.keepalive_pings > 0 {
+                    match with_attempt_timeout(
+                        args.timeout,
+                        adaptive_timeout,
+                        PeerConnection::connect(handshake.clone(), addr),
+                    )
+                    .await
+                    {
+                        Ok(Ok(connection)) => Ok(Ok(keepalive(
+                            connection,
+                            options.keepalive_pings,
+                            args.timeout,
+                        )
+                        .await)),
+                        Ok(Err(e)) => Ok(Err(e)),
+                        Err(e) => Err(e),
+                    }
+                } else {
+                    handshake_with_retries(
+                        handshake,
+                        addr,
+                        args.retries,
+                        args.timeout,
+                        adaptive_timeout,
+                        &mut capture,
+                    )
+                    .await
+                };
+                results.push(result);
+            }
+            (addr, results, capture)
+        }
+    };
 
+    // With `--prefer-faster-family`, the addresses of the slower family of a host start a little later, but still
+    // concurrently with all the others
+    let family_preference = if args.prefer_faster_family {
+        FamilyPreference::race(
+            &socket_addresses,
+            hosts,
+            args.timeout,
+            |address| async move { TcpStream::connect(address).await.map(drop) },
+        )
+        .await
+    } else {
+        FamilyPreference::default()
+    };
     // Since we do need the output of the list of futures to be in-order, it is more efficient to use `FuturesUnordered` than `futures::futures::future::join_all()`
-    let mut timeout_futures: FuturesUnordered<_> = socket_addresses
+    let mut scans: FuturesUnordered<_> = socket_addresses
         .into_iter()
         .map(|addr| {
-            timeout(
-                cli.timeout,
-                perform_handshake(cli.chain, cli.services, cli.receiving_services, addr),
-            )
+            let (scan_address, delay) = (
+                &scan_address,
+                family_preference.delay(hosts.get(&addr), addr),
+            );
+            async move {
+                sleep(delay).await;
+                scan_address(addr).await
+            }
         })
         .collect();
 
-    while let Some(result) = timeout_futures.next().await {
-        match result {
-            Ok(Ok(_)) => {
-                tracing::info!("Handshake succeeded!");
-                success += 1;
+    let deadline = async {
+        match args.max_duration {
+            Some(max_duration) => sleep(max_duration).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::pin!(deadline);
+    loop {
+        let (addr, results, capture) = tokio::select! {
+            next = scans.next() => match next {
+                Some(next) => next,
+                None => break,
+            },
+            _ = &mut deadline => {
+                summary.abandoned = scans.len() as u32;
+                tracing::warn!(
+                    abandoned = summary.abandoned,
+                    "Maximum scan duration reached, abandoning the remaining handshakes"
+                );
+                break;
             }
-            Ok(Err(e)) => {
-                tracing::info!("Handshake failed with error: {}", e);
-                failure += 1;
+        };
+        if let Some(capture_file) = &mut capture_file {
+            capture_file.write_all(&capture)?;
+        }
+        summary.peers_scanned += 1;
+        if args.repeat > 1 {
+            summary
+                .reliability
+                .push(PeerReliability::new(addr, &results));
+        }
+        for result in results {
+            if let Some(metrics) = &options.metrics {
+                metrics.record(&result);
             }
-            Err(e) => {
-                tracing::info!("Handshake timed out with error: {}", e);
-                failure += 1;
+            match result {
+                Ok(Ok(result)) => {
+                    let summary_line = result.summary_line();
+                    if !result.verack_sent {
+                        tracing::info!("Handshake succeeded (verack not sent)! {summary_line}");
+                    } else if result.verack_skipped {
+                        tracing::info!("Handshake succeeded (verack skipped)! {summary_line}");
+                    } else {
+                        tracing::info!("Handshake succeeded! {summary_line}");
+                    }
+                    summary.record_success(&result);
+                    if let Some(adaptive_timeout) = &adaptive_timeout {
+                        adaptive_timeout.borrow_mut().record(result.latency);
+                    }
+                    if options.listening_only && !result.looks_like_listening_node() {
+                        tracing::info!(peer_address = %result.peer_address, "Peer does not look like a listening node");
+                        continue;
+                    }
+                    if args.json {
+                        println!("{}", serde_json::to_string(&PeerReport::from(&result))?);
+                    } else if options.listening_only {
+                        println!("{}", result.peer_address);
+                    }
+                    if options.verbose_peer {
+                        println!("{}", describe_peer_version(&result, args.chain));
+                    }
+                }
+                Ok(Err(e)) => {
+                    tracing::info!("Handshake failed with error: {}", e);
+                    summary.record_handshake_failure();
+                }
+                Err(e) => {
+                    tracing::info!("Handshake timed out with error: {}", e);
+                    summary.record_timeout();
+                }
             }
         }
     }
+    summary
+        .reliability
+        .sort_by_key(|reliability| reliability.peer_address);
+    summary.duration_secs = started_at.elapsed().as_secs_f64();
+    summary.group_duplicate_nonces();
+    Ok(summary)
+}
+
+/// Resolves the targets of `args` and scans them, logging the outcome and writing the summary if requested
+async fn scan_targets(args: &ConnectArgs, options: &ScanOptions<'_>) -> anyhow::Result<ExitCode> {
+    if args.dns_seed.is_none() && args.addr.is_empty() && args.seed_file.is_none() {
+        return Err(anyhow::anyhow!(
+            "No targets given: pass a DNS seed, --addr or --seed-file"
+        ));
+    }
+    if let Some(hint) = args.port_hint() {
+        tracing::warn!("{hint}");
+    }
+    let (socket_addresses, hosts) = resolve_targets(args).await?;
+    if socket_addresses.is_empty() {
+        tracing::info!("No peers were resolved");
+    }
 
-    tracing::info!("Handshake Success Count: {success}");
-    tracing::info!("Handshake Failure Count: {failure}");
+    let summary = run_scan(args, options, socket_addresses, &hosts).await?;
 
-    Ok(())
+    tracing::info!("Handshake Success Count: {}", summary.success);
+    tracing::info!("Handshake Failure Count: {}", summary.failure);
+    if summary.abandoned > 0 {
+        tracing::info!("Handshake Abandoned Count: {}", summary.abandoned);
+    }
+    for group in &summary.duplicate_nonces {
+        tracing::warn!(
+            nonce = group.nonce,
+            peer_addresses = ?group.peer_addresses,
+            "Peers advertised the same nonce"
+        );
+    }
+    if let Some(summary_json) = &args.summary_json {
+        summary.write_json(summary_json)?;
+    }
+
+    Ok(summary.exit_code())
 }
 
-async fn perform_handshake(
-    chain: Chain,
-    services: Services,
-    receiving_services: Services,
-    socket_address: SocketAddr,
-) -> anyhow::Result<()> {
-    let mut tcp_stream = TcpStream::connect(socket_address).await?;
-    exchange_version_message(chain, services, receiving_services, &mut tcp_stream).await?;
-    exchange_verack_message(chain, &mut tcp_stream).await?;
-    Ok(())
+#[tokio::main]
+async fn main() -> ExitCode {
+    tracing_subscriber::fmt()
+        .compact()
+        .with_file(true)
+        .with_line_number(true)
+        .init();
+
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(error) => {
+            let _ = error.print();
+            // clap exits with 2 on invalid arguments, which would read as a scan that resolved no peers
+            return if error.use_stderr() {
+                ExitCode::from(ERROR_EXIT_CODE)
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+    };
+    exit_code_of(run(cli).await)
 }
 
-async fn exchange_version_message(
-    chain: Chain,
-    services: Services,
-    receiving_services: Services,
-    tcp_stream: &mut TcpStream,
-) -> anyhow::Result<()> {
-    let version_message = VersionMessage::new(
-        PROTOCOL_VERSION,
-        services,
-        SystemTime::now()
-            .duration_since(SystemTime::UNIX_EPOCH)?
-            .as_secs() as i64,
-        receiving_services,
-        tcp_stream.peer_addr()?,
-        tcp_stream.local_addr()?,
-        services,
-        rand::random(),
-        format!(""),
-        0,
-        false,
-    );
-    let message = Message::<VersionMessage>::new(chain, version_message);
-    tcp_stream.write_all(&message.encode()?).await?;
+/// Exit code of a run that failed with an error instead of reporting the outcome of its handshakes, e.g. because of an
+/// unreadable `--seed-file` or a `--metrics-addr` that could not be bound
+const ERROR_EXIT_CODE: u8 = 3;
 
-    let mut buffer_reader = BufReader::new(tcp_stream);
-    let mut bytes = buffer_reader.fill_buf().await?;
-    let received_message = Message::<VersionMessage>::decode(&mut bytes)?;
-    let bytes_len = bytes.len();
-    buffer_reader.consume(bytes_len);
+/// Exit code of the outcome of [`run`], printing its error if it failed
+fn exit_code_of(result: anyhow::Result<ExitCode>) -> ExitCode {
+    result.unwrap_or_else(|error| {
+        eprintln!("Error: {error:?}");
+        ExitCode::from(ERROR_EXIT_CODE)
+    })
+}
 
-    if received_message.chain != chain {
-        return Err(anyhow!("Invalid Bitcoin Network"));
+/// Performs the command given by `cli`
+async fn run(cli: Cli) -> anyhow::Result<ExitCode> {
+    let command = cli.into_command();
+    if command.connect_args().is_some_and(|args| args.json_schema) {
+        println!("{}", serde_json::to_string_pretty(&json_schema())?);
+        return Ok(ExitCode::SUCCESS);
+    }
+    let metrics = match command.connect_args().and_then(|args| args.metrics_addr) {
+        Some(metrics_address) => {
+            let listener = TcpListener::bind(metrics_address).await?;
+            tracing::info!("Serving metrics on {}", listener.local_addr()?);
+            let metrics = Arc::new(Metrics::default());
+            tokio::spawn(serve_metrics(listener, metrics.clone()));
+            Some(metrics)
+        }
+        None => None,
+    };
+    match command {
+        Command::Handshake(args) => {
+            if let Some(listen_address) = args.listen {
+                return listen(&args.connect, listen_address, metrics).await;
+            }
+            let options = ScanOptions {
+                capture_file: args.capture_file.as_deref(),
+                verbose_peer: args.verbose_peer,
+                metrics,
+                ..ScanOptions::default()
+            };
+            scan_targets(&args.connect, &options).await
+        }
+        Command::Crawl(args) => {
+            let options = ScanOptions {
+                listening_only: true,
+                metrics,
+                ..ScanOptions::default()
+            };
+            scan_targets(&args, &options).await
+        }
+        Command::Keepalive(args) => {
+            let options = ScanOptions {
+                keepalive_pings: args.pings,
+                metrics,
+                ..ScanOptions::default()
+            };
+            scan_targets(&args.connect, &options).await
+        }
+        Command::Decode(args) => {
+            print_capture(&args.capture_file)?;
+            Ok(ExitCode::SUCCESS)
+        }
     }
-
-    Ok(())
 }
 
-async fn exchange_verack_message(chain: Chain, tcp_stream: &mut TcpStream) -> anyhow::Result<()> {
-    let verack_message = VerackMessage;
-    let message = Message::<VerackMessage>::new(chain, verack_message);
-    tcp_stream.write_all(&message.encode()?).await?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bitcoin_p2p::constants::MAINNET_PORT_NUMBER;
+    use bitcoin_p2p::messages::codec::Decode;
+
+    /// Parses `args` given without a command, i.e. as options of the default `handshake` command
+    fn connect_args(args: &[&str]) -> ConnectArgs {
+        let cli = Cli::parse_from([&["bitcoin-p2p-handshake"], args].concat());
+        match cli.into_command() {
+            Command::Handshake(args) => args.connect,
+            command => panic!("unexpected command {command:?}"),
+        }
+    }
+
+    #[test]
+    fn cli_should_parse_each_subcommand() {
+        let parse = |args: &[&str]| {
+            Cli::parse_from([&["bitcoin-p2p-handshake"], args].concat()).into_command()
+        };
+
+        match parse(&["--addr", "1.2.3.4:8333", "--capture-file", "out.txt"]) {
+            Command::Handshake(args) => {
+                assert_eq!(args.connect.addr, vec!["1.2.3.4:8333".parse().unwrap()]);
+                assert_eq!(args.capture_file, Some(PathBuf::from("out.txt")));
+            }
+            command => panic!("unexpected command {command:?}"),
+        }
+        match parse(&["handshake", "--verbose-peer", "--retries", "2"]) {
+            Command::Handshake(args) => {
+                assert!(args.verbose_peer);
+                assert_eq!(args.connect.retries, 2);
+            }
+            command => panic!("unexpected command {command:?}"),
+        }
+        match parse(&["crawl", "--chain", "testnet3", "--json"]) {
+            Command::Crawl(args) => {
+                assert_eq!(args.chain, Chain::Testnet3);
+                assert!(args.json);
+            }
+            command => panic!("unexpected command {command:?}"),
+        }
+        match parse(&["keepalive", "--pings", "5", "--timeout", "2"]) {
+            Command::Keepalive(args) => {
+                assert_eq!(args.pings, 5);
+                assert_eq!(args.connect.timeout, Duration::from_secs(2));
+            }
+            command => panic!("unexpected command {command:?}"),
+        }
+        match parse(&["decode", "capture.txt"]) {
+            Command::Decode(args) => assert_eq!(args.capture_file, PathBuf::from("capture.txt")),
+            command => panic!("unexpected command {command:?}"),
+        }
+        assert!(Cli::try_parse_from(["bitcoin-p2p-handshake", "decode"]).is_err());
+    }
+
+    #[tokio::test]
+    async fn resolve_within_should_time_out_on_stalled_lookup() {
+        let lookup = std::future::pending::<std::io::Result<Vec<SocketAddr>>>();
+        let error = resolve_within("seed.example", Duration::from_millis(10), lookup)
+            .await
+            .unwrap_err();
 
-    let mut buffer_reader = BufReader::new(tcp_stream);
-    let mut bytes = buffer_reader.fill_buf().await?;
-    let bytes_len = bytes.len();
-    // Note: In some of my trials, an empty buffer gets received instead of a Verack Message. Therefore, I have assumed that the Verack Message is optional and an empty buffer can be received instead.
-    if bytes_len == 0 {
-        tracing::info!("VERACK message was not exchanged by peer");
-        return Ok(());
+        let error = error.downcast_ref::<ResolveTimeout>().unwrap();
+        assert_eq!(error.host, "seed.example");
+        assert_eq!(error.timeout, Duration::from_millis(10));
     }
-    let received_message = Message::<VerackMessage>::decode(&mut bytes)?;
-    buffer_reader.consume(bytes_len);
 
-    if received_message.chain != chain {
-        return Err(anyhow!("Invalid Bitcoin Network!"));
+    #[test]
+    fn read_seed_file_should_skip_comments_and_blank_lines() {
+        let path = std::env::temp_dir().join(format!("seed-file-{}.txt", std::process::id()));
+        std::fs::write(
+            &path,
+            "# reproducible scan targets\n\nseed.bitcoin.sipa.be:8333\n  1.2.3.4:18333  \n# 5.6.7.8:8333\n[::1]:8333\n9.9.9.9\n",
+        )
+        .unwrap();
+
+        let targets = read_seed_file(&path, MAINNET_PORT_NUMBER).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            targets,
+            vec![
+                SeedTarget::Host("seed.bitcoin.sipa.be".to_string(), 8333),
+                SeedTarget::Address("1.2.3.4:18333".parse().unwrap()),
+                SeedTarget::Address("[::1]:8333".parse().unwrap()),
+                SeedTarget::Address("9.9.9.9:8333".parse().unwrap()),
+            ]
+        );
     }
 
-    Ok(())
+    #[test]
+    fn limit_targets_should_truncate_or_sample_reproducibly() {
+        let targets: Vec<SocketAddr> = (1..=20)
+            .map(|host| SocketAddr::from(([10, 0, 0, host], 8333)))
+            .collect();
+        let limit = |args: &[&str]| {
+            let mut limited = targets.clone();
+            limit_targets(&mut limited, &connect_args(args));
+            limited
+        };
+
+        assert_eq!(limit(&[]), targets);
+        assert_eq!(limit(&["--count", "3"]), targets[..3]);
+        assert_eq!(limit(&["--count", "30"]), targets);
+
+        let sampled = limit(&["--count", "5", "--sample", "--sample-seed", "42"]);
+        assert_eq!(sampled.len(), 5);
+        assert!(sampled.iter().all(|target| targets.contains(target)));
+        assert_ne!(sampled, targets[..5]);
+        assert_eq!(
+            limit(&["--count", "5", "--sample", "--sample-seed", "42"]),
+            sampled
+        );
+    }
+
+    #[test]
+    fn exclude_targets_should_drop_listed_addresses_and_ranges() {
+        let rules = ["1.2.3.4", "5.6.7.8:8333", "10.0.0.0/8", "2001:db8::/32"]
+            .into_iter()
+            .map(ExcludeRule::parse)
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        let mut targets: Vec<SocketAddr> = [
+            "1.2.3.4:8333",
+            "1.2.3.4:18333",
+            "5.6.7.8:8333",
+            "5.6.7.8:18333",
+            "10.20.30.40:8333",
+            "11.0.0.1:8333",
+            "[2001:db8::1]:8333",
+            "[2001:db9::1]:8333",
+        ]
+        .into_iter()
+        .map(|target| target.parse().unwrap())
+        .collect();
+
+        exclude_targets(&mut targets, &rules);
+
+        assert_eq!(
+            targets,
+            ["5.6.7.8:18333", "11.0.0.1:8333", "[2001:db9::1]:8333"]
+                .map(|target| target.parse::<SocketAddr>().unwrap())
+        );
+        assert!(ExcludeRule::parse("10.0.0.0/33").is_err());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn family_preference_should_delay_second_family_after_faster_connect() {
+        let ipv4: SocketAddr = "192.0.2.1:8333".parse().unwrap();
+        let second_ipv4: SocketAddr = "192.0.2.2:8333".parse().unwrap();
+        let ipv6: SocketAddr = "[2001:db8::1]:8333".parse().unwrap();
+        let other: SocketAddr = "198.51.100.1:8333".parse().unwrap();
+        let seed = "seed.example".to_string();
+        let hosts = HashMap::from([
+            (ipv4, seed.clone()),
+            (second_ipv4, seed.clone()),
+            (ipv6, seed.clone()),
+            (other, "other.example".to_string()),
+        ]);
+        let addresses = [ipv4, ipv6, second_ipv4, other];
+        // Simulates connects that take 50ms over IPv4 and 10ms over IPv6, or fail over IPv6 if `ipv6_fails` is set
+        let connect = |ipv6_fails: bool| {
+            move |address: SocketAddr| async move {
+                let latency = if address.is_ipv6() { 10 } else { 50 };
+                sleep(Duration::from_millis(latency)).await;
+                if address.is_ipv6() && ipv6_fails {
+                    Err(std::io::ErrorKind::ConnectionRefused.into())
+                } else {
+                    Ok(())
+                }
+            }
+        };
+        let timeout = Duration::from_secs(1);
+
+        let family_preference =
+            FamilyPreference::race(&addresses, &hosts, timeout, connect(false)).await;
+        assert_eq!(family_preference.delay(Some(&seed), ipv6), Duration::ZERO);
+        for address in [ipv4, second_ipv4] {
+            assert_eq!(
+                family_preference.delay(Some(&seed), address),
+                FamilyPreference::HEAD_START
+            );
+        }
+        // A host resolved to a single family, or an address given directly, is never delayed
+        let other_host = "other.example".to_string();
+        assert_eq!(
+            family_preference.delay(Some(&other_host), other),
+            Duration::ZERO
+        );
+        assert_eq!(family_preference.delay(None, ipv4), Duration::ZERO);
+
+        // The faster family failed, so the slower one that did connect is preferred instead
+        let family_preference =
+            FamilyPreference::race(&addresses, &hosts, timeout, connect(true)).await;
+        assert_eq!(family_preference.delay(Some(&seed), ipv4), Duration::ZERO);
+        assert_eq!(
+            family_preference.delay(Some(&seed), ipv6),
+            FamilyPreference::HEAD_START
+        );
+    }
+
+    #[test]
+    fn timestamp_flag_should_accept_timestamp_source_alias() {
+        assert_eq!(connect_args(&[]).timestamp_source, TimestampSource::Now);
+        assert_eq!(
+            connect_args(&["--timestamp", "fixed:1700000000"]).timestamp_source,
+            TimestampSource::Fixed(1700000000)
+        );
+        assert_eq!(
+            connect_args(&["--timestamp-source", "zero"]).timestamp_source,
+            TimestampSource::Zero
+        );
+    }
+
+    #[test]
+    fn port_hint_should_only_flag_default_port_of_other_chain() {
+        let hint = |args: &[&str]| connect_args(args).port_hint();
+        assert_eq!(
+            hint(&["--port", "18333"]).as_deref(),
+            Some("Port 18333 is the default port of testnet3, but the chain is mainnet")
+        );
+        assert_eq!(hint(&["--chain", "testnet3", "--port", "18333"]), None);
+        assert_eq!(hint(&["--port", "12345"]), None);
+        assert_eq!(hint(&[]), None);
+    }
+
+    /// Result of a handshake with `peer_address` whose version message advertised `nonce`
+    fn handshake_result(peer_address: &str, nonce: u64) -> HandshakeResult {
+        // Hexdump example of version message taken from https://developer.bitcoin.org/reference/p2p_networking.html#version
+        let mut peer_version = VersionMessage::decode_bytes(hex::decode("721101000100000000000000bc8f5e5400000000010000000000000000000000000000000000ffffc61b6409208d010000000000000000000000000000000000ffffcb0071c0208d128035cbc97953f80f2f5361746f7368693a302e392e332fcf05050001").unwrap()).unwrap();
+        peer_version.nonce = nonce;
+        HandshakeResult {
+            peer_address: peer_address.parse().unwrap(),
+            peer_version,
+            local_services: Services::empty(),
+            negotiated_version: PROTOCOL_VERSION,
+            verack_sent: true,
+            verack_received: true,
+            verack_skipped: false,
+            peer_supports_addrv2: false,
+            peer_supports_wtxidrelay: false,
+            peer_sent_sendheaders: false,
+            bytes_sent: 0,
+            bytes_received: 0,
+            latency: Duration::ZERO,
+        }
+    }
+
+    #[tokio::test]
+    async fn summary_json_should_contain_totals_of_scan() {
+        let (succeeding, peer_task) = spawn_mock_peer(7).await;
+        // Nothing listens on the address anymore once the listener is dropped
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let refusing = listener.local_addr().unwrap();
+        drop(listener);
+
+        let path = std::env::temp_dir().join(format!("summary-{}.json", std::process::id()));
+        let args = connect_args(&[
+            "--addr",
+            &succeeding.to_string(),
+            "--addr",
+            &refusing.to_string(),
+            "--timeout",
+            "5",
+            "--summary-json",
+            path.to_str().unwrap(),
+        ]);
+        let exit_code = scan_targets(&args, &ScanOptions::default()).await.unwrap();
+        peer_task.await.unwrap();
+        let written: serde_json::Value =
+            serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!(exit_code, ExitCode::SUCCESS);
+        assert_eq!(written["peers_scanned"], 2);
+        assert_eq!(written["success"], 1);
+        assert_eq!(written["failure"], 1);
+        assert_eq!(written["outcomes"]["succeeded"], 1);
+        assert_eq!(written["outcomes"]["handshake_failed"], 1);
+        assert_eq!(written["outcomes"]["timed_out"], 0);
+        assert!(written["duration_secs"].as_f64().unwrap() > 0.0);
+        assert_eq!(written["duplicate_nonces"], serde_json::json!([]));
+    }
+
+    #[test]
+    fn json_schema_should_describe_report_and_summary() {
+        let printed = serde_json::to_string_pretty(&json_schema()).unwrap();
+        let schema: serde_json::Value = serde_json::from_str(&printed).unwrap();
+
+        let peer_report = &schema["peer_report"]["properties"];
+        assert_eq!(peer_report["peer_user_agent"]["type"], "string");
+        let summary = &schema["summary"]["properties"];
+        assert!(summary.get("duplicate_nonces").is_some());
+        assert!(summary.get("peer_nonces").is_none());
+        assert!(connect_args(&["--json-schema"]).json_schema);
+    }
+
+    /// Scans `socket_addresses` with the default options and no further targets
+    async fn scan(socket_addresses: Vec<SocketAddr>) -> ScanSummary {
+        let args = connect_args(&["--timeout", "5"]);
+        run_scan(
+            &args,
+            &ScanOptions::default(),
+            socket_addresses,
+            &HashMap::new(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn run_scan_should_exit_with_2_when_no_peers_were_resolved() {
+        let summary = scan(Vec::new()).await;
+        assert_eq!(summary.peers_scanned, 0);
+        assert_eq!(summary.exit_code(), ExitCode::from(2));
+    }
+
+    #[tokio::test]
+    async fn run_scan_should_exit_with_2_when_seed_lookup_fails() {
+        let lookup = async { Err::<Vec<SocketAddr>, _>(std::io::Error::other("no such host")) };
+        let socket_addresses =
+            resolve_or_warn("seed.example", Duration::from_secs(2), lookup).await;
+        assert!(socket_addresses.is_empty());
+
+        let summary = scan(socket_addresses).await;
+        assert_eq!(summary.exit_code(), ExitCode::from(2));
+    }
+
+    #[tokio::test]
+    async fn run_should_exit_with_3_when_setup_fails() {
+        let too_long_user_agent = "a".repeat(MAX_USER_AGENT_LENGTH as usize + 1);
+        for args in [
+            ["--seed-file", "/nonexistent/seeds.txt"],
+            ["--user-agent", too_long_user_agent.as_str()],
+        ] {
+            let cli = Cli::parse_from(
+                [
+                    &["bitcoin-p2p-handshake", "--addr", "127.0.0.1:8333"],
+                    &args[..],
+                ]
+                .concat(),
+            );
+            assert_eq!(
+                exit_code_of(run(cli).await),
+                ExitCode::from(ERROR_EXIT_CODE)
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn run_scan_should_exit_with_1_when_no_handshake_succeeded() {
+        // Nothing listens on the address anymore once the listener is dropped
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        drop(listener);
+
+        let summary = scan(vec![address]).await;
+        assert_eq!(summary.failure, 1);
+        assert_eq!(summary.exit_code(), ExitCode::from(1));
+    }
+
+    #[tokio::test]
+    async fn respond_should_complete_handshake_initiated_over_loopback() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let handshake = Handshake::new(handshake_config(&connect_args(&[])).unwrap());
+
+        let (initiator, responder) = tokio::join!(handshake.connect(address), async {
+            let (stream, peer_address) = listener.accept().await.unwrap();
+            respond(&handshake, stream, peer_address, Duration::from_secs(5)).await
+        });
+        let (initiator, responder) = (initiator.unwrap(), responder.unwrap().unwrap());
+
+        assert!(initiator.verack_received && responder.verack_received);
+        assert_eq!(initiator.peer_address, address);
+        assert_eq!(
+            responder.peer_version.user_agent,
+            handshake.config().user_agent
+        );
+        assert_eq!(
+            initiator.peer_version.version,
+            responder.peer_version.version
+        );
+    }
+
+    /// Answers the handshake initiated over `stream`, echoing our version back with its nonce replaced by `nonce`
+    async fn answer_handshake(mut stream: TcpStream, nonce: u64) {
+        use bitcoin_p2p::{
+            constants::MAX_PAYLOAD_SIZE,
+            messages::{
+                codec::Encode, framed::read_framed_message, types::verack::VerackMessage, Message,
+            },
+        };
+        use tokio::io::AsyncWriteExt;
+
+        let mut version = read_framed_message::<VersionMessage, _>(&mut stream, MAX_PAYLOAD_SIZE)
+            .await
+            .unwrap();
+        version.message.nonce = nonce;
+        stream.write_all(&version.encode().unwrap()).await.unwrap();
+        read_framed_message::<VerackMessage, _>(&mut stream, MAX_PAYLOAD_SIZE)
+            .await
+            .unwrap();
+        let verack = Message::new(Chain::Mainnet, VerackMessage);
+        stream.write_all(&verack.encode().unwrap()).await.unwrap();
+    }
+
+    /// Listens for a single handshake, echoing our version back with its nonce replaced by `nonce`
+    async fn spawn_mock_peer(nonce: u64) -> (SocketAddr, tokio::task::JoinHandle<()>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let peer_task = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            answer_handshake(stream, nonce).await;
+        });
+        (address, peer_task)
+    }
+
+    #[tokio::test]
+    async fn run_scan_should_exit_with_0_when_a_handshake_succeeded() {
+        let (address, peer_task) = spawn_mock_peer(7).await;
+
+        let summary = scan(vec![address]).await;
+        peer_task.await.unwrap();
+        assert_eq!(summary.success, 1);
+        assert_eq!(summary.exit_code(), ExitCode::SUCCESS);
+    }
+
+    #[tokio::test]
+    async fn run_scan_should_scan_every_address_of_a_host_when_preferring_faster_family() {
+        let (first, first_task) = spawn_mock_peer(1).await;
+        let (second, second_task) = spawn_mock_peer(2).await;
+        let hosts = HashMap::from([
+            (first, "local.example".to_string()),
+            (second, "local.example".to_string()),
+        ]);
+
+        let args = connect_args(&["--timeout", "5", "--prefer-faster-family"]);
+        let summary = run_scan(&args, &ScanOptions::default(), vec![first, second], &hosts)
+            .await
+            .unwrap();
+        first_task.await.unwrap();
+        second_task.await.unwrap();
+        assert_eq!(summary.peers_scanned, 2);
+        assert_eq!(summary.success, 2);
+    }
+
+    #[tokio::test]
+    async fn metrics_endpoint_should_count_handshakes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let metrics_address = listener.local_addr().unwrap();
+        let metrics = Arc::new(Metrics::default());
+        tokio::spawn(serve_metrics(listener, metrics.clone()));
+        let (address, peer_task) = spawn_mock_peer(7).await;
+
+        let options = ScanOptions {
+            metrics: Some(metrics),
+            ..ScanOptions::default()
+        };
+        let args = connect_args(&["--timeout", "5"]);
+        run_scan(&args, &options, vec![address], &HashMap::new())
+            .await
+            .unwrap();
+        peer_task.await.unwrap();
+
+        let mut stream = TcpStream::connect(metrics_address).await.unwrap();
+        stream
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+        for line in [
+            "bitcoin_p2p_handshakes_total 1",
+            "bitcoin_p2p_handshake_failures_total{reason=\"handshake_failed\"} 0",
+            "bitcoin_p2p_current_connections 0",
+        ] {
+            assert!(response.lines().any(|l| l == line), "{line} in {response}");
+        }
+    }
+
+    #[tokio::test]
+    async fn run_scan_should_record_reliability_of_repeated_handshakes() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        // Hangs up on every other connection right away, failing the handshake attempted over it
+        let peer_task = tokio::spawn(async move {
+            for attempt in 0..4 {
+                let (stream, _) = listener.accept().await.unwrap();
+                if attempt % 2 == 1 {
+                    answer_handshake(stream, 7).await;
+                }
+            }
+        });
+
+        let args = connect_args(&["--timeout", "5", "--repeat", "4"]);
+        let summary = run_scan(
+            &args,
+            &ScanOptions::default(),
+            vec![address],
+            &HashMap::new(),
+        )
+        .await
+        .unwrap();
+        peer_task.await.unwrap();
+
+        assert_eq!(summary.peers_scanned, 1);
+        assert_eq!((summary.success, summary.failure), (2, 2));
+        let [reliability] = &summary.reliability[..] else {
+            panic!("unexpected reliability {:?}", summary.reliability);
+        };
+        assert_eq!(reliability.peer_address, address);
+        assert_eq!((reliability.attempts, reliability.successes), (4, 2));
+        let (min, median, max) = (
+            reliability.min_latency_secs.unwrap(),
+            reliability.median_latency_secs.unwrap(),
+            reliability.max_latency_secs.unwrap(),
+        );
+        assert!(min <= median && median <= max);
+    }
+
+    #[test]
+    fn adaptive_timeout_should_follow_median_latency_within_bounds() {
+        let mut adaptive_timeout = AdaptiveTimeout::new(Duration::from_secs(10));
+        for millis in [900, 100, 500, 700, 300, 1100, 1300, 1500, 1700] {
+            adaptive_timeout.record(Duration::from_millis(millis));
+        }
+        // Too few samples to adapt yet
+        assert_eq!(adaptive_timeout.current(), Duration::from_secs(10));
+
+        adaptive_timeout.record(Duration::from_millis(1900));
+        // The median of the 10 samples is 1100ms
+        assert_eq!(adaptive_timeout.current(), Duration::from_millis(3300));
+
+        for _ in 0..20 {
+            adaptive_timeout.record(Duration::from_secs(9));
+        }
+        assert_eq!(adaptive_timeout.current(), Duration::from_secs(10));
+
+        let mut fast_peers = AdaptiveTimeout::new(Duration::from_secs(10));
+        for _ in 0..AdaptiveTimeout::MIN_SAMPLES {
+            fast_peers.record(Duration::from_millis(5));
+        }
+        assert_eq!(fast_peers.current(), AdaptiveTimeout::MIN);
+    }
+
+    #[tokio::test]
+    async fn handshake_with_retries_should_apply_adaptive_timeout_to_every_attempt() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        // Accepts connections without ever answering them, so that every attempt runs into the timeout
+        let peer_task = tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok(Ok((stream, _))) = timeout(Duration::from_secs(2), listener.accept()).await
+            {
+                streams.push(stream);
+            }
+            streams.len()
+        });
+        let handshake = Handshake::new(handshake_config(&connect_args(&[])).unwrap());
+        let adaptive_timeout = RefCell::new(AdaptiveTimeout::new(Duration::from_millis(300)));
+
+        let result = handshake_with_retries(
+            &handshake,
+            address,
+            2,
+            Duration::from_secs(30),
+            Some(&adaptive_timeout),
+            &mut Vec::new(),
+        )
+        .await;
+
+        assert!(result.is_err());
+        assert_eq!(peer_task.await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn run_scan_should_abandon_handshakes_after_max_duration() {
+        let (address, peer_task) = spawn_mock_peer(7).await;
+        // Connections to these are queued by the OS but never answered, so their handshakes stall until the timeout
+        let mut stalled_listeners = Vec::new();
+        for _ in 0..5 {
+            stalled_listeners.push(tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap());
+        }
+        let mut socket_addresses: Vec<_> = stalled_listeners
+            .iter()
+            .map(|listener| listener.local_addr().unwrap())
+            .collect();
+        socket_addresses.push(address);
+
+        let args = connect_args(&["--timeout", "30", "--max-duration", "1"]);
+        let started_at = Instant::now();
+        let summary = run_scan(
+            &args,
+            &ScanOptions::default(),
+            socket_addresses,
+            &HashMap::new(),
+        )
+        .await
+        .unwrap();
+        peer_task.await.unwrap();
+
+        assert!(started_at.elapsed() < Duration::from_secs(10));
+        assert_eq!(summary.success, 1);
+        assert_eq!(summary.peers_scanned, 1);
+        assert_eq!(summary.abandoned, 5);
+        assert_eq!(summary.exit_code(), ExitCode::SUCCESS);
+    }
+
+    #[tokio::test]
+    async fn run_scan_should_group_peers_sharing_a_nonce() {
+        let (first_address, first_peer) = spawn_mock_peer(42).await;
+        let (second_address, second_peer) = spawn_mock_peer(42).await;
+        let (other_address, other_peer) = spawn_mock_peer(7).await;
+
+        let summary = scan(vec![first_address, second_address, other_address]).await;
+        for peer in [first_peer, second_peer, other_peer] {
+            peer.await.unwrap();
+        }
+
+        let mut peer_addresses = vec![first_address, second_address];
+        peer_addresses.sort();
+        assert_eq!(summary.success, 3);
+        assert_eq!(
+            summary.duplicate_nonces,
+            vec![NonceGroup {
+                nonce: 42,
+                peer_addresses
+            }]
+        );
+    }
+
+    #[test]
+    fn describe_peer_version_should_contain_every_field() {
+        let result = handshake_result("203.0.113.192:8333", 0xf85379c9cb358012);
+
+        let description = describe_peer_version(&result, Chain::Mainnet);
+        for line in [
+            "Peer 203.0.113.192:8333 on mainnet",
+            "  version: 70002",
+            "  services: NODE_NETWORK",
+            "  timestamp: 2014-11-08T21:48:44Z",
+            "  receiving node: 198.27.100.9:8333 (services: NODE_NETWORK)",
+            "  transmitting node: 203.0.113.192:8333 (services: NODE_NETWORK)",
+            "  nonce: 0xf85379c9cb358012",
+            "  user agent: /Satoshi:0.9.3/",
+            "  start height: 329167",
+            "  relay: true",
+        ] {
+            assert!(description.lines().any(|l| l == line), "{line}");
+        }
+    }
+
+    #[tokio::test]
+    async fn user_agent_should_be_built_from_name_and_version() {
+        use bitcoin_p2p::{constants::MAX_PAYLOAD_SIZE, messages::framed::read_framed_message};
+
+        let args = connect_args(&["--ua-name", "Foo", "--ua-version", "1.2.3"]);
+        let handshake = Handshake::new(handshake_config(&args).unwrap());
+
+        let (mut client, mut peer) = tokio::io::duplex(1024);
+        let peer_task = tokio::spawn(async move {
+            read_framed_message::<VersionMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                .await
+                .unwrap()
+        });
+        // The peer hangs up after reading our version, so only the version we sent matters here
+        let _ = handshake
+            .perform(
+                &mut client,
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+                None,
+            )
+            .await;
+
+        assert_eq!(peer_task.await.unwrap().message.user_agent, "/Foo:1.2.3/");
+    }
+
+    #[test]
+    fn version_message_should_carry_overridden_protocol_version() {
+        use bitcoin_p2p::messages::codec::Encode;
+
+        let args = connect_args(&["--protocol-version", "70001"]);
+        let handshake = Handshake::new(handshake_config(&args).unwrap());
+        let version_message = handshake.version_message(
+            "1.2.3.4:8333".parse().unwrap(),
+            "127.0.0.1:50000".parse().unwrap(),
+        );
+        assert_eq!(
+            version_message.encode().unwrap()[..4],
+            70001i32.to_le_bytes()
+        );
+
+        assert_eq!(connect_args(&[]).protocol_version, PROTOCOL_VERSION);
+        assert!(Cli::try_parse_from(["bitcoin-p2p-handshake", "--protocol-version=-1"]).is_err());
+    }
+
+    #[test]
+    fn handshake_config_should_reject_too_long_user_agent() {
+        let name = "a".repeat(MAX_USER_AGENT_LENGTH as usize);
+        let args = connect_args(&["--ua-name", &name]);
+        let error = handshake_config(&args).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<CodecError>(),
+            Some(CodecError::UserAgentTooLong(_))
+        ));
+    }
 }
@@ -0,0 +1,605 @@
+//! Module contains the functionality for keeping a connection to a peer open after the handshake
+
+use crate::{
+    handshake::{Handshake, HandshakeError, HandshakeResult},
+    messages::{
+        codec::{Decode, Encode},
+        framed::{CountingStream, MessageStream, TimeoutStream},
+        types::{ping::PingMessage, pong::PongMessage},
+        Chain, CommandName, Message,
+    },
+};
+use futures::{ready, Sink};
+use std::{
+    future::Future,
+    io,
+    net::SocketAddr,
+    num::NonZeroU32,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt, Join, ReadHalf},
+    net::TcpStream,
+    sync::{mpsc, oneshot},
+};
+
+/// Encoded message waiting in the send queue, along with where to report the outcome of writing it
+struct WriteRequest {
+    bytes: Vec<u8>,
+    completion: Option<oneshot::Sender<io::Result<()>>>,
+}
+
+/// Handle for queueing messages on a [`PeerConnection`]
+///
+/// Clones share the same queue, so messages are written in the order in which `send` was called across all of them.
+#[derive(Debug, Clone)]
+pub struct MessageSender {
+    chain: Chain,
+    queue: mpsc::UnboundedSender<WriteRequest>,
+}
+
+impl MessageSender {
+    /// Queues `message` behind every previously queued message, returning a receiver that resolves once it was written
+    pub fn send<M: CommandName + Encode>(
+        &self,
+        message: M,
+    ) -> crate::Result<oneshot::Receiver<io::Result<()>>> {
+        let (completion, completed) = oneshot::channel();
+        let request = WriteRequest {
+            bytes: Message::new(self.chain, message).encode()?,
+            completion: Some(completion),
+        };
+        self.queue.send(request).map_err(|_| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "Connection writer has stopped")
+        })?;
+        Ok(completed)
+    }
+
+    /// Queues `message` and waits until it was written
+    async fn send_and_wait<M: CommandName + Encode>(&self, message: M) -> crate::Result<()> {
+        self.send(message)?.await.map_err(|_| {
+            io::Error::new(io::ErrorKind::BrokenPipe, "Connection writer has stopped")
+        })??;
+        Ok(())
+    }
+
+    /// Turns the sender into a [`Sink`] of messages, to be driven with the combinators of [`futures::SinkExt`]
+    pub fn into_sink(self) -> MessageSink {
+        MessageSink {
+            sender: self,
+            last_write: None,
+        }
+    }
+}
+
+/// [`Sink`] queueing every message it is given on a [`PeerConnection`], obtained via [`MessageSender::into_sink`]
+///
+/// The send queue is unbounded, so the sink is always ready to accept a message. Flushing waits until every message
+/// given so far was written, reporting the outcome of writing the last one.
+#[derive(Debug)]
+pub struct MessageSink {
+    sender: MessageSender,
+    last_write: Option<oneshot::Receiver<io::Result<()>>>,
+}
+
+impl<M: CommandName + Encode> Sink<M> for MessageSink {
+    type Error = crate::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<crate::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, message: M) -> crate::Result<()> {
+        let this = self.get_mut();
+        this.last_write = Some(this.sender.send(message)?);
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<()>> {
+        let this = self.get_mut();
+        if let Some(last_write) = &mut this.last_write {
+            // Messages are written in queue order, so the others were written by the time the last one was
+            let result = ready!(Pin::new(last_write).poll(cx));
+            this.last_write = None;
+            result.map_err(|_| {
+                io::Error::new(io::ErrorKind::BrokenPipe, "Connection writer has stopped")
+            })??;
+        }
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<crate::Result<()>> {
+        Sink::<M>::poll_flush(self, cx)
+    }
+}
+
+/// Write side of the stream used during the handshake, which hands every chunk to the send queue
+struct QueueWriter {
+    queue: mpsc::UnboundedSender<WriteRequest>,
+}
+
+impl AsyncWrite for QueueWriter {
+    fn poll_write(
+        self: Pin<&mut Self>,
+        _cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let request = WriteRequest {
+            bytes: buf.to_vec(),
+            completion: None,
+        };
+        match self.queue.send(request) {
+            Ok(()) => Poll::Ready(Ok(buf.len())),
+            Err(_) => Poll::Ready(Err(io::ErrorKind::BrokenPipe.into())),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// Token bucket pacing the writes of the writer task
+struct RateLimiter {
+    messages_per_second: f64,
+    tokens: f64,
+    /// Read from tokio's clock, so that the pacing follows paused time in tests
+    refilled_at: tokio::time::Instant,
+}
+
+impl RateLimiter {
+    /// Allows `messages_per_second` writes per second, starting with a full bucket of as many tokens
+    fn new(messages_per_second: NonZeroU32) -> Self {
+        let messages_per_second = f64::from(messages_per_second.get());
+        Self {
+            messages_per_second,
+            tokens: messages_per_second,
+            refilled_at: tokio::time::Instant::now(),
+        }
+    }
+
+    /// Waits until a token is available and takes it
+    async fn acquire(&mut self) {
+        self.refill();
+        if self.tokens < 1.0 {
+            let missing = 1.0 - self.tokens;
+            tokio::time::sleep(Duration::from_secs_f64(missing / self.messages_per_second)).await;
+            self.refill();
+        }
+        self.tokens = (self.tokens - 1.0).max(0.0);
+    }
+
+    fn refill(&mut self) {
+        let now = tokio::time::Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens =
+            (self.tokens + elapsed * self.messages_per_second).min(self.messages_per_second);
+        self.refilled_at = now;
+    }
+}
+
+/// Writes the queued requests to `writer` one at a time, in the order in which they were queued
+///
+/// With `max_messages_per_second`, requests stay queued until the rate limit allows writing them. With
+/// `flush_each_message`, a request only counts as written once `writer` was flushed after it.
+async fn write_queued<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut queue: mpsc::UnboundedReceiver<WriteRequest>,
+    bytes_written: Arc<AtomicU64>,
+    max_messages_per_second: Option<NonZeroU32>,
+    flush_each_message: bool,
+) {
+    let mut rate_limiter = max_messages_per_second.map(RateLimiter::new);
+    while let Some(request) = queue.recv().await {
+        if let Some(rate_limiter) = &mut rate_limiter {
+            rate_limiter.acquire().await;
+        }
+        let mut result = writer.write_all(&request.bytes).await;
+        if result.is_ok() {
+            bytes_written.fetch_add(request.bytes.len() as u64, Ordering::Relaxed);
+            if flush_each_message {
+                result = writer.flush().await;
+            }
+        }
+        if let Some(completion) = request.completion {
+            // The submitter may not be interested in the outcome anymore
+            let _ = completion.send(result);
+        }
+    }
+}
+
+/// Read side of a [`PeerConnection`], obtained via [`PeerConnection::split`]
+///
+/// It only needs the send queue to answer the peer's pings, so it can be moved into a task of its own while other
+/// tasks keep writing through their [`MessageSender`].
+pub struct MessageReader<S> {
+    stream: MessageStream<CountingStream<Join<ReadHalf<TimeoutStream<S>>, QueueWriter>>>,
+    sender: MessageSender,
+}
+
+impl<S: AsyncRead + Unpin> MessageReader<S> {
+    /// Reads messages until one of type `M` arrives, answering the peer's pings in the meantime
+    pub async fn read<M: CommandName + Encode + Decode>(&mut self) -> crate::Result<M> {
+        loop {
+            let raw_message = self.stream.read_raw_message().await?;
+            if raw_message.header.chain != self.sender.chain {
+                Err(HandshakeError::WrongNetwork)?
+            }
+
+            if raw_message.is::<M>() {
+                return Ok(raw_message.into_message::<M>()?.message);
+            } else if raw_message.is::<PingMessage>() {
+                let ping = raw_message.into_message::<PingMessage>()?.message;
+                self.sender
+                    .send_and_wait(PongMessage { nonce: ping.nonce })
+                    .await?;
+            } else {
+                tracing::debug!(
+                    command = %raw_message.command(),
+                    "Ignoring message received while waiting for a reply"
+                );
+            }
+        }
+    }
+
+    /// Total number of bytes read from the connection
+    pub fn bytes_read(&self) -> u64 {
+        self.stream.get_ref().bytes_read()
+    }
+}
+
+/// Persistent connection to a peer that performs the handshake once and then allows repeated round trips over it
+///
+/// All writes, including the ones of the handshake, go through a send queue drained by a dedicated writer task, so
+/// messages are never interleaved with each other.
+pub struct PeerConnection<S> {
+    handshake: Handshake,
+    reader: MessageReader<S>,
+    bytes_written: Arc<AtomicU64>,
+    result: HandshakeResult,
+}
+
+impl<S: AsyncRead + AsyncWrite + Send + Unpin + 'static> PeerConnection<S> {
+    /// Performs the handshake over `stream` and keeps it open for further messages
+    pub async fn establish(
+        handshake: Handshake,
+        stream: S,
+        peer_address: SocketAddr,
+        local_address: SocketAddr,
+    ) -> crate::Result<Self> {
+        let (reader, writer) =
+            tokio::io::split(TimeoutStream::new(stream, handshake.config().io_timeout));
+        let (queue, queued) = mpsc::unbounded_channel();
+        let bytes_written = Arc::new(AtomicU64::new(0));
+        tokio::spawn(write_queued(
+            writer,
+            queued,
+            bytes_written.clone(),
+            handshake.config().max_messages_per_second,
+            handshake.config().flush_each_message,
+        ));
+
+        let writer = QueueWriter {
+            queue: queue.clone(),
+        };
+        let mut stream = MessageStream::with_capacity(
+            handshake.config().read_buffer_size,
+            CountingStream::new(tokio::io::join(reader, writer)),
+        );
+        let result = handshake
+            .perform_framed(
+                &mut stream,
+                peer_address,
+                handshake.version_message(peer_address, local_address),
+            )
+            .await?;
+        let sender = MessageSender {
+            chain: handshake.config().chain,
+            queue,
+        };
+        Ok(Self {
+            handshake,
+            reader: MessageReader { stream, sender },
+            bytes_written,
+            result,
+        })
+    }
+
+    /// Result of the most recent handshake performed over this connection
+    pub fn handshake_result(&self) -> &HandshakeResult {
+        &self.result
+    }
+
+    /// Handle for queueing messages on this connection, e.g. from other tasks
+    pub fn sender(&self) -> MessageSender {
+        self.reader.sender.clone()
+    }
+
+    /// Splits the connection into its read side and a handle for writing, so that reading and writing can happen in
+    /// separate tasks without sharing a lock
+    pub fn split(self) -> (MessageReader<S>, MessageSender) {
+        let sender = self.sender();
+        (self.reader, sender)
+    }
+
+    /// Total number of bytes written to and read from the connection
+    pub fn bytes_transferred(&self) -> (u64, u64) {
+        (
+            self.bytes_written.load(Ordering::Relaxed),
+            self.reader.bytes_read(),
+        )
+    }
+
+    /// Sends a ping with a random nonce and waits for the matching pong, returning the round trip time
+    pub async fn ping(&mut self) -> crate::Result<Duration> {
+        let nonce = rand::random();
+        let started_at = Instant::now();
+        self.reader
+            .sender
+            .send_and_wait(PingMessage { nonce })
+            .await?;
+        loop {
+            let pong = self.reader.read::<PongMessage>().await?;
+            if pong.nonce == nonce {
+                return Ok(started_at.elapsed());
+            }
+            tracing::debug!(pong.nonce, "Ignoring pong with unexpected nonce");
+        }
+    }
+
+    /// Sends `request` and waits for the next message of type `R`
+    pub async fn round_trip<Q, R>(&mut self, request: Q) -> crate::Result<R>
+    where
+        Q: CommandName + Encode + Decode,
+        R: CommandName + Encode + Decode,
+    {
+        self.reader.sender.send_and_wait(request).await?;
+        self.reader.read::<R>().await
+    }
+}
+
+impl PeerConnection<TcpStream> {
+    /// Opens a TCP connection to `socket_address` and performs the handshake over it
+    pub async fn connect(handshake: Handshake, socket_address: SocketAddr) -> crate::Result<Self> {
+        let tcp_stream = handshake.open_stream(socket_address).await?;
+        let local_address = tcp_stream.local_addr()?;
+        Self::establish(handshake, tcp_stream, socket_address, local_address).await
+    }
+
+    /// Replaces a failed connection with a fresh one to the same peer, performing the handshake again
+    pub async fn reconnect(&mut self) -> crate::Result<()> {
+        *self = Self::connect(self.handshake.clone(), self.result.peer_address).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::MAX_PAYLOAD_SIZE,
+        handshake::HandshakeConfig,
+        messages::{
+            framed::read_framed_message,
+            types::{verack::VerackMessage, version::VersionMessage},
+            Chain, Command,
+        },
+    };
+    use futures::SinkExt;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+    /// Plays the peer side of the handshake
+    async fn answer_handshake(stream: &mut DuplexStream) {
+        let mut version = read_framed_message::<VersionMessage, _>(stream, MAX_PAYLOAD_SIZE)
+            .await
+            .unwrap();
+        // Echo our version back under a nonce of its own, so that it isn't taken for a self-connection
+        version.message.nonce = version.message.nonce.wrapping_add(1);
+        stream.write_all(&version.encode().unwrap()).await.unwrap();
+        read_framed_message::<VerackMessage, _>(stream, MAX_PAYLOAD_SIZE)
+            .await
+            .unwrap();
+        let message = Message::new(Chain::Mainnet, VerackMessage);
+        stream.write_all(&message.encode().unwrap()).await.unwrap();
+    }
+
+    /// Plays the peer side of the handshake and then answers `pings` pings, returning their nonces in arrival order
+    async fn mock_peer(mut stream: DuplexStream, pings: usize) -> Vec<u64> {
+        answer_handshake(&mut stream).await;
+
+        let mut nonces = Vec::with_capacity(pings);
+        for _ in 0..pings {
+            let ping = read_framed_message::<PingMessage, _>(&mut stream, MAX_PAYLOAD_SIZE)
+                .await
+                .unwrap();
+            nonces.push(ping.message.nonce);
+            let pong = Message::new(
+                Chain::Mainnet,
+                PongMessage {
+                    nonce: ping.message.nonce,
+                },
+            );
+            stream.write_all(&pong.encode().unwrap()).await.unwrap();
+        }
+        nonces
+    }
+
+    async fn establish(client: DuplexStream) -> PeerConnection<DuplexStream> {
+        establish_with(client, HandshakeConfig::for_chain(Chain::Mainnet)).await
+    }
+
+    async fn establish_with(
+        client: DuplexStream,
+        config: HandshakeConfig,
+    ) -> PeerConnection<DuplexStream> {
+        let handshake = Handshake::new(config);
+        PeerConnection::establish(
+            handshake,
+            client,
+            "1.2.3.4:8333".parse().unwrap(),
+            "127.0.0.1:50000".parse().unwrap(),
+        )
+        .await
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn peer_connection_should_ping_repeatedly_after_handshake() {
+        let (client, peer) = tokio::io::duplex(1024);
+        let peer_task = tokio::spawn(mock_peer(peer, 3));
+
+        let mut connection = establish(client).await;
+        assert!(connection.handshake_result().verack_received);
+
+        for _ in 0..3 {
+            connection.ping().await.unwrap();
+        }
+        peer_task.await.unwrap();
+
+        let (bytes_sent, _) = connection.bytes_transferred();
+        assert_eq!(
+            bytes_sent,
+            connection.handshake_result().bytes_sent + 3 * (24 + 8)
+        );
+    }
+
+    #[tokio::test]
+    async fn message_sender_should_write_messages_in_submission_order() {
+        let (client, peer) = tokio::io::duplex(1024);
+        let peer_task = tokio::spawn(mock_peer(peer, 3));
+        let connection = establish(client).await;
+
+        let senders = [
+            connection.sender(),
+            connection.sender(),
+            connection.sender(),
+        ];
+        let completions: Vec<_> = senders
+            .iter()
+            .zip(1..=3)
+            .map(|(sender, nonce)| sender.send(PingMessage { nonce }).unwrap())
+            .collect();
+        for completion in futures::future::join_all(completions).await {
+            completion.unwrap().unwrap();
+        }
+
+        assert_eq!(peer_task.await.unwrap(), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn split_connection_should_read_and_write_from_separate_tasks() {
+        let (client, peer) = tokio::io::duplex(1024);
+        let peer_task = tokio::spawn(mock_peer(peer, 3));
+        let (mut reader, sender) = establish(client).await.split();
+
+        let reader_task = tokio::spawn(async move {
+            let mut nonces = Vec::new();
+            for _ in 0..3 {
+                nonces.push(reader.read::<PongMessage>().await.unwrap().nonce);
+            }
+            nonces
+        });
+        let writer_task = tokio::spawn(async move {
+            for nonce in 1..=3 {
+                sender.send_and_wait(PingMessage { nonce }).await.unwrap();
+            }
+        });
+
+        writer_task.await.unwrap();
+        assert_eq!(reader_task.await.unwrap(), vec![1, 2, 3]);
+        assert_eq!(peer_task.await.unwrap(), vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn message_sink_should_write_messages_in_order() {
+        let (client, mut peer) = tokio::io::duplex(1024);
+        let ping = Message::new(Chain::Mainnet, PingMessage { nonce: 1 });
+        let pong = Message::new(Chain::Mainnet, PongMessage { nonce: 2 });
+        let expected = [ping.encode().unwrap(), pong.encode().unwrap()].concat();
+        let expected_len = expected.len();
+        let peer_task = tokio::spawn(async move {
+            answer_handshake(&mut peer).await;
+            let mut received = vec![0u8; expected_len];
+            peer.read_exact(&mut received).await.unwrap();
+            received
+        });
+        let connection = establish(client).await;
+
+        let mut sink = connection.sender().into_sink();
+        sink.send(ping.message).await.unwrap();
+        sink.send(pong.message).await.unwrap();
+
+        assert_eq!(peer_task.await.unwrap(), expected);
+    }
+
+    /// Message of a command this crate has no type for, which can only be encoded
+    struct EncodeOnlyMessage(Vec<u8>);
+
+    impl CommandName for EncodeOnlyMessage {
+        fn command() -> Command {
+            Command::Unknown(*b"custom\0\0\0\0\0\0")
+        }
+    }
+
+    impl Encode for EncodeOnlyMessage {
+        fn encode(&self) -> crate::Result<Vec<u8>> {
+            Ok(self.0.clone())
+        }
+    }
+
+    #[tokio::test]
+    async fn message_sink_should_write_message_that_can_only_be_encoded() {
+        let (client, mut peer) = tokio::io::duplex(1024);
+        let expected = Message::new(Chain::Mainnet, EncodeOnlyMessage(vec![1, 2, 3]))
+            .encode()
+            .unwrap();
+        let expected_len = expected.len();
+        let peer_task = tokio::spawn(async move {
+            answer_handshake(&mut peer).await;
+            let mut received = vec![0u8; expected_len];
+            peer.read_exact(&mut received).await.unwrap();
+            received
+        });
+        let connection = establish(client).await;
+
+        let mut sink = connection.sender().into_sink();
+        sink.send(EncodeOnlyMessage(vec![1, 2, 3])).await.unwrap();
+
+        assert_eq!(peer_task.await.unwrap(), expected);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn message_sender_should_pace_bursts_when_rate_limited() {
+        let (client, peer) = tokio::io::duplex(64 * 1024);
+        let peer_task = tokio::spawn(mock_peer(peer, 28));
+        let mut config = HandshakeConfig::for_chain(Chain::Mainnet);
+        config.max_messages_per_second = NonZeroU32::new(20);
+        let connection = establish_with(client, config).await;
+
+        // The version and verack took 2 of the 20 tokens, so 18 pings go out at once and the other 10 every 50ms
+        let started_at = tokio::time::Instant::now();
+        let sender = connection.sender();
+        let completions: Vec<_> = (1..=28)
+            .map(|nonce| sender.send(PingMessage { nonce }).unwrap())
+            .collect();
+        for completion in futures::future::join_all(completions).await {
+            completion.unwrap().unwrap();
+        }
+        let elapsed = started_at.elapsed();
+
+        // Time is paused, so the elapsed time is exactly what the rate limiter slept for
+        assert!(elapsed >= Duration::from_millis(495), "{elapsed:?}");
+        assert!(elapsed <= Duration::from_millis(505), "{elapsed:?}");
+        assert_eq!(peer_task.await.unwrap(), (1..=28).collect::<Vec<_>>());
+    }
+}
@@ -1,6 +1,19 @@
 /// Bitcoin p2p protocol version used in this implementation
 pub const PROTOCOL_VERSION: i32 = 70015;
 
+/// Protocol version from which `sendheaders` is understood (https://github.com/bitcoin/bips/blob/master/bip-0130.mediawiki)
+pub const SENDHEADERS_VERSION: i32 = 70012;
+
+/// Protocol version from which `feefilter` is understood (https://github.com/bitcoin/bips/blob/master/bip-0133.mediawiki)
+pub const FEEFILTER_VERSION: i32 = 70013;
+
+/// Protocol version from which `sendcmpct` is understood (https://github.com/bitcoin/bips/blob/master/bip-0152.mediawiki)
+pub const SHORT_IDS_BLOCKS_VERSION: i32 = 70014;
+
+/// Protocol version from which `wtxidrelay` and `sendaddrv2` may be sent before the verack
+/// (https://github.com/bitcoin/bips/blob/master/bip-0339.mediawiki)
+pub const WTXID_RELAY_VERSION: i32 = 70016;
+
 pub const MAINNET_MAGIC_VALUE: [u8; 4] = [0xf9, 0xbe, 0xb4, 0xd9];
 pub const REGNET_MAGIC_VALUE: [u8; 4] = [0xfa, 0xbf, 0xb5, 0xda];
 pub const TESTNET3_MAGIC_VALUE: [u8; 4] = [0x0b, 0x11, 0x09, 0x07];
@@ -10,5 +23,56 @@ pub const NAMECOIN_MAGIC_VALUE: [u8; 4] = [0xf9, 0xbe, 0xb4, 0xfe];
 /// Default port number for peers on the Bitcoin Mainnet (https://developer.bitcoin.org/reference/p2p_networking.html#constants-and-defaults)
 pub const MAINNET_PORT_NUMBER: u16 = 8333;
 
+/// Default port number for peers on the Bitcoin Testnet3 (https://developer.bitcoin.org/reference/p2p_networking.html#constants-and-defaults)
+pub const TESTNET3_PORT_NUMBER: u16 = 18333;
+
+/// Default port number for peers on the Bitcoin Regtest network (https://developer.bitcoin.org/reference/p2p_networking.html#constants-and-defaults)
+pub const REGNET_PORT_NUMBER: u16 = 18444;
+
+/// Default port number for peers on the Bitcoin Signet (https://github.com/bitcoin/bips/blob/master/bip-0325.mediawiki)
+pub const SIGNET_PORT_NUMBER: u16 = 38333;
+
+/// Default port number for peers on the Namecoin network
+pub const NAMECOIN_PORT_NUMBER: u16 = 8334;
+
+/// Fee rate (in satoshis per kilobyte) sent in our `feefilter` message, matching Bitcoin Core's default minimum relay fee
+pub const DEFAULT_FEEFILTER_RATE: u64 = 1000;
+
+/// Maximum length of the user agent in a `version` message (matches Bitcoin Core's `MAX_SUBVERSION_LENGTH`)
+pub const MAX_USER_AGENT_LENGTH: u64 = 256;
+
+/// User agent advertised in our version message (https://github.com/bitcoin/bips/blob/master/bip-0014.mediawiki)
+pub const DEFAULT_USER_AGENT: &str = concat!("/bitcoin-p2p:", env!("CARGO_PKG_VERSION"), "/");
+
 /// Maximum allowed Payload size (https://developer.bitcoin.org/reference/p2p_networking.html#message-headers)
 pub const MAX_PAYLOAD_SIZE: u32 = 32 * 1024 * 1024;
+
+/// Size of a message header in bytes (https://developer.bitcoin.org/reference/p2p_networking.html#message-headers)
+pub const MESSAGE_HEADER_SIZE: usize = 24;
+
+/// Maximum number of entries allowed in an `addr` message (https://developer.bitcoin.org/reference/p2p_networking.html#addr)
+pub const MAX_ADDR_ENTRIES: u64 = 1000;
+
+/// Maximum number of inventory entries allowed in an `inv` or `getdata` message (https://developer.bitcoin.org/reference/p2p_networking.html#inv)
+pub const MAX_INV_ENTRIES: u64 = 50_000;
+
+/// Maximum number of bytes of data allowed in a `filteradd` message (https://developer.bitcoin.org/reference/p2p_networking.html#filteradd)
+pub const MAX_FILTER_ADD_DATA_SIZE: u64 = 520;
+
+/// Maximum number of headers allowed in a `headers` message (https://developer.bitcoin.org/reference/p2p_networking.html#headers)
+pub const MAX_HEADERS_ENTRIES: u64 = 2000;
+
+/// Maximum number of outpoints allowed in a `getutxos` message (https://github.com/bitcoin/bips/blob/master/bip-0064.mediawiki)
+pub const MAX_GETUTXOS_OUTPOINTS: u64 = 100;
+
+/// Maximum number of block locator hashes accepted in a `getheaders` message (matches Bitcoin Core's `MAX_LOCATOR_SZ`)
+pub const MAX_LOCATOR_HASHES: u64 = 101;
+
+/// Maximum size of an address in an `addrv2` message (https://github.com/bitcoin/bips/blob/master/bip-0155.mediawiki)
+pub const MAX_ADDRV2_ADDRESS_SIZE: u64 = 512;
+
+/// Maximum length of the rejected command name in a `reject` message, the size of the command field of a message header
+pub const MAX_REJECT_MESSAGE_LENGTH: u64 = 12;
+
+/// Maximum length of the reason in a `reject` message, well above the 111 bytes Bitcoin Core sends
+pub const MAX_REJECT_REASON_LENGTH: u64 = 4096;
@@ -6,8 +6,13 @@ use sha2::{Digest, Sha256};
 ///
 /// Source: https://developer.bitcoin.org/reference/p2p_networking.html#message-headers
 pub fn checksum(payload: &[u8]) -> [u8; 4] {
-    let hash = Sha256::digest(Sha256::digest(payload));
+    let hash = double_sha256(payload);
     let mut buffer = [0u8; 4];
     buffer.copy_from_slice(&hash[..4]);
     buffer
 }
+
+/// Computes SHA256(SHA256(`data`)), which is used to identify blocks and transactions
+pub fn double_sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(Sha256::digest(data)).into()
+}
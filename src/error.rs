@@ -0,0 +1,163 @@
+//! Module contains the error type returned by the functions of this crate
+
+use crate::{
+    handshake::HandshakeError,
+    messages::{
+        capture::CaptureError, codec::CodecError, framed::StreamLimitError, MessageDecodeError,
+        MessageEncodeError,
+    },
+};
+use std::{error::Error as StdError, io};
+
+/// Any error raised by this crate
+///
+/// Every variant wraps one of the crate's specific error types (or an I/O error), so the error can be matched on
+/// directly, inspected with [`Error::downcast_ref`] or propagated with `?` into `Box<dyn std::error::Error>`.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Codec(#[from] CodecError),
+    #[error(transparent)]
+    MessageEncode(#[from] MessageEncodeError),
+    #[error(transparent)]
+    MessageDecode(#[from] MessageDecodeError),
+    #[error(transparent)]
+    Handshake(#[from] HandshakeError),
+    #[error(transparent)]
+    StreamLimit(#[from] StreamLimitError),
+    #[error(transparent)]
+    Capture(#[from] CaptureError),
+    #[error(transparent)]
+    Io(#[from] io::Error),
+}
+
+/// Result type returned by the functions of this crate
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+impl Error {
+    /// Returns the wrapped error if it is of type `E`
+    pub fn downcast_ref<E: StdError + 'static>(&self) -> Option<&E> {
+        let inner: &(dyn StdError + 'static) = match self {
+            Error::Codec(error) => error,
+            Error::MessageEncode(error) => error,
+            Error::MessageDecode(error) => error,
+            Error::Handshake(error) => error,
+            Error::StreamLimit(error) => error,
+            Error::Capture(error) => error,
+            Error::Io(error) => error,
+        };
+        inner.downcast_ref()
+    }
+
+    /// Whether an operation that failed with this error, e.g. a handshake, is worth attempting again
+    ///
+    /// Transport errors (e.g. a refused or reset connection, a timeout or a peer hanging up mid-handshake) may be
+    /// transient, while a protocol error means the peer misbehaves and will most likely do so again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Io(error) => is_transient(error.kind()),
+            Error::Codec(error) => match error.root() {
+                CodecError::UnexpectedEof => true,
+                CodecError::Io(kind) => is_transient(*kind),
+                // Every other codec error is a malformed message sent by the peer
+                _ => false,
+            },
+            Error::MessageDecode(
+                MessageDecodeError::PayloadTooBig
+                | MessageDecodeError::CommandNameUnkown
+                | MessageDecodeError::CheksumIsInvalid { .. }
+                | MessageDecodeError::TrailingBytes(_),
+            ) => false,
+            Error::Handshake(
+                HandshakeError::InvalidPeerVersion(_)
+                | HandshakeError::WrongNetwork
+                | HandshakeError::Cancelled
+                | HandshakeError::SelfConnection
+                | HandshakeError::UnexpectedMessageBeforeVersion(_)
+                | HandshakeError::NoVerack,
+            ) => false,
+            Error::MessageEncode(_) | Error::StreamLimit(_) | Error::Capture(_) => false,
+        }
+    }
+
+    /// Replaces an I/O error raised while decoding with the matching [`CodecError`], leaving any other error untouched
+    pub(crate) fn into_codec_error(self) -> Self {
+        match self {
+            Error::Io(error) => Error::Codec(error.into()),
+            error => error,
+        }
+    }
+}
+
+fn is_transient(kind: io::ErrorKind) -> bool {
+    matches!(
+        kind,
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted
+            | io::ErrorKind::NotConnected
+            | io::ErrorKind::BrokenPipe
+            | io::ErrorKind::TimedOut
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::UnexpectedEof
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{
+        codec::Decode, types::ping::PingMessage, Chain, CommandName, MessageHeader,
+    };
+
+    fn decode_ping(bytes: &[u8]) -> std::result::Result<u64, Box<dyn StdError + Send + Sync>> {
+        Ok(PingMessage::decode_bytes(bytes)?.nonce)
+    }
+
+    #[test]
+    fn error_should_propagate_into_boxed_error() {
+        assert_eq!(decode_ping(&[1, 0, 0, 0, 0, 0, 0, 0]).unwrap(), 1);
+
+        let error = decode_ping(&[1]).unwrap_err();
+        assert_eq!(
+            error
+                .downcast_ref::<Error>()
+                .unwrap()
+                .downcast_ref::<CodecError>(),
+            Some(&CodecError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn is_retryable_should_only_accept_transport_errors() {
+        for error in [
+            Error::from(io::Error::from(io::ErrorKind::ConnectionRefused)),
+            io::Error::from(io::ErrorKind::ConnectionReset).into(),
+            CodecError::UnexpectedEof.into(),
+            CodecError::Io(io::ErrorKind::ConnectionAborted).into(),
+        ] {
+            assert!(error.is_retryable(), "{error}");
+        }
+
+        for error in [
+            Error::from(HandshakeError::WrongNetwork),
+            HandshakeError::InvalidPeerVersion(-1).into(),
+            HandshakeError::SelfConnection.into(),
+            MessageDecodeError::CheksumIsInvalid {
+                header: MessageHeader {
+                    chain: Chain::Mainnet,
+                    command_name: PingMessage::command_name(),
+                    payload_len: 8,
+                    checksum: [0; 4],
+                },
+                computed: [1; 4],
+            }
+            .into(),
+            MessageDecodeError::CommandNameUnkown.into(),
+            CodecError::TooManyItems { count: 2, max: 1 }.into(),
+            io::Error::from(io::ErrorKind::InvalidData).into(),
+        ] {
+            assert!(!error.is_retryable(), "{error}");
+        }
+    }
+}
@@ -0,0 +1,1922 @@
+//! Module contains the functionality for performing the Bitcoin p2p handshake with a peer
+
+use crate::{
+    constants::{
+        DEFAULT_FEEFILTER_RATE, DEFAULT_USER_AGENT, FEEFILTER_VERSION, PROTOCOL_VERSION,
+        SENDHEADERS_VERSION, SHORT_IDS_BLOCKS_VERSION, WTXID_RELAY_VERSION,
+    },
+    messages::{
+        capture::{write_peer_line, TeeStream},
+        codec::CodecError,
+        framed::{CountingStream, MessageStream, TimeoutStream, DEFAULT_READ_BUFFER_SIZE},
+        types::{
+            feefilter::FeeFilterMessage,
+            sendaddrv2::SendAddrV2Message,
+            sendcmpct::SendCmpctMessage,
+            sendheaders::SendHeadersMessage,
+            verack::VerackMessage,
+            version::{NetworkAddress, Services, VersionMessage},
+            wtxidrelay::WtxidRelayMessage,
+        },
+        Chain, Command, Message,
+    },
+};
+use std::{
+    fmt::{self, Debug, Formatter},
+    io::{self, Write},
+    net::SocketAddr,
+    num::NonZeroU32,
+    str::FromStr,
+    sync::Arc,
+    time::{Duration, Instant, SystemTime},
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite, AsyncWriteExt},
+    net::{TcpSocket, TcpStream},
+};
+use tokio_util::sync::CancellationToken;
+
+/// Errors raised when the peer violates the handshake protocol
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum HandshakeError {
+    /// The protocol version is conceptually unsigned, so a negative advertised version can't be negotiated
+    #[error("Peer advertised an invalid protocol version: {0}")]
+    InvalidPeerVersion(i32),
+    #[error("Invalid Bitcoin Network")]
+    WrongNetwork,
+    #[error("Handshake was cancelled")]
+    Cancelled,
+    /// The peer's version carries the nonce of ours, i.e. we connected to ourselves
+    #[error("Connected to ourselves")]
+    SelfConnection,
+    /// The peer sent another message before its version while [`HandshakeConfig::strict_message_order`] was set
+    #[error("Peer sent a {0} message before its version message")]
+    UnexpectedMessageBeforeVersion(Command),
+    /// The peer hung up instead of sending a verack while [`HandshakeConfig::require_verack`] was set
+    #[error("Peer did not send a verack message")]
+    NoVerack,
+}
+
+/// Where the timestamp of our version message comes from
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampSource {
+    /// The current time, like a real node
+    #[default]
+    Now,
+    /// A fixed UNIX timestamp, e.g. for reproducible handshakes
+    Fixed(i64),
+    /// A timestamp of 0
+    Zero,
+}
+
+impl TimestampSource {
+    /// Timestamp (in seconds since the UNIX epoch) to put into a version message built now
+    pub fn timestamp(&self) -> i64 {
+        match self {
+            TimestampSource::Now => SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs() as i64,
+            TimestampSource::Fixed(timestamp) => *timestamp,
+            TimestampSource::Zero => 0,
+        }
+    }
+}
+
+/// Error returned when parsing a [`TimestampSource`] that is neither `now`, `zero` nor `fixed:<unix timestamp>`
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[error("cannot convert {0:?} to a timestamp source, expected `now`, `zero` or `fixed:<unix timestamp>`")]
+pub struct ParseTimestampSourceError(String);
+
+impl FromStr for TimestampSource {
+    type Err = ParseTimestampSourceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "now" => Ok(TimestampSource::Now),
+            "zero" => Ok(TimestampSource::Zero),
+            _ => s
+                .strip_prefix("fixed:")
+                .and_then(|timestamp| timestamp.parse().ok())
+                .map(TimestampSource::Fixed)
+                .ok_or_else(|| ParseTimestampSourceError(s.to_string())),
+        }
+    }
+}
+
+/// Where the nonce of our version message comes from
+///
+/// The nonce is how we detect connecting to ourselves ([`HandshakeError::SelfConnection`]): a peer version carrying the
+/// nonce of ours fails the handshake. Reusing a nonce across handshakes therefore also fails any handshake with another
+/// process advertising the same nonce, and lets peers link those handshakes to each other.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NonceSource {
+    /// A fresh random nonce for every handshake, like a real node
+    #[default]
+    PerConnection,
+    /// One nonce shared by every handshake of this run, drawn at random when parsed from `per-run`
+    PerRun(u64),
+    /// A fixed nonce, e.g. for reproducible handshakes
+    Fixed(u64),
+}
+
+impl NonceSource {
+    /// Nonce to put into a version message built now
+    pub fn nonce(&self) -> u64 {
+        match self {
+            NonceSource::PerConnection => rand::random(),
+            NonceSource::PerRun(nonce) | NonceSource::Fixed(nonce) => *nonce,
+        }
+    }
+}
+
+/// Error returned when parsing a [`NonceSource`] that is neither `per-connection`, `per-run[:<u64>]` nor `fixed:<u64>`
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[error("cannot convert {0:?} to a nonce source, expected `per-connection`, `per-run[:<u64>]` or `fixed:<u64>`")]
+pub struct ParseNonceSourceError(String);
+
+impl FromStr for NonceSource {
+    type Err = ParseNonceSourceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_nonce = |nonce: &str| nonce.parse().ok();
+        match s {
+            "per-connection" => Ok(NonceSource::PerConnection),
+            "per-run" => Ok(NonceSource::PerRun(rand::random())),
+            _ => None
+                .or_else(|| {
+                    s.strip_prefix("per-run:")
+                        .and_then(parse_nonce)
+                        .map(NonceSource::PerRun)
+                })
+                .or_else(|| {
+                    s.strip_prefix("fixed:")
+                        .and_then(parse_nonce)
+                        .map(NonceSource::Fixed)
+                })
+                .ok_or_else(|| ParseNonceSourceError(s.to_string())),
+        }
+    }
+}
+
+/// Parameters used to build the messages sent during the handshake
+#[derive(Debug, Clone)]
+pub struct HandshakeConfig {
+    /// Bitcoin Network to connect to
+    pub chain: Chain,
+    /// Protocol version advertised in our version message
+    pub protocol_version: i32,
+    /// Port that peers are assumed to listen on when none is given
+    pub port: u16,
+    /// Services supported by the transmitting node
+    pub services: Services,
+    /// Services supported by the receiving node
+    pub receiving_services: Services,
+    /// Capacity (in bytes) of the buffer that messages from the peer are read through
+    pub read_buffer_size: usize,
+    /// Whether to consider the handshake complete once version messages were exchanged and our verack was sent,
+    /// without waiting for the peer's verack
+    pub skip_verack: bool,
+    /// Whether to consider the handshake complete as soon as the peer's version was received, without even sending our
+    /// verack, e.g. for the cheapest possible liveness probe
+    pub skip_verack_send: bool,
+    /// Whether a message other than `version` arriving before the peer's version fails the handshake, rather than
+    /// being skipped
+    pub strict_message_order: bool,
+    /// Whether a peer hanging up instead of sending its verack fails the handshake, rather than being tolerated
+    pub require_verack: bool,
+    /// User agent advertised in our version message
+    pub user_agent: String,
+    /// Source of the timestamp advertised in our version message
+    pub timestamp_source: TimestampSource,
+    /// Source of the nonce advertised in our version message
+    pub nonce_source: NonceSource,
+    /// Whether to send `sendheaders`, `sendcmpct` and `feefilter` after the verack exchange, like a real node does
+    pub post_handshake_politeness: bool,
+    /// Fee rate (in satoshis per kilobyte) sent in the `feefilter` message of [`Self::post_handshake_politeness`]
+    pub feefilter_rate: u64,
+    /// Local address that connections to peers are opened from, chosen by the operating system when `None`
+    pub bind_address: Option<SocketAddr>,
+    /// Maximum time a single read from or write to the peer may make no progress, unlimited when `None`
+    pub io_timeout: Option<Duration>,
+    /// Maximum number of messages a [`PeerConnection`](crate::connection::PeerConnection) writes per second, allowing
+    /// bursts of up to that many messages, unlimited when `None`
+    pub max_messages_per_second: Option<NonZeroU32>,
+    /// Whether a [`PeerConnection`](crate::connection::PeerConnection) flushes the stream after every message it writes,
+    /// rather than leaving it to the stream when to send buffered bytes
+    pub flush_each_message: bool,
+    /// Whether our version message carries the local address of the connection, or the unspecified address
+    /// ([`NetworkAddress::unspecified`]) when `false`
+    pub advertise_local_address: bool,
+}
+
+/// Builds a user agent in the `/Name:Version/` format of [BIP 0014](https://github.com/bitcoin/bips/blob/master/bip-0014.mediawiki)
+pub fn build_user_agent(name: &str, version: &str) -> String {
+    format!("/{name}:{version}/")
+}
+
+impl HandshakeConfig {
+    /// Sensible configuration for connecting to peers on `chain`, meant to be adjusted field by field afterwards
+    pub fn for_chain(chain: Chain) -> Self {
+        Self {
+            chain,
+            protocol_version: PROTOCOL_VERSION,
+            port: chain.default_port(),
+            services: chain.suggested_handshake_services(),
+            receiving_services: Services::UNNAMED,
+            read_buffer_size: DEFAULT_READ_BUFFER_SIZE,
+            skip_verack: false,
+            skip_verack_send: false,
+            require_verack: false,
+            strict_message_order: false,
+            user_agent: DEFAULT_USER_AGENT.to_string(),
+            timestamp_source: TimestampSource::Now,
+            nonce_source: NonceSource::PerConnection,
+            post_handshake_politeness: false,
+            feefilter_rate: DEFAULT_FEEFILTER_RATE,
+            bind_address: None,
+            io_timeout: None,
+            max_messages_per_second: None,
+            flush_each_message: false,
+            advertise_local_address: true,
+        }
+    }
+}
+
+/// Creates a TCP socket for connecting to `peer_address`, bound to `bind_address` if one is given
+///
+/// The socket is of the address family of `bind_address` (or of `peer_address` when there is none), so a mismatch
+/// between the two surfaces as an error when connecting.
+pub fn bind_socket(
+    peer_address: SocketAddr,
+    bind_address: Option<SocketAddr>,
+) -> io::Result<TcpSocket> {
+    let socket = if bind_address.unwrap_or(peer_address).is_ipv4() {
+        TcpSocket::new_v4()?
+    } else {
+        TcpSocket::new_v6()?
+    };
+    if let Some(bind_address) = bind_address {
+        socket.bind(bind_address)?;
+    }
+    Ok(socket)
+}
+
+/// Information gathered about a peer during a successful handshake
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HandshakeResult {
+    /// Address of the peer
+    pub peer_address: SocketAddr,
+    /// Version message sent by the peer
+    pub peer_version: VersionMessage,
+    /// Services advertised in the version message we sent
+    pub local_services: Services,
+    /// Lower of the protocol versions advertised by us and the peer, which decides the messages that may be sent
+    pub negotiated_version: i32,
+    /// Whether we sent a verack message (see [`HandshakeConfig::skip_verack_send`])
+    pub verack_sent: bool,
+    /// Whether the peer sent a verack message
+    pub verack_received: bool,
+    /// Whether waiting for the peer's verack was skipped on purpose (see [`HandshakeConfig::skip_verack`] and
+    /// [`HandshakeConfig::skip_verack_send`])
+    pub verack_skipped: bool,
+    /// Whether the peer sent `sendaddrv2` (BIP155) before its verack
+    pub peer_supports_addrv2: bool,
+    /// Whether the peer sent `wtxidrelay` (BIP339) before its verack
+    pub peer_supports_wtxidrelay: bool,
+    /// Whether the peer sent `sendheaders` before its verack
+    pub peer_sent_sendheaders: bool,
+    /// Number of bytes sent to the peer during the handshake
+    pub bytes_sent: u64,
+    /// Number of bytes received from the peer during the handshake
+    pub bytes_received: u64,
+    /// Time from starting to send our version message until the handshake completed
+    pub latency: Duration,
+}
+
+impl HandshakeResult {
+    /// Heuristic for whether the peer looks like a listening node worth recording when crawling
+    ///
+    /// A peer qualifies when it serves full blocks (`NODE_NETWORK`), identifies itself with a user agent and reports a
+    /// best block height above 0.
+    pub fn looks_like_listening_node(&self) -> bool {
+        self.peer_version.services.contains(Services::NODE_NETWORK)
+            && !self.peer_version.user_agent.is_empty()
+            && self.peer_version.start_height > 0
+    }
+
+    /// One-line summary of the peer and the handshake, e.g. for logging
+    ///
+    /// For example `peer=1.2.3.4:8333 ua="/Satoshi:25.0.0/" ver=70016 services="NODE_NETWORK | NODE_WITNESS"
+    /// height=800000 latency=42ms`. The user agent and services are quoted and escaped, so that a user agent chosen by
+    /// the peer can't break the line apart or forge other fields.
+    pub fn summary_line(&self) -> String {
+        format!(
+            "peer={} ua={:?} ver={} services={:?} height={} latency={}ms",
+            self.peer_address,
+            self.peer_version.user_agent,
+            self.peer_version.version,
+            self.peer_version.services.to_string(),
+            self.peer_version.start_height,
+            self.latency.as_millis()
+        )
+    }
+
+    /// Services we advertised that the peer does not offer
+    pub fn services_we_have_peer_lacks(&self) -> Services {
+        self.local_services.difference(self.peer_version.services)
+    }
+
+    /// Services the peer offers that we did not advertise
+    pub fn peer_has_we_lack(&self) -> Services {
+        self.peer_version.services.difference(self.local_services)
+    }
+}
+
+/// Stage of a handshake reported to the handler set with [`Handshake::with_event_handler`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandshakeEvent {
+    /// The handshake with the peer at this address started over an established connection
+    Connected(SocketAddr),
+    /// Our version message was written to the peer
+    VersionSent(VersionMessage),
+    /// The peer's version message was received, before it is checked
+    VersionReceived(VersionMessage),
+    /// Our verack message was written to the peer
+    VerackSent,
+    /// The handshake succeeded
+    Completed(HandshakeResult),
+}
+
+/// Side of the connection that we are on, which decides who sends the first version message
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Role {
+    /// We opened the connection and send our version right away
+    Initiator,
+    /// The peer opened the connection, so we wait for its version before sending ours
+    Responder,
+}
+
+/// Callback observing the [`HandshakeEvent`]s of every handshake performed by a [`Handshake`]
+pub type HandshakeEventHandler = dyn Fn(HandshakeEvent) + Send + Sync;
+
+/// Performs the handshake (exchange of version and verack messages) with a peer
+#[derive(Clone)]
+pub struct Handshake {
+    config: HandshakeConfig,
+    on_event: Option<Arc<HandshakeEventHandler>>,
+}
+
+impl Debug for Handshake {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Handshake")
+            .field("config", &self.config)
+            .field(
+                "on_event",
+                &self.on_event.as_ref().map(|_| "Fn(HandshakeEvent)"),
+            )
+            .finish()
+    }
+}
+
+impl Handshake {
+    pub fn new(config: HandshakeConfig) -> Self {
+        Self {
+            config,
+            on_event: None,
+        }
+    }
+
+    /// Calls `on_event` at every stage of the handshakes performed from now on, e.g. for monitoring
+    pub fn with_event_handler(
+        mut self,
+        on_event: impl Fn(HandshakeEvent) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_event = Some(Arc::new(on_event));
+        self
+    }
+
+    /// Reports the event built by `event` to the event handler, if there is one
+    fn emit(&self, event: impl FnOnce() -> HandshakeEvent) {
+        if let Some(on_event) = &self.on_event {
+            on_event(event());
+        }
+    }
+
+    pub fn config(&self) -> &HandshakeConfig {
+        &self.config
+    }
+
+    /// Opens a TCP connection to `socket_address` from [`HandshakeConfig::bind_address`]
+    pub(crate) async fn open_stream(&self, socket_address: SocketAddr) -> io::Result<TcpStream> {
+        bind_socket(socket_address, self.config.bind_address)?
+            .connect(socket_address)
+            .await
+    }
+
+    /// Opens a TCP connection to `socket_address` and performs the handshake over it
+    pub async fn connect(&self, socket_address: SocketAddr) -> crate::Result<HandshakeResult> {
+        let mut tcp_stream = self.open_stream(socket_address).await?;
+        let local_address = tcp_stream.local_addr()?;
+        self.perform(&mut tcp_stream, socket_address, local_address, None)
+            .await
+    }
+
+    /// Same as [`Handshake::connect`], but tees every byte exchanged with the peer into `capture`
+    ///
+    /// The capture starts with a line naming the peer, so captures of several connections can be appended to the
+    /// same file and decoded again with [`crate::messages::capture::decode_capture`].
+    pub async fn connect_with_capture<W: Write + Unpin>(
+        &self,
+        socket_address: SocketAddr,
+        capture: &mut W,
+    ) -> crate::Result<HandshakeResult> {
+        write_peer_line(capture, socket_address)?;
+        let tcp_stream = self.open_stream(socket_address).await?;
+        let local_address = tcp_stream.local_addr()?;
+        let mut stream = TeeStream::new(tcp_stream, capture);
+        self.perform(&mut stream, socket_address, local_address, None)
+            .await
+    }
+
+    /// Performs the handshake over an already established `stream`
+    ///
+    /// Triggering `cancellation` aborts the handshake at its next `.await` with [`HandshakeError::Cancelled`] and shuts
+    /// the stream down. Without a token the handshake is never cancelled.
+    pub async fn perform<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        peer_address: SocketAddr,
+        local_address: SocketAddr,
+        cancellation: Option<CancellationToken>,
+    ) -> crate::Result<HandshakeResult> {
+        let version = self.version_message(peer_address, local_address);
+        self.perform_with_version(version, stream, peer_address, cancellation)
+            .await
+    }
+
+    /// Same as [`Handshake::perform`], but sends `version` verbatim instead of building the version message from the
+    /// config
+    ///
+    /// The rest of the handshake (the peer's version, the verack exchange and any politeness messages) is unchanged.
+    pub async fn perform_with_version<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        version: VersionMessage,
+        stream: &mut S,
+        peer_address: SocketAddr,
+        cancellation: Option<CancellationToken>,
+    ) -> crate::Result<HandshakeResult> {
+        self.perform_as(Role::Initiator, version, stream, peer_address, cancellation)
+            .await
+    }
+
+    /// Performs the responder side of the handshake over a `stream` accepted from the peer at `peer_address`
+    ///
+    /// The peer's version is awaited (and checked) before ours is sent, after which the verack exchange goes on as in
+    /// [`Handshake::perform`]. Cancellation works the same way, too.
+    pub async fn perform_responder<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut S,
+        peer_address: SocketAddr,
+        local_address: SocketAddr,
+        cancellation: Option<CancellationToken>,
+    ) -> crate::Result<HandshakeResult> {
+        let version = self.version_message(peer_address, local_address);
+        self.perform_as(Role::Responder, version, stream, peer_address, cancellation)
+            .await
+    }
+
+    async fn perform_as<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        role: Role,
+        version: VersionMessage,
+        stream: &mut S,
+        peer_address: SocketAddr,
+        cancellation: Option<CancellationToken>,
+    ) -> crate::Result<HandshakeResult> {
+        let cancellation = cancellation.unwrap_or_default();
+        let mut stream = MessageStream::with_capacity(
+            self.config.read_buffer_size,
+            CountingStream::new(TimeoutStream::new(stream, self.config.io_timeout)),
+        );
+        tokio::select! {
+            result = self.perform_framed_as(role, &mut stream, peer_address, version) => result,
+            _ = cancellation.cancelled() => {
+                if let Err(e) = stream.get_mut().shutdown().await {
+                    tracing::debug!("Shutting down cancelled handshake failed with error: {}", e);
+                }
+                Err(HandshakeError::Cancelled)?
+            }
+        }
+    }
+
+    /// Performs the handshake over an already wrapped `stream`, leaving any message received after the verack buffered
+    pub(crate) async fn perform_framed<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut MessageStream<CountingStream<S>>,
+        peer_address: SocketAddr,
+        version: VersionMessage,
+    ) -> crate::Result<HandshakeResult> {
+        self.perform_framed_as(Role::Initiator, stream, peer_address, version)
+            .await
+    }
+
+    async fn perform_framed_as<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        role: Role,
+        stream: &mut MessageStream<CountingStream<S>>,
+        peer_address: SocketAddr,
+        version: VersionMessage,
+    ) -> crate::Result<HandshakeResult> {
+        self.emit(|| HandshakeEvent::Connected(peer_address));
+        let local_services = version.services;
+        let local_version = version.version;
+        let started_at = Instant::now();
+        let peer_version = self.exchange_version_message(role, stream, version).await?;
+        let negotiated_version = local_version.min(peer_version.version);
+        let mut result = HandshakeResult {
+            peer_address,
+            peer_version,
+            local_services,
+            negotiated_version,
+            verack_sent: false,
+            verack_received: false,
+            verack_skipped: self.config.skip_verack || self.config.skip_verack_send,
+            peer_supports_addrv2: false,
+            peer_supports_wtxidrelay: false,
+            peer_sent_sendheaders: false,
+            bytes_sent: 0,
+            bytes_received: 0,
+            latency: Duration::ZERO,
+        };
+        // Our verack is sent as soon as the peer's version passed the checks, without waiting for anything else
+        if !self.config.skip_verack_send {
+            self.send_feature_messages(stream, negotiated_version)
+                .await?;
+            self.send_verack_message(stream).await?;
+            result.verack_sent = true;
+            if !self.config.skip_verack {
+                self.read_verack_message(stream, &mut result).await?;
+                if self.config.post_handshake_politeness {
+                    self.send_politeness_messages(stream, negotiated_version)
+                        .await?;
+                }
+            }
+        }
+        result.bytes_sent = stream.get_ref().bytes_written();
+        result.bytes_received = stream.get_ref().bytes_read();
+        result.latency = started_at.elapsed();
+        self.emit(|| HandshakeEvent::Completed(result.clone()));
+        Ok(result)
+    }
+
+    /// Version message advertising the config to the peer at `peer_address`, with a nonce taken from
+    /// [`HandshakeConfig::nonce_source`] and a timestamp taken from [`HandshakeConfig::timestamp_source`]
+    pub fn version_message(
+        &self,
+        peer_address: SocketAddr,
+        local_address: SocketAddr,
+    ) -> VersionMessage {
+        let mut version = VersionMessage::new(
+            self.config.protocol_version,
+            self.config.services,
+            self.config.timestamp_source.timestamp(),
+            self.config.receiving_services,
+            peer_address,
+            local_address,
+            self.config.services,
+            self.config.nonce_source.nonce(),
+            self.config.user_agent.clone(),
+            0,
+            false,
+        );
+        if !self.config.advertise_local_address {
+            version.transmitting_node = NetworkAddress::unspecified(self.config.services);
+        }
+        version
+    }
+
+    async fn exchange_version_message<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        role: Role,
+        stream: &mut MessageStream<S>,
+        version: VersionMessage,
+    ) -> crate::Result<VersionMessage> {
+        let nonce = version.nonce;
+        tracing::debug!(
+            receiving_node_kind = ?version.receiving_node.address_kind(),
+            "Sending version message"
+        );
+        let message = Message::<VersionMessage>::new(self.config.chain, version);
+        let mut raw_message = match role {
+            Role::Initiator => {
+                stream
+                    .write_and_read_raw_message_with(&message, || {
+                        self.emit(|| HandshakeEvent::VersionSent(message.message.clone()))
+                    })
+                    .await?
+            }
+            Role::Responder => stream.read_raw_message().await?,
+        };
+        while !raw_message.is::<VersionMessage>() {
+            if self.config.strict_message_order {
+                Err(HandshakeError::UnexpectedMessageBeforeVersion(
+                    raw_message.command(),
+                ))?
+            }
+            tracing::debug!(
+                command = %raw_message.command(),
+                "Ignoring message received before version"
+            );
+            raw_message = stream.read_raw_message().await?;
+        }
+        let received_message = raw_message.into_message::<VersionMessage>()?;
+        tracing::debug!(
+            chain = %received_message.chain,
+            message = ?received_message.message.redacted(),
+            "Received version message"
+        );
+        self.emit(|| HandshakeEvent::VersionReceived(received_message.message.clone()));
+
+        if received_message.chain != self.config.chain {
+            Err(HandshakeError::WrongNetwork)?
+        }
+        if received_message.message.version < 0 {
+            Err(HandshakeError::InvalidPeerVersion(
+                received_message.message.version,
+            ))?
+        }
+        if received_message.message.nonce == nonce {
+            Err(HandshakeError::SelfConnection)?
+        }
+        if role == Role::Responder {
+            // Only a peer whose version passed the checks gets to see ours
+            stream.write_message(&message).await?;
+            self.emit(|| HandshakeEvent::VersionSent(message.message));
+        }
+
+        Ok(received_message.message)
+    }
+
+    /// Announces the features that have to be negotiated between the version and verack messages (BIP339 and BIP155),
+    /// which peers below [`WTXID_RELAY_VERSION`] would treat as a protocol violation
+    async fn send_feature_messages<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut MessageStream<S>,
+        negotiated_version: i32,
+    ) -> crate::Result<()> {
+        if negotiated_version < WTXID_RELAY_VERSION {
+            return Ok(());
+        }
+        let chain = self.config.chain;
+        stream
+            .write_message(&Message::new(chain, WtxidRelayMessage))
+            .await?;
+        stream
+            .write_message(&Message::new(chain, SendAddrV2Message))
+            .await
+    }
+
+    /// Tells the peer how we'd like blocks and transactions to be announced, as nodes do right after the handshake
+    ///
+    /// Each message is only sent if the negotiated version is recent enough for the peer to understand it.
+    async fn send_politeness_messages<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut MessageStream<S>,
+        negotiated_version: i32,
+    ) -> crate::Result<()> {
+        let chain = self.config.chain;
+        if negotiated_version >= SENDHEADERS_VERSION {
+            stream
+                .write_message(&Message::new(chain, SendHeadersMessage))
+                .await?;
+        }
+        if negotiated_version >= SHORT_IDS_BLOCKS_VERSION {
+            let sendcmpct = SendCmpctMessage {
+                announce: false,
+                version: 2,
+            };
+            stream
+                .write_message(&Message::new(chain, sendcmpct))
+                .await?;
+        }
+        if negotiated_version >= FEEFILTER_VERSION {
+            let feefilter = FeeFilterMessage {
+                fee_rate: self.config.feefilter_rate,
+            };
+            stream
+                .write_message(&Message::new(chain, feefilter))
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn send_verack_message<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut MessageStream<S>,
+    ) -> crate::Result<()> {
+        let message = Message::<VerackMessage>::new(self.config.chain, VerackMessage);
+        stream.write_message(&message).await?;
+        self.emit(|| HandshakeEvent::VerackSent);
+        Ok(())
+    }
+
+    /// Waits for the peer's verack, recording the feature negotiation messages received before it
+    ///
+    /// Peers may send messages such as `sendaddrv2` or `wtxidrelay` between their version and verack messages, so any
+    /// message other than verack is tolerated rather than treated as an error.
+    async fn read_verack_message<S: AsyncRead + AsyncWrite + Unpin>(
+        &self,
+        stream: &mut MessageStream<S>,
+        result: &mut HandshakeResult,
+    ) -> crate::Result<()> {
+        loop {
+            // Note: In some of my trials, an empty buffer gets received instead of a Verack Message. Therefore, I have assumed that the Verack Message is optional and an empty buffer can be received instead.
+            let raw_message = match stream.read_raw_message().await {
+                Ok(raw_message) => raw_message,
+                Err(e) if e.downcast_ref::<CodecError>() == Some(&CodecError::UnexpectedEof) => {
+                    if self.config.require_verack {
+                        Err(HandshakeError::NoVerack)?
+                    }
+                    tracing::info!("VERACK message was not exchanged by peer");
+                    return Ok(());
+                }
+                Err(e) => return Err(e),
+            };
+
+            if raw_message.header.chain != self.config.chain {
+                Err(HandshakeError::WrongNetwork)?
+            }
+
+            if raw_message.is::<VerackMessage>() {
+                let received_message = raw_message.into_message::<VerackMessage>()?;
+                tracing::debug!(?received_message, "Received verack message");
+                result.verack_received = true;
+                return Ok(());
+            } else if raw_message.is::<SendAddrV2Message>() {
+                result.peer_supports_addrv2 = true;
+            } else if raw_message.is::<WtxidRelayMessage>() {
+                result.peer_supports_wtxidrelay = true;
+            } else if raw_message.is::<SendHeadersMessage>() {
+                result.peer_sent_sendheaders = true;
+            } else {
+                tracing::debug!(
+                    command = %raw_message.command(),
+                    "Ignoring message received before verack"
+                );
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::MAX_PAYLOAD_SIZE,
+        messages::{
+            capture::{decode_capture, Direction},
+            codec::{Decode, Encode},
+            framed::{read_framed_message, read_raw_message},
+            types::ping::PingMessage,
+            RawMessage,
+        },
+    };
+    use std::{
+        net::Ipv6Addr,
+        pin::Pin,
+        task::{Context, Poll},
+    };
+    use tokio::io::AsyncWriteExt;
+    use tokio::io::DuplexStream;
+
+    #[tokio::test]
+    async fn bind_socket_should_bind_before_connecting() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let peer_address = listener.local_addr().unwrap();
+
+        let socket = bind_socket(peer_address, Some("127.0.0.1:0".parse().unwrap())).unwrap();
+        let bound_address = socket.local_addr().unwrap();
+        assert!(bound_address.ip().is_loopback());
+        assert_ne!(bound_address.port(), 0);
+
+        let stream = socket.connect(peer_address).await.unwrap();
+        let (_, accepted_from) = listener.accept().await.unwrap();
+        assert_eq!(stream.local_addr().unwrap(), bound_address);
+        assert_eq!(accepted_from, bound_address);
+    }
+
+    fn peer_version(services: Services, user_agent: &str, start_height: i32) -> VersionMessage {
+        let network_address = NetworkAddress {
+            services,
+            ip_address: Ipv6Addr::UNSPECIFIED,
+            port: 8333,
+        };
+        VersionMessage {
+            version: PROTOCOL_VERSION,
+            services,
+            timestamp: 1415483324,
+            receiving_node: network_address,
+            transmitting_node: network_address,
+            nonce: 1,
+            user_agent: user_agent.to_string(),
+            user_agent_lossy: false,
+            start_height,
+            relay: true,
+        }
+    }
+
+    fn handshake_result(peer_version: VersionMessage) -> HandshakeResult {
+        HandshakeResult {
+            peer_address: "1.2.3.4:8333".parse().unwrap(),
+            peer_version,
+            local_services: Services::empty(),
+            negotiated_version: PROTOCOL_VERSION,
+            verack_sent: true,
+            verack_received: true,
+            verack_skipped: false,
+            peer_supports_addrv2: false,
+            peer_supports_wtxidrelay: false,
+            peer_sent_sendheaders: false,
+            bytes_sent: 0,
+            bytes_received: 0,
+            latency: Duration::ZERO,
+        }
+    }
+
+    fn config() -> HandshakeConfig {
+        HandshakeConfig::for_chain(Chain::Mainnet)
+    }
+
+    #[test]
+    fn version_message_should_take_timestamp_from_source() {
+        let version_timestamp = |timestamp_source| {
+            let handshake = Handshake::new(HandshakeConfig {
+                timestamp_source,
+                ..config()
+            });
+            let version = handshake.version_message(
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+            );
+            // The timestamp follows the 4-byte version and the 8-byte services
+            let encoded = version.encode().unwrap();
+            i64::from_le_bytes(encoded[12..20].try_into().unwrap())
+        };
+
+        let before = TimestampSource::Now.timestamp();
+        let now = version_timestamp("now".parse().unwrap());
+        assert!(now >= before && now <= TimestampSource::Now.timestamp());
+        assert_eq!(
+            version_timestamp("fixed:1415483324".parse().unwrap()),
+            1415483324
+        );
+        assert_eq!(version_timestamp("zero".parse().unwrap()), 0);
+        assert_eq!(
+            "fixed:soon".parse::<TimestampSource>(),
+            Err(ParseTimestampSourceError("fixed:soon".to_string()))
+        );
+    }
+
+    #[test]
+    fn version_message_should_take_nonce_from_source() {
+        let nonces = |nonce_source: &str| {
+            let handshake = Handshake::new(HandshakeConfig {
+                nonce_source: nonce_source.parse().unwrap(),
+                ..config()
+            });
+            let nonce = || {
+                handshake
+                    .version_message(
+                        "1.2.3.4:8333".parse().unwrap(),
+                        "127.0.0.1:50000".parse().unwrap(),
+                    )
+                    .nonce
+            };
+            (nonce(), nonce())
+        };
+
+        let (first, second) = nonces("per-connection");
+        assert_ne!(first, second);
+        let (first, second) = nonces("per-run");
+        assert_eq!(first, second);
+        assert_eq!(nonces("per-run:7"), (7, 7));
+        assert_eq!(nonces("fixed:42"), (42, 42));
+        assert_eq!(
+            "fixed:".parse::<NonceSource>(),
+            Err(ParseNonceSourceError("fixed:".to_string()))
+        );
+    }
+
+    #[test]
+    fn version_message_should_hide_local_address_unless_advertised() {
+        let peer_address = "1.2.3.4:8333".parse().unwrap();
+        let local_address = "192.168.1.2:50000".parse().unwrap();
+        let handshake = Handshake::new(config());
+        assert_eq!(
+            handshake
+                .version_message(peer_address, local_address)
+                .transmitting_node
+                .port,
+            50000
+        );
+
+        let mut config = config();
+        config.advertise_local_address = false;
+        let handshake = Handshake::new(config);
+        assert_eq!(
+            handshake
+                .version_message(peer_address, local_address)
+                .transmitting_node,
+            NetworkAddress::unspecified(handshake.config().services)
+        );
+    }
+
+    /// Plays the peer side of the handshake, answering with `version` and then a verack
+    async fn mock_peer(mut stream: DuplexStream, version: VersionMessage) {
+        mock_peer_with(&mut stream, version, vec![]).await
+    }
+
+    /// Plays the peer side of the handshake, sending the `before_verack` frames between its version and verack
+    async fn mock_peer_with(
+        stream: &mut DuplexStream,
+        version: VersionMessage,
+        before_verack: Vec<Vec<u8>>,
+    ) {
+        read_framed_message::<VersionMessage, _>(stream, MAX_PAYLOAD_SIZE)
+            .await
+            .unwrap();
+        let message = Message::new(Chain::Mainnet, version);
+        stream.write_all(&message.encode().unwrap()).await.unwrap();
+        for frame in before_verack {
+            stream.write_all(&frame).await.unwrap();
+        }
+        read_framed_message::<VerackMessage, _>(stream, MAX_PAYLOAD_SIZE)
+            .await
+            .unwrap();
+        let message = Message::new(Chain::Mainnet, VerackMessage);
+        stream.write_all(&message.encode().unwrap()).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn perform_should_work() {
+        let (mut client, peer) = tokio::io::duplex(1024);
+        let version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+        let peer_task = tokio::spawn(mock_peer(peer, version.clone()));
+
+        let result = Handshake::new(config())
+            .perform(
+                &mut client,
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+                None,
+            )
+            .await
+            .unwrap();
+        peer_task.await.unwrap();
+
+        assert_eq!(result.peer_version, version);
+        assert_eq!(result.local_services, config().services);
+        assert!(result.verack_received);
+    }
+
+    #[tokio::test]
+    async fn perform_responder_should_complete_with_initiator() {
+        let (mut initiator_stream, mut responder_stream) = tokio::io::duplex(1024);
+        let (initiator_address, responder_address) = (
+            "127.0.0.1:50000".parse().unwrap(),
+            "127.0.0.1:8333".parse().unwrap(),
+        );
+        let initiator = Handshake::new(HandshakeConfig {
+            user_agent: "/initiator:1.0/".to_string(),
+            ..config()
+        });
+        let events = Arc::new(std::sync::Mutex::new(vec![]));
+        let responder = Handshake::new(HandshakeConfig {
+            protocol_version: 70015,
+            user_agent: "/responder:1.0/".to_string(),
+            ..config()
+        })
+        .with_event_handler({
+            let events = events.clone();
+            move |event| events.lock().unwrap().push(event)
+        });
+
+        let (initiator_result, responder_result) = tokio::join!(
+            initiator.perform(
+                &mut initiator_stream,
+                responder_address,
+                initiator_address,
+                None
+            ),
+            responder.perform_responder(
+                &mut responder_stream,
+                initiator_address,
+                responder_address,
+                None
+            ),
+        );
+        let (initiator_result, responder_result) =
+            (initiator_result.unwrap(), responder_result.unwrap());
+
+        assert!(initiator_result.verack_received && responder_result.verack_received);
+        assert_eq!(initiator_result.peer_version.version, 70015);
+        assert_eq!(initiator_result.peer_version.user_agent, "/responder:1.0/");
+        assert_eq!(
+            responder_result.peer_version.version,
+            initiator.config().protocol_version
+        );
+        assert_eq!(responder_result.peer_version.user_agent, "/initiator:1.0/");
+
+        // The responder only sends its version once it received the initiator's
+        let events = events.lock().unwrap();
+        assert!(matches!(events[1], HandshakeEvent::VersionReceived(_)));
+        assert!(matches!(events[2], HandshakeEvent::VersionSent(_)));
+    }
+
+    #[tokio::test]
+    async fn perform_should_emit_events_in_order() {
+        let (mut client, peer) = tokio::io::duplex(1024);
+        let version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+        let peer_task = tokio::spawn(mock_peer(peer, version.clone()));
+
+        let events = Arc::new(std::sync::Mutex::new(vec![]));
+        let handshake = Handshake::new(config()).with_event_handler({
+            let events = events.clone();
+            move |event| events.lock().unwrap().push(event)
+        });
+        let peer_address = "1.2.3.4:8333".parse().unwrap();
+        let sent_version =
+            handshake.version_message(peer_address, "127.0.0.1:50000".parse().unwrap());
+        let result = handshake
+            .perform_with_version(sent_version.clone(), &mut client, peer_address, None)
+            .await
+            .unwrap();
+        peer_task.await.unwrap();
+
+        assert_eq!(
+            *events.lock().unwrap(),
+            vec![
+                HandshakeEvent::Connected(peer_address),
+                HandshakeEvent::VersionSent(sent_version),
+                HandshakeEvent::VersionReceived(version),
+                HandshakeEvent::VerackSent,
+                HandshakeEvent::Completed(result),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn perform_should_emit_version_sent_before_silent_peer_answers() {
+        let (mut client, _peer) = tokio::io::duplex(1024);
+        let cancellation = CancellationToken::new();
+        let events = Arc::new(std::sync::Mutex::new(vec![]));
+        let handshake = Handshake::new(config()).with_event_handler({
+            let (events, cancellation) = (events.clone(), cancellation.clone());
+            move |event| {
+                if matches!(event, HandshakeEvent::VersionSent(_)) {
+                    cancellation.cancel();
+                }
+                events.lock().unwrap().push(event)
+            }
+        });
+        let peer_address = "1.2.3.4:8333".parse().unwrap();
+
+        // The peer accepts our version but never sends its own, so only the event can end the handshake
+        let error = handshake
+            .perform(
+                &mut client,
+                peer_address,
+                "127.0.0.1:50000".parse().unwrap(),
+                Some(cancellation),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<HandshakeError>(),
+            Some(&HandshakeError::Cancelled)
+        );
+
+        let events = events.lock().unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0], HandshakeEvent::Connected(peer_address));
+        assert!(matches!(events[1], HandshakeEvent::VersionSent(_)));
+    }
+
+    #[tokio::test]
+    async fn perform_should_record_feature_messages_sent_before_verack() {
+        let (mut client, mut peer) = tokio::io::duplex(1024);
+        let version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+        let sendaddrv2 = Message::new(Chain::Mainnet, SendAddrV2Message)
+            .encode()
+            .unwrap();
+        let peer_task =
+            tokio::spawn(async move { mock_peer_with(&mut peer, version, vec![sendaddrv2]).await });
+
+        let result = Handshake::new(config())
+            .perform(
+                &mut client,
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+                None,
+            )
+            .await
+            .unwrap();
+        peer_task.await.unwrap();
+
+        assert!(result.verack_received);
+        assert!(result.peer_supports_addrv2);
+        assert!(!result.peer_supports_wtxidrelay);
+        assert!(!result.peer_sent_sendheaders);
+    }
+
+    #[tokio::test]
+    async fn perform_should_send_politeness_messages_after_verack() {
+        let (mut client, mut peer) = tokio::io::duplex(1024);
+        let version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+        let peer_task = tokio::spawn(async move {
+            mock_peer_with(&mut peer, version, vec![]).await;
+            let mut received = Vec::new();
+            for _ in 0..3 {
+                received.push(read_raw_message(&mut peer, MAX_PAYLOAD_SIZE).await.unwrap());
+            }
+            received
+        });
+
+        let result = Handshake::new(HandshakeConfig {
+            post_handshake_politeness: true,
+            feefilter_rate: 2000,
+            ..config()
+        })
+        .perform(
+            &mut client,
+            "1.2.3.4:8333".parse().unwrap(),
+            "127.0.0.1:50000".parse().unwrap(),
+            None,
+        )
+        .await
+        .unwrap();
+        assert!(result.verack_received);
+
+        let received = peer_task.await.unwrap();
+        assert!(received[0].is::<SendHeadersMessage>());
+        assert_eq!(
+            received[1]
+                .clone()
+                .into_message::<SendCmpctMessage>()
+                .unwrap()
+                .message,
+            SendCmpctMessage {
+                announce: false,
+                version: 2
+            }
+        );
+        assert_eq!(
+            received[2]
+                .clone()
+                .into_message::<FeeFilterMessage>()
+                .unwrap()
+                .message,
+            FeeFilterMessage { fee_rate: 2000 }
+        );
+    }
+
+    /// Performs the handshake advertising `local_version` with a peer advertising `peer_version`, returning the result
+    /// along with the commands of the messages the peer received between the versions and our verack
+    async fn commands_sent_before_verack(
+        local_version: i32,
+        peer_version_number: i32,
+    ) -> (HandshakeResult, Vec<Command>) {
+        let (mut client, mut peer) = tokio::io::duplex(1024);
+        let mut version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+        version.version = peer_version_number;
+        let peer_task = tokio::spawn(async move {
+            read_framed_message::<VersionMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                .await
+                .unwrap();
+            let message = Message::new(Chain::Mainnet, version);
+            peer.write_all(&message.encode().unwrap()).await.unwrap();
+            let mut commands = Vec::new();
+            loop {
+                let raw_message = read_raw_message(&mut peer, MAX_PAYLOAD_SIZE).await.unwrap();
+                if raw_message.is::<VerackMessage>() {
+                    break;
+                }
+                commands.push(raw_message.command());
+            }
+            let message = Message::new(Chain::Mainnet, VerackMessage);
+            peer.write_all(&message.encode().unwrap()).await.unwrap();
+            commands
+        });
+
+        let result = Handshake::new(HandshakeConfig {
+            protocol_version: local_version,
+            ..config()
+        })
+        .perform(
+            &mut client,
+            "1.2.3.4:8333".parse().unwrap(),
+            "127.0.0.1:50000".parse().unwrap(),
+            None,
+        )
+        .await
+        .unwrap();
+        (result, peer_task.await.unwrap())
+    }
+
+    #[tokio::test]
+    async fn perform_should_announce_features_only_from_wtxidrelay_version() {
+        let (result, commands) =
+            commands_sent_before_verack(WTXID_RELAY_VERSION, WTXID_RELAY_VERSION).await;
+        assert_eq!(result.negotiated_version, WTXID_RELAY_VERSION);
+        assert_eq!(commands, vec![Command::WtxidRelay, Command::SendAddrV2]);
+
+        let (result, commands) = commands_sent_before_verack(WTXID_RELAY_VERSION, 70001).await;
+        assert!(result.verack_received);
+        assert_eq!(result.negotiated_version, 70001);
+        assert!(!commands.contains(&Command::WtxidRelay));
+        assert!(commands.is_empty());
+    }
+
+    #[tokio::test]
+    async fn perform_should_count_bytes_sent_and_received() {
+        let (mut client, peer) = tokio::io::duplex(1024);
+        let version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+        let peer_task = tokio::spawn(mock_peer(peer, version.clone()));
+
+        let result = Handshake::new(config())
+            .perform(
+                &mut client,
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+                None,
+            )
+            .await
+            .unwrap();
+        peer_task.await.unwrap();
+
+        // Our version carries the default user agent after its fixed-size fields
+        let version_len = 24 + 86 + DEFAULT_USER_AGENT.len() as u64;
+        let verack_len = Message::new(Chain::Mainnet, VerackMessage)
+            .encode()
+            .unwrap()
+            .len() as u64;
+        let peer_version_len = Message::new(Chain::Mainnet, version)
+            .encode()
+            .unwrap()
+            .len() as u64;
+        assert_eq!(result.bytes_sent, version_len + verack_len);
+        assert_eq!(result.bytes_received, peer_version_len + verack_len);
+    }
+
+    #[tokio::test]
+    async fn perform_should_exchange_versions_concurrently() {
+        // The pipe is smaller than either version message, so the peer can't finish sending its version until we read
+        // it, and we couldn't finish sending ours first without the peer reading it
+        let (mut client, mut peer) = tokio::io::duplex(64);
+        let version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+        let sent_version = version.clone();
+        let peer_task = tokio::spawn(async move {
+            let message = Message::new(Chain::Mainnet, sent_version);
+            peer.write_all(&message.encode().unwrap()).await.unwrap();
+            let received_version =
+                read_framed_message::<VersionMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                    .await
+                    .unwrap();
+            read_framed_message::<VerackMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                .await
+                .unwrap();
+            let message = Message::new(Chain::Mainnet, VerackMessage);
+            peer.write_all(&message.encode().unwrap()).await.unwrap();
+            received_version
+        });
+
+        let result = Handshake::new(config())
+            .perform(
+                &mut client,
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+                None,
+            )
+            .await
+            .unwrap();
+        let received_version = peer_task.await.unwrap();
+
+        assert_eq!(received_version.message.version, PROTOCOL_VERSION);
+        assert_eq!(result.peer_version, version);
+        assert!(result.verack_received);
+    }
+
+    #[tokio::test]
+    async fn perform_should_succeed_without_verack_when_skipping_it() {
+        let (mut client, mut peer) = tokio::io::duplex(1024);
+        let version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+        let peer_version = version.clone();
+        // The peer only answers the version message and never sends a verack
+        let peer_task = tokio::spawn(async move {
+            read_framed_message::<VersionMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                .await
+                .unwrap();
+            let message = Message::new(Chain::Mainnet, peer_version);
+            peer.write_all(&message.encode().unwrap()).await.unwrap();
+            peer
+        });
+
+        let result = Handshake::new(HandshakeConfig {
+            skip_verack: true,
+            ..config()
+        })
+        .perform(
+            &mut client,
+            "1.2.3.4:8333".parse().unwrap(),
+            "127.0.0.1:50000".parse().unwrap(),
+            None,
+        )
+        .await
+        .unwrap();
+        let _peer = peer_task.await.unwrap();
+
+        assert_eq!(result.peer_version, version);
+        assert!(result.verack_skipped);
+        assert!(result.verack_sent);
+        assert!(!result.verack_received);
+    }
+
+    #[tokio::test]
+    async fn perform_should_fail_without_verack_only_when_required() {
+        for require_verack in [false, true] {
+            let (mut client, mut peer) = tokio::io::duplex(1024);
+            let version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+            // The peer answers the version message and hangs up once it read our verack
+            let peer_task = tokio::spawn(async move {
+                read_framed_message::<VersionMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                    .await
+                    .unwrap();
+                let message = Message::new(Chain::Mainnet, version);
+                peer.write_all(&message.encode().unwrap()).await.unwrap();
+                read_framed_message::<VerackMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                    .await
+                    .unwrap();
+            });
+
+            let result = Handshake::new(HandshakeConfig {
+                require_verack,
+                ..config()
+            })
+            .perform(
+                &mut client,
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+                None,
+            )
+            .await;
+            peer_task.await.unwrap();
+
+            if require_verack {
+                assert_eq!(
+                    result.unwrap_err().downcast_ref::<HandshakeError>(),
+                    Some(&HandshakeError::NoVerack)
+                );
+            } else {
+                assert!(!result.unwrap().verack_received);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn perform_should_handle_ping_before_version_per_strictness() {
+        for strict_message_order in [false, true] {
+            let (mut client, mut peer) = tokio::io::duplex(1024);
+            let version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+            // The peer pings us before answering with its version, then finishes the handshake if we carried on
+            let peer_task = tokio::spawn(async move {
+                read_framed_message::<VersionMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                    .await
+                    .unwrap();
+                let ping = Message::new(Chain::Mainnet, PingMessage { nonce: 7 });
+                peer.write_all(&ping.encode().unwrap()).await.unwrap();
+                let message = Message::new(Chain::Mainnet, version);
+                peer.write_all(&message.encode().unwrap()).await.unwrap();
+                if !strict_message_order {
+                    read_framed_message::<VerackMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                        .await
+                        .unwrap();
+                    let message = Message::new(Chain::Mainnet, VerackMessage);
+                    peer.write_all(&message.encode().unwrap()).await.unwrap();
+                }
+            });
+
+            let result = Handshake::new(HandshakeConfig {
+                strict_message_order,
+                ..config()
+            })
+            .perform(
+                &mut client,
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+                None,
+            )
+            .await;
+            peer_task.await.unwrap();
+
+            if strict_message_order {
+                assert_eq!(
+                    result.unwrap_err().downcast_ref::<HandshakeError>(),
+                    Some(&HandshakeError::UnexpectedMessageBeforeVersion(
+                        Command::Ping
+                    ))
+                );
+            } else {
+                assert!(result.unwrap().verack_received);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn perform_should_send_verack_right_after_peer_version() {
+        let (mut client, mut peer) = tokio::io::duplex(1024);
+        let version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+        // The peer withholds its verack until ours arrived, which it only waits a moment for
+        let peer_task = tokio::spawn(async move {
+            read_framed_message::<VersionMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                .await
+                .unwrap();
+            let message = Message::new(Chain::Mainnet, version);
+            peer.write_all(&message.encode().unwrap()).await.unwrap();
+            tokio::time::timeout(
+                Duration::from_millis(100),
+                read_framed_message::<VerackMessage, _>(&mut peer, MAX_PAYLOAD_SIZE),
+            )
+            .await
+            .expect("verack should follow the peer's version without delay")
+            .unwrap();
+            let message = Message::new(Chain::Mainnet, VerackMessage);
+            peer.write_all(&message.encode().unwrap()).await.unwrap();
+        });
+
+        let result = Handshake::new(config())
+            .perform(
+                &mut client,
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+                None,
+            )
+            .await
+            .unwrap();
+        peer_task.await.unwrap();
+
+        assert!(result.verack_sent && result.verack_received);
+    }
+
+    /// Stream that records the bytes written to `inner` between consecutive flushes
+    struct FlushRecorder {
+        inner: DuplexStream,
+        unflushed: Vec<u8>,
+        flushed: Vec<Vec<u8>>,
+    }
+
+    impl AsyncRead for FlushRecorder {
+        fn poll_read(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &mut tokio::io::ReadBuf<'_>,
+        ) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_read(cx, buf)
+        }
+    }
+
+    impl AsyncWrite for FlushRecorder {
+        fn poll_write(
+            mut self: Pin<&mut Self>,
+            cx: &mut Context<'_>,
+            buf: &[u8],
+        ) -> Poll<io::Result<usize>> {
+            let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+            if let Poll::Ready(Ok(written)) = poll {
+                self.unflushed.extend_from_slice(&buf[..written]);
+            }
+            poll
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            let poll = Pin::new(&mut self.inner).poll_flush(cx);
+            if let Poll::Ready(Ok(())) = poll {
+                let unflushed = std::mem::take(&mut self.unflushed);
+                self.flushed.push(unflushed);
+            }
+            poll
+        }
+
+        fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+            Pin::new(&mut self.inner).poll_shutdown(cx)
+        }
+    }
+
+    #[tokio::test]
+    async fn perform_should_flush_version_and_verack_once_written() {
+        let (client, peer) = tokio::io::duplex(1024);
+        let version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+        let peer_task = tokio::spawn(mock_peer(peer, version));
+        let mut client = FlushRecorder {
+            inner: client,
+            unflushed: Vec::new(),
+            flushed: Vec::new(),
+        };
+
+        Handshake::new(config())
+            .perform(
+                &mut client,
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+                None,
+            )
+            .await
+            .unwrap();
+        peer_task.await.unwrap();
+
+        assert!(client.unflushed.is_empty());
+        let [version_frame, verack_frame] = &client.flushed[..] else {
+            panic!("unexpected flushes {:?}", client.flushed);
+        };
+        let version_message = RawMessage::decode_bytes(version_frame).unwrap();
+        assert!(version_message.is::<VersionMessage>());
+        assert_eq!(version_message.encode().unwrap(), *version_frame);
+        assert_eq!(
+            *verack_frame,
+            Message::new(Chain::Mainnet, VerackMessage)
+                .encode()
+                .unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn perform_should_not_send_verack_when_connected_to_itself() {
+        let (mut client, mut peer) = tokio::io::duplex(1024);
+        // The peer echoes our own version back, just like we'd answer ourselves
+        let peer_task = tokio::spawn(async move {
+            let version = read_framed_message::<VersionMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                .await
+                .unwrap();
+            peer.write_all(&version.encode().unwrap()).await.unwrap();
+            let mut rest = Vec::new();
+            tokio::io::AsyncReadExt::read_to_end(&mut peer, &mut rest)
+                .await
+                .unwrap();
+            rest
+        });
+
+        let error = Handshake::new(config())
+            .perform(
+                &mut client,
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+                None,
+            )
+            .await
+            .unwrap_err();
+        drop(client);
+
+        assert_eq!(
+            error.downcast_ref::<HandshakeError>(),
+            Some(&HandshakeError::SelfConnection)
+        );
+        assert!(peer_task.await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn perform_should_succeed_with_verack_whether_required_or_not() {
+        for require_verack in [false, true] {
+            let (mut client, peer) = tokio::io::duplex(1024);
+            let version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+            let peer_task = tokio::spawn(mock_peer(peer, version));
+
+            let result = Handshake::new(HandshakeConfig {
+                require_verack,
+                ..config()
+            })
+            .perform(
+                &mut client,
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+                None,
+            )
+            .await
+            .unwrap();
+            peer_task.await.unwrap();
+
+            assert!(result.verack_received);
+        }
+    }
+
+    #[tokio::test]
+    async fn perform_should_never_send_verack_when_skipping_its_send() {
+        let (mut client, mut peer) = tokio::io::duplex(1024);
+        let version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+        let peer_version = version.clone();
+        // The peer answers the version message and then collects everything else we send until we hang up
+        let peer_task = tokio::spawn(async move {
+            read_framed_message::<VersionMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                .await
+                .unwrap();
+            let message = Message::new(Chain::Mainnet, peer_version);
+            peer.write_all(&message.encode().unwrap()).await.unwrap();
+            let mut received = vec![];
+            tokio::io::AsyncReadExt::read_to_end(&mut peer, &mut received)
+                .await
+                .unwrap();
+            received
+        });
+
+        let result = Handshake::new(HandshakeConfig {
+            skip_verack_send: true,
+            ..config()
+        })
+        .perform(
+            &mut client,
+            "1.2.3.4:8333".parse().unwrap(),
+            "127.0.0.1:50000".parse().unwrap(),
+            None,
+        )
+        .await
+        .unwrap();
+        drop(client);
+
+        assert!(peer_task.await.unwrap().is_empty());
+        assert_eq!(result.peer_version, version);
+        assert!(!result.verack_sent);
+        assert!(result.verack_skipped);
+        assert!(!result.verack_received);
+    }
+
+    #[tokio::test]
+    async fn perform_should_abort_and_close_stream_when_cancelled() {
+        let (mut client, mut peer) = tokio::io::duplex(1024);
+        let cancellation = CancellationToken::new();
+        let peer_cancellation = cancellation.clone();
+        let peer_task = tokio::spawn(async move {
+            // The peer never answers, so the handshake is stuck waiting for its version until cancelled
+            read_framed_message::<VersionMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                .await
+                .unwrap();
+            peer_cancellation.cancel();
+            let mut remaining = Vec::new();
+            tokio::io::AsyncReadExt::read_to_end(&mut peer, &mut remaining)
+                .await
+                .unwrap();
+            remaining
+        });
+
+        let error = Handshake::new(config())
+            .perform(
+                &mut client,
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+                Some(cancellation),
+            )
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            error.downcast_ref::<HandshakeError>(),
+            Some(&HandshakeError::Cancelled)
+        );
+        // Reaching the end of the stream means it was shut down without anything else being sent
+        assert!(peer_task.await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn perform_with_version_should_send_version_verbatim() {
+        let mut version = peer_version(Services::NODE_GETUTXO, "/handcrafted:1.0/", 123456);
+        version.nonce = 0x0123456789abcdef;
+        version.timestamp = 1415483324;
+        let expected_bytes = Message::new(Chain::Mainnet, version.clone())
+            .encode()
+            .unwrap();
+
+        let (mut client, mut peer) = tokio::io::duplex(1024);
+        let peer_task = tokio::spawn(async move {
+            let mut received_bytes = vec![0u8; expected_bytes.len()];
+            tokio::io::AsyncReadExt::read_exact(&mut peer, &mut received_bytes)
+                .await
+                .unwrap();
+            assert_eq!(received_bytes, expected_bytes);
+
+            let reply = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+            let message = Message::new(Chain::Mainnet, reply);
+            peer.write_all(&message.encode().unwrap()).await.unwrap();
+            read_framed_message::<VerackMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                .await
+                .unwrap();
+            let message = Message::new(Chain::Mainnet, VerackMessage);
+            peer.write_all(&message.encode().unwrap()).await.unwrap();
+        });
+
+        let result = Handshake::new(config())
+            .perform_with_version(version, &mut client, "1.2.3.4:8333".parse().unwrap(), None)
+            .await
+            .unwrap();
+        peer_task.await.unwrap();
+
+        assert_eq!(result.peer_version.user_agent, "/Satoshi:25.0.0/");
+        assert!(result.verack_received);
+    }
+
+    #[tokio::test]
+    async fn io_timeout_should_fire_before_overall_timeout_when_peer_stalls() {
+        let (mut client, mut peer) = tokio::io::duplex(1024);
+        let peer_task = tokio::spawn(async move {
+            read_framed_message::<VersionMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                .await
+                .unwrap();
+            // Only part of the header of our version is sent back, after which the peer stalls
+            let version = Message::new(Chain::Mainnet, peer_version(Services::NODE_NETWORK, "", 0));
+            peer.write_all(&version.encode().unwrap()[..10])
+                .await
+                .unwrap();
+            peer
+        });
+
+        let mut config = config();
+        config.io_timeout = Some(Duration::from_millis(50));
+        let overall_timeout = Duration::from_secs(10);
+        let started_at = std::time::Instant::now();
+        let error = tokio::time::timeout(
+            overall_timeout,
+            Handshake::new(config).perform(
+                &mut client,
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+                None,
+            ),
+        )
+        .await
+        .expect("the io timeout should fire first")
+        .unwrap_err();
+        let _peer = peer_task.await.unwrap();
+
+        assert!(started_at.elapsed() < overall_timeout);
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::Io(io::ErrorKind::TimedOut))
+        );
+        assert!(error.is_retryable());
+    }
+
+    #[tokio::test]
+    async fn perform_should_reject_negative_peer_version() {
+        let (mut client, mut peer) = tokio::io::duplex(1024);
+        let mut version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+        version.version = -1;
+        let peer_task = tokio::spawn(async move {
+            read_framed_message::<VersionMessage, _>(&mut peer, MAX_PAYLOAD_SIZE)
+                .await
+                .unwrap();
+            let message = Message::new(Chain::Mainnet, version);
+            peer.write_all(&message.encode().unwrap()).await.unwrap();
+        });
+
+        let error = Handshake::new(config())
+            .perform(
+                &mut client,
+                "1.2.3.4:8333".parse().unwrap(),
+                "127.0.0.1:50000".parse().unwrap(),
+                None,
+            )
+            .await
+            .unwrap_err();
+        peer_task.await.unwrap();
+
+        assert_eq!(
+            error.downcast_ref::<HandshakeError>(),
+            Some(&HandshakeError::InvalidPeerVersion(-1))
+        );
+    }
+
+    #[test]
+    fn for_chain_should_use_defaults_of_chain() {
+        let config = HandshakeConfig::for_chain(Chain::Testnet3);
+        assert_eq!(config.chain, Chain::Testnet3);
+        assert_eq!(config.port, 18333);
+        assert_eq!(config.services, Services::UNNAMED);
+        assert!(config.user_agent.starts_with("/bitcoin-p2p:"));
+    }
+
+    #[tokio::test]
+    async fn captured_handshake_should_decode_to_version_and_verack() {
+        let (client, peer) = tokio::io::duplex(1024);
+        let version = peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 800000);
+        let peer_task = tokio::spawn(mock_peer(peer, version.clone()));
+
+        let peer_address = "1.2.3.4:8333".parse().unwrap();
+        let mut capture = Vec::new();
+        write_peer_line(&mut capture, peer_address).unwrap();
+        let mut stream = TeeStream::new(client, &mut capture);
+        Handshake::new(config())
+            .perform(
+                &mut stream,
+                peer_address,
+                "127.0.0.1:50000".parse().unwrap(),
+                None,
+            )
+            .await
+            .unwrap();
+        peer_task.await.unwrap();
+
+        let messages = decode_capture(&String::from_utf8(capture).unwrap()).unwrap();
+        let directions: Vec<_> = messages.iter().map(|m| m.direction).collect();
+        assert_eq!(
+            directions,
+            vec![
+                Direction::Sent,
+                Direction::Received,
+                Direction::Sent,
+                Direction::Received
+            ]
+        );
+        assert!(messages
+            .iter()
+            .all(|m| m.peer_address == Some(peer_address)));
+        assert!(messages[0].message.is::<VersionMessage>());
+        assert_eq!(
+            messages[1]
+                .message
+                .clone()
+                .into_message::<VersionMessage>()
+                .unwrap()
+                .message,
+            version
+        );
+        assert!(messages[2].message.is::<VerackMessage>());
+        assert!(messages[3].message.is::<VerackMessage>());
+    }
+
+    #[test]
+    fn looks_like_listening_node_should_accept_full_node() {
+        let result = handshake_result(peer_version(
+            Services::NODE_NETWORK | Services::NODE_WITNESS,
+            "/Satoshi:25.0.0/",
+            800000,
+        ));
+        assert!(result.looks_like_listening_node());
+    }
+
+    #[test]
+    fn looks_like_listening_node_should_reject_non_serving_or_anonymous_peers() {
+        let without_node_network = handshake_result(peer_version(
+            Services::NODE_NETWORK_LIMITED,
+            "/Satoshi:25.0.0/",
+            800000,
+        ));
+        let without_user_agent = handshake_result(peer_version(Services::NODE_NETWORK, "", 800000));
+        let without_start_height =
+            handshake_result(peer_version(Services::NODE_NETWORK, "/Satoshi:25.0.0/", 0));
+
+        assert!(!without_node_network.looks_like_listening_node());
+        assert!(!without_user_agent.looks_like_listening_node());
+        assert!(!without_start_height.looks_like_listening_node());
+    }
+
+    #[test]
+    fn summary_line_should_describe_peer_on_one_line() {
+        let mut result = handshake_result(peer_version(
+            Services::NODE_NETWORK | Services::NODE_WITNESS,
+            "/Satoshi:25.0.0/",
+            800000,
+        ));
+        result.peer_version.version = 70016;
+        result.latency = Duration::from_millis(42);
+
+        assert_eq!(
+            result.summary_line(),
+            r#"peer=1.2.3.4:8333 ua="/Satoshi:25.0.0/" ver=70016 services="NODE_NETWORK | NODE_WITNESS" height=800000 latency=42ms"#
+        );
+
+        result.peer_version.user_agent = "/evil/\nheight=1 ua=\"\u{FFFD}".to_string();
+        let summary_line = result.summary_line();
+        assert!(!summary_line.contains('\n'));
+        assert!(summary_line.contains(" ua=\"/evil/\\nheight=1 ua=\\\"\u{FFFD}\" ver=70016 "));
+    }
+
+    #[test]
+    fn services_differences_should_be_computed_in_both_directions() {
+        let mut result = handshake_result(peer_version(
+            Services::NODE_NETWORK | Services::NODE_WITNESS | Services::NODE_BLOOM,
+            "/Satoshi:25.0.0/",
+            800000,
+        ));
+        result.local_services = Services::NODE_WITNESS | Services::NODE_NETWORK_LIMITED;
+
+        assert_eq!(
+            result.services_we_have_peer_lacks(),
+            Services::NODE_NETWORK_LIMITED
+        );
+        assert_eq!(
+            result.peer_has_we_lack(),
+            Services::NODE_NETWORK | Services::NODE_BLOOM
+        );
+
+        result.local_services = result.peer_version.services;
+        assert!(result.services_we_have_peer_lacks().is_empty());
+        assert!(result.peer_has_we_lack().is_empty());
+    }
+}
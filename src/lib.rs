@@ -1,5 +1,10 @@
 //! Crate that deals with Bitcoin p2p networking functionality
 
+pub mod connection;
 pub mod constants;
 mod crypto;
+mod error;
+pub mod handshake;
 pub mod messages;
+
+pub use error::{Error, Result};
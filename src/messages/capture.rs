@@ -0,0 +1,242 @@
+//! Module contains the functionality for capturing the bytes exchanged with a peer and decoding them again
+//!
+//! A capture is a text log where every chunk of bytes is written on its own line as hex, prefixed by `>` when it was
+//! sent and `<` when it was received. Lines starting with `#` name the peer the following chunks were exchanged with.
+
+use crate::{
+    constants::MESSAGE_HEADER_SIZE,
+    messages::{codec::Decode, RawMessage},
+};
+use std::{
+    io::{self, Write},
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+
+/// Errors that can occur while decoding a capture
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum CaptureError {
+    #[error("invalid capture line: {0}")]
+    InvalidLine(String),
+    #[error("invalid peer address: {0}")]
+    InvalidPeerAddress(String),
+    #[error("invalid hex string: {0}")]
+    InvalidHex(String),
+}
+
+/// Direction in which bytes travelled
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Sent,
+    Received,
+}
+
+impl Direction {
+    fn marker(&self) -> char {
+        match self {
+            Direction::Sent => '>',
+            Direction::Received => '<',
+        }
+    }
+}
+
+/// Wraps an async stream and tees every chunk read from or written to it into `log`
+pub struct TeeStream<S, W> {
+    inner: S,
+    log: W,
+}
+
+impl<S, W: Write> TeeStream<S, W> {
+    pub fn new(inner: S, log: W) -> Self {
+        Self { inner, log }
+    }
+
+    pub fn into_parts(self) -> (S, W) {
+        (self.inner, self.log)
+    }
+
+    fn record(&mut self, direction: Direction, bytes: &[u8]) -> io::Result<()> {
+        if bytes.is_empty() {
+            return Ok(());
+        }
+        let mut line = String::with_capacity(3 + 2 * bytes.len());
+        line.push(direction.marker());
+        line.push(' ');
+        for byte in bytes {
+            line.push_str(&format!("{byte:02x}"));
+        }
+        line.push('\n');
+        self.log.write_all(line.as_bytes())
+    }
+}
+
+impl<S: AsyncRead + Unpin, W: Write + Unpin> AsyncRead for TeeStream<S, W> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled_before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            self.record(Direction::Received, &buf.filled()[filled_before..])?;
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin, W: Write + Unpin> AsyncWrite for TeeStream<S, W> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.record(Direction::Sent, &buf[..written])?;
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Writes the line naming the peer that the following capture lines belong to
+pub fn write_peer_line(log: &mut impl Write, peer_address: SocketAddr) -> io::Result<()> {
+    writeln!(log, "# {peer_address}")
+}
+
+/// Message reassembled from a capture
+#[derive(Debug, Clone, PartialEq)]
+pub struct CapturedMessage {
+    /// Peer named by the closest preceding `#` line, if any
+    pub peer_address: Option<SocketAddr>,
+    pub direction: Direction,
+    pub message: RawMessage,
+}
+
+/// Reassembles the messages contained in a capture, in the order in which they were completed
+///
+/// Bytes are buffered per direction, so a message split across several chunks (or interleaved with chunks going the
+/// other way) is still decoded correctly.
+pub fn decode_capture(capture: &str) -> crate::Result<Vec<CapturedMessage>> {
+    let mut messages = Vec::new();
+    let mut peer_address = None;
+    let (mut sent, mut received) = (Vec::new(), Vec::new());
+
+    for line in capture
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+    {
+        let mut chars = line.chars();
+        let marker = chars.next();
+        let rest = chars.as_str();
+        let direction = match marker {
+            Some('#') => {
+                let address = rest.trim();
+                peer_address = Some(
+                    address
+                        .parse()
+                        .map_err(|_| CaptureError::InvalidPeerAddress(address.to_string()))?,
+                );
+                sent.clear();
+                received.clear();
+                continue;
+            }
+            Some('>') => Direction::Sent,
+            Some('<') => Direction::Received,
+            _ => Err(CaptureError::InvalidLine(line.to_string()))?,
+        };
+
+        let buffer = match direction {
+            Direction::Sent => &mut sent,
+            Direction::Received => &mut received,
+        };
+        buffer.extend(decode_hex(rest.trim())?);
+
+        while let Some(frame_len) = complete_frame_len(buffer) {
+            let message = RawMessage::decode_bytes(&buffer[..frame_len])?;
+            buffer.drain(..frame_len);
+            messages.push(CapturedMessage {
+                peer_address,
+                direction,
+                message,
+            });
+        }
+    }
+
+    Ok(messages)
+}
+
+/// Length of the first frame in `buffer` if it has been fully captured
+fn complete_frame_len(buffer: &[u8]) -> Option<usize> {
+    if buffer.len() < MESSAGE_HEADER_SIZE {
+        return None;
+    }
+    let payload_len = u32::from_le_bytes(buffer[16..20].try_into().ok()?) as usize;
+    let frame_len = MESSAGE_HEADER_SIZE + payload_len;
+    (buffer.len() >= frame_len).then_some(frame_len)
+}
+
+fn decode_hex(hex: &str) -> Result<Vec<u8>, CaptureError> {
+    let invalid = || CaptureError::InvalidHex(hex.to_string());
+    if !hex.len().is_multiple_of(2) {
+        return Err(invalid());
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| {
+            hex.get(i..i + 2)
+                .and_then(|digits| u8::from_str_radix(digits, 16).ok())
+                .ok_or_else(invalid)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{codec::Encode, types::verack::VerackMessage, Chain, Message};
+
+    #[test]
+    fn decode_capture_should_reassemble_split_frames() {
+        let frame = Message::new(Chain::Mainnet, VerackMessage)
+            .encode()
+            .unwrap();
+        let (first, second) = frame.split_at(10);
+        let capture = format!(
+            "# 1.2.3.4:8333\n> {}\n< {}\n> {}\n",
+            hex::encode(first),
+            hex::encode(&frame),
+            hex::encode(second)
+        );
+
+        let messages = decode_capture(&capture).unwrap();
+        assert_eq!(messages.len(), 2);
+        assert_eq!(messages[0].direction, Direction::Received);
+        assert_eq!(messages[1].direction, Direction::Sent);
+        assert_eq!(
+            messages[1].peer_address,
+            Some("1.2.3.4:8333".parse().unwrap())
+        );
+        assert!(messages[1].message.is::<VerackMessage>());
+    }
+
+    #[test]
+    fn decode_capture_should_reject_line_starting_with_multibyte_character() {
+        let error = decode_capture("é 00").unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<CaptureError>(),
+            Some(CaptureError::InvalidLine(line)) if line == "é 00"
+        ));
+    }
+}
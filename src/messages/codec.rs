@@ -1,11 +1,582 @@
-use std::io::Read;
+use crate::constants::{
+    MAX_ADDR_ENTRIES, MAX_FILTER_ADD_DATA_SIZE, MAX_INV_ENTRIES, MAX_USER_AGENT_LENGTH,
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, ErrorKind, Read, Write};
 
 /// Encodes a Bitcoin p2p message as bytes
 pub trait Encode {
-    fn encode(&self) -> anyhow::Result<Vec<u8>>;
+    fn encode(&self) -> crate::Result<Vec<u8>>;
+
+    /// Writes the encoded bytes to `writer`
+    ///
+    /// Types can override this to write their fields directly instead of going through a freshly allocated buffer,
+    /// which [`Encode::encode_into`] and [`Encode::encoded_len`] then benefit from too.
+    fn encode_to(&self, writer: &mut dyn Write) -> crate::Result<()> {
+        writer.write_all(&self.encode()?)?;
+        Ok(())
+    }
+
+    /// Appends the encoded bytes to `buffer`
+    fn encode_into(&self, buffer: &mut Vec<u8>) -> crate::Result<()> {
+        self.encode_to(buffer)
+    }
+
+    /// Number of bytes that [`Encode::encode`] produces, counted as they are written to [`Encode::encode_to`]
+    fn encoded_len(&self) -> crate::Result<u64> {
+        let mut writer = CountingWriter::new(io::sink());
+        self.encode_to(&mut writer)?;
+        Ok(writer.bytes_written())
+    }
+}
+
+/// Lets references be passed wherever an encodable value is expected, e.g. to generic helpers taking `impl Encode`
+impl<T: Encode + ?Sized> Encode for &T {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        T::encode(self)
+    }
+
+    fn encode_to(&self, writer: &mut dyn Write) -> crate::Result<()> {
+        T::encode_to(self, writer)
+    }
+
+    fn encode_into(&self, buffer: &mut Vec<u8>) -> crate::Result<()> {
+        T::encode_into(self, buffer)
+    }
+
+    fn encoded_len(&self) -> crate::Result<u64> {
+        T::encoded_len(self)
+    }
+}
+
+/// Wraps a writer and counts the bytes written to it
+#[derive(Debug)]
+pub struct CountingWriter<W> {
+    inner: W,
+    bytes_written: u64,
+}
+
+impl<W> CountingWriter<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            bytes_written: 0,
+        }
+    }
+
+    /// Total number of bytes written to the inner writer
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    pub fn into_inner(self) -> W {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.bytes_written += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Wraps a reader and counts the bytes read from it
+#[derive(Debug)]
+pub struct CountingReader<R> {
+    inner: R,
+    bytes_read: u64,
+}
+
+impl<R> CountingReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            bytes_read: 0,
+        }
+    }
+
+    /// Total number of bytes read from the inner reader
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+}
+
+impl<R: Read> Read for CountingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.bytes_read += read as u64;
+        Ok(read)
+    }
 }
 
 /// Decodes a bytes into a Bitoin p2p message
 pub trait Decode: Sized {
-    fn decode(bytes: &mut impl Read) -> anyhow::Result<Self>;
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self>;
+
+    /// Decodes from an in-memory buffer without having to set up a reader
+    fn decode_bytes(bytes: impl AsRef<[u8]>) -> crate::Result<Self> {
+        Self::decode(&mut bytes.as_ref()).map_err(crate::Error::into_codec_error)
+    }
+}
+
+/// Errors that can occur while encoding or decoding the fields of a message
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum CodecError {
+    #[error("too many list items: {count} (maximum is {max})")]
+    TooManyItems { count: u64, max: u64 },
+    #[error("too many addresses: {0} (maximum is {MAX_ADDR_ENTRIES})")]
+    TooManyAddresses(u64),
+    #[error("too many inventory vectors: {0} (maximum is {MAX_INV_ENTRIES})")]
+    TooManyInventoryVectors(u64),
+    #[error("unknown inventory type: {0}")]
+    UnknownInventoryType(u32),
+    #[error("unknown reject code: {0:#04x}")]
+    UnknownRejectCode(u8),
+    #[error("invalid magic value: {0:02x?}")]
+    InvalidMagic([u8; 4]),
+    #[error("filteradd data too big: {0} bytes (maximum is {MAX_FILTER_ADD_DATA_SIZE})")]
+    FilterAddDataTooBig(u64),
+    #[error("payload of empty message is not empty")]
+    NonEmptyPayload,
+    #[error("transaction indexes must be strictly increasing")]
+    IndexesNotIncreasing,
+    #[error("differentially encoded transaction index overflows")]
+    IndexOverflow,
+    #[error("invalid address length {len} for network id {network_id}")]
+    InvalidAddressLength { network_id: u8, len: u64 },
+    #[error("invalid boolean encoding: {0}")]
+    InvalidBool(u8),
+    #[error("invalid segwit flag: {0} (expected 1)")]
+    InvalidSegwitFlag(u8),
+    #[error("headers must not contain transactions")]
+    HeadersContainTransactions,
+    #[error("string is not valid UTF-8")]
+    InvalidUtf8,
+    #[error("string too long: {len} bytes (maximum is {max})")]
+    StringTooLong { len: u64, max: u64 },
+    #[error("user agent too long: {0} bytes (maximum is {MAX_USER_AGENT_LENGTH})")]
+    UserAgentTooLong(u64),
+    #[error("input ended before the message was complete")]
+    UnexpectedEof,
+    #[error("I/O error while decoding: {0}")]
+    Io(ErrorKind),
+    #[error("invalid `{name}` field: {source}")]
+    Field {
+        name: &'static str,
+        source: Box<CodecError>,
+    },
+}
+
+impl CodecError {
+    /// The error underneath any [`CodecError::Field`] context
+    pub fn root(&self) -> &CodecError {
+        match self {
+            CodecError::Field { source, .. } => source.root(),
+            error => error,
+        }
+    }
+}
+
+/// Runs `decode` and tags the codec error it fails with, if any, as raised while decoding the field `name`
+pub(crate) fn decode_field<T>(
+    name: &'static str,
+    decode: impl FnOnce() -> crate::Result<T>,
+) -> crate::Result<T> {
+    decode().map_err(|error| match error.into_codec_error() {
+        crate::Error::Codec(source) => CodecError::Field {
+            name,
+            source: Box::new(source),
+        }
+        .into(),
+        error => error,
+    })
+}
+
+impl From<io::Error> for CodecError {
+    fn from(error: io::Error) -> Self {
+        match error.kind() {
+            ErrorKind::UnexpectedEof => CodecError::UnexpectedEof,
+            kind => CodecError::Io(kind),
+        }
+    }
+}
+
+/// How bytes left over in a payload after its message was decoded are treated
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum TrailingBytesPolicy {
+    /// Ignore them, e.g. to accept fields added by newer protocol versions
+    #[default]
+    Ignore,
+    /// Fail with [`MessageDecodeError::TrailingBytes`](crate::messages::MessageDecodeError::TrailingBytes)
+    Reject,
+}
+
+/// Variable length integer used to prefix lists and strings (also known as CompactSize)
+///
+/// Source: https://developer.bitcoin.org/reference/transactions.html#compactsize-unsigned-integers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct VarInt(pub u64);
+
+impl Encode for VarInt {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(9);
+        match self.0 {
+            0..=0xfc => buffer.write_u8(self.0 as u8)?,
+            0xfd..=0xffff => {
+                buffer.write_u8(0xfd)?;
+                buffer.write_u16::<LittleEndian>(self.0 as u16)?;
+            }
+            0x10000..=0xffffffff => {
+                buffer.write_u8(0xfe)?;
+                buffer.write_u32::<LittleEndian>(self.0 as u32)?;
+            }
+            _ => {
+                buffer.write_u8(0xff)?;
+                buffer.write_u64::<LittleEndian>(self.0)?;
+            }
+        }
+        Ok(buffer)
+    }
+}
+
+impl Decode for VarInt {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let value = match bytes.read_u8()? {
+            0xfd => bytes.read_u16::<LittleEndian>()? as u64,
+            0xfe => bytes.read_u32::<LittleEndian>()? as u64,
+            0xff => bytes.read_u64::<LittleEndian>()?,
+            value => value as u64,
+        };
+        Ok(VarInt(value))
+    }
+}
+
+/// Hash of a block or transaction, held in internal byte order, i.e. the order it is sent in over the wire
+///
+/// Block explorers and RPCs display hashes with their bytes reversed, which [`Hash256::to_hex_be`] and
+/// [`Hash256::from_hex_be`] take care of.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Hash256(pub [u8; 32]);
+
+/// Error returned when parsing a [`Hash256`] from anything but 64 hexadecimal digits
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[error("cannot convert {0:?} to a 32-byte hash")]
+pub struct ParseHashError(String);
+
+impl Hash256 {
+    /// Hexadecimal form of the hash as usually displayed, i.e. with its bytes reversed
+    pub fn to_hex_be(&self) -> String {
+        self.0
+            .iter()
+            .rev()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    /// Parses the hash from its usual, reversed hexadecimal form, see [`Hash256::to_hex_be`]
+    pub fn from_hex_be(hex: &str) -> Result<Self, ParseHashError> {
+        let error = || ParseHashError(hex.to_string());
+        if hex.len() != 64 || !hex.is_ascii() {
+            return Err(error());
+        }
+        let mut bytes = [0u8; 32];
+        for (byte, digits) in bytes.iter_mut().rev().zip(hex.as_bytes().chunks(2)) {
+            let digits = std::str::from_utf8(digits).map_err(|_| error())?;
+            *byte = u8::from_str_radix(digits, 16).map_err(|_| error())?;
+        }
+        Ok(Self(bytes))
+    }
+}
+
+impl std::fmt::Display for Hash256 {
+    /// Formats the hash as [`Hash256::to_hex_be`] does
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_hex_be())
+    }
+}
+
+impl From<[u8; 32]> for Hash256 {
+    fn from(bytes: [u8; 32]) -> Self {
+        Self(bytes)
+    }
+}
+
+impl Encode for Hash256 {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        Ok(self.0.to_vec())
+    }
+}
+
+impl Decode for Hash256 {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let mut hash = [0u8; 32];
+        bytes.read_exact(&mut hash)?;
+        Ok(Self(hash))
+    }
+}
+
+/// Upper bound on the number of list items space is reserved for before any of them has been read
+const MAX_PREALLOCATED_ITEMS: u64 = 1024;
+
+/// Reads exactly `len` bytes, growing the buffer as bytes arrive rather than allocating `len` bytes up front
+///
+/// A length taken from untrusted input can't make us allocate more memory than the input actually contains.
+pub(crate) fn read_exact_len(reader: &mut impl Read, len: u64) -> crate::Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    reader.take(len).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < len {
+        Err(CodecError::UnexpectedEof)?
+    }
+    Ok(bytes)
+}
+
+/// Reads a CompactSize-prefixed UTF-8 string, checking its length against `max_len` before reading it
+pub(crate) fn read_var_string(reader: &mut impl Read, max_len: u64) -> crate::Result<String> {
+    let VarInt(len) = VarInt::decode(reader)?;
+    if len > max_len {
+        Err(CodecError::StringTooLong { len, max: max_len })?
+    }
+    Ok(String::from_utf8(read_exact_len(reader, len)?).map_err(|_| CodecError::InvalidUtf8)?)
+}
+
+/// Reads a CompactSize-prefixed list, decoding its items one at a time with `decode_item`
+///
+/// The count is checked against `max_items` before anything is allocated, so a peer cannot make us reserve memory for
+/// a list it never sends.
+pub fn read_vec_with_cap<T, R: Read>(
+    reader: &mut R,
+    max_items: u64,
+    decode_item: impl FnMut(&mut R) -> crate::Result<T>,
+) -> crate::Result<Vec<T>> {
+    read_vec_with_cap_or(reader, max_items, too_many_items, decode_item)
+}
+
+/// Same as [`read_vec_with_cap`], but fails with the error `too_many(count, max_items)` if the count is over the cap
+pub fn read_vec_with_cap_or<T, R: Read>(
+    reader: &mut R,
+    max_items: u64,
+    too_many: fn(u64, u64) -> CodecError,
+    mut decode_item: impl FnMut(&mut R) -> crate::Result<T>,
+) -> crate::Result<Vec<T>> {
+    let VarInt(count) = VarInt::decode(reader)?;
+    if count > max_items {
+        Err(too_many(count, max_items))?
+    }
+
+    // Caps derived from the input (e.g. a transaction count) can be huge, so only a bounded amount is reserved
+    let mut items = Vec::with_capacity(count.min(MAX_PREALLOCATED_ITEMS) as usize);
+    for _ in 0..count {
+        items.push(decode_item(reader)?);
+    }
+    Ok(items)
+}
+
+fn too_many_items(count: u64, max: u64) -> CodecError {
+    CodecError::TooManyItems { count, max }
+}
+
+/// Lazily decodes a CompactSize-prefixed list, see [`read_items_with_cap`]
+pub struct ItemDecoder<R, F> {
+    reader: R,
+    max_items: u64,
+    too_many: fn(u64, u64) -> CodecError,
+    decode_item: F,
+    /// Number of items left to decode, `None` until the count was read
+    remaining: Option<u64>,
+    failed: bool,
+}
+
+impl<T, R: Read, F: FnMut(&mut R) -> crate::Result<T>> ItemDecoder<R, F> {
+    fn decode_next(&mut self) -> crate::Result<Option<T>> {
+        let remaining = match self.remaining {
+            Some(remaining) => remaining,
+            None => {
+                let VarInt(count) = VarInt::decode(&mut self.reader)?;
+                if count > self.max_items {
+                    Err((self.too_many)(count, self.max_items))?
+                }
+                count
+            }
+        };
+        if remaining == 0 {
+            self.remaining = Some(0);
+            return Ok(None);
+        }
+        self.remaining = Some(remaining - 1);
+        (self.decode_item)(&mut self.reader).map(Some)
+    }
+}
+
+impl<T, R: Read, F: FnMut(&mut R) -> crate::Result<T>> Iterator for ItemDecoder<R, F> {
+    type Item = crate::Result<T>;
+
+    /// Yields the next item, or the error that stopped decoding after which nothing else is yielded
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        self.decode_next()
+            .map_err(|error| {
+                self.failed = true;
+                error.into_codec_error()
+            })
+            .transpose()
+    }
+}
+
+/// Same as [`read_vec_with_cap`], but yields the items one at a time as they are decoded instead of collecting them
+///
+/// The count is only read (and checked against `max_items`) on the first call to `next`, so an oversized count surfaces
+/// as the first item.
+pub fn read_items_with_cap<T, R: Read, F: FnMut(&mut R) -> crate::Result<T>>(
+    reader: R,
+    max_items: u64,
+    decode_item: F,
+) -> ItemDecoder<R, F> {
+    read_items_with_cap_or(reader, max_items, too_many_items, decode_item)
+}
+
+/// Same as [`read_items_with_cap`], but fails with the error `too_many(count, max_items)` if the count is over the cap
+pub fn read_items_with_cap_or<T, R: Read, F: FnMut(&mut R) -> crate::Result<T>>(
+    reader: R,
+    max_items: u64,
+    too_many: fn(u64, u64) -> CodecError,
+    decode_item: F,
+) -> ItemDecoder<R, F> {
+    ItemDecoder {
+        reader,
+        max_items,
+        too_many,
+        decode_item,
+        remaining: None,
+        failed: false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::types::ping::PingMessage;
+
+    #[test]
+    fn hash256_should_display_wire_bytes_reversed() {
+        // Hash of the genesis block as displayed by block explorers
+        let displayed = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+        let hash = Hash256::from_hex_be(displayed).unwrap();
+
+        let wire_bytes = hash.encode().unwrap();
+        assert_eq!(
+            hex::encode(&wire_bytes),
+            "6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000"
+        );
+        assert_eq!(Hash256::decode_bytes(&wire_bytes).unwrap(), hash);
+        assert_eq!(hash.to_hex_be(), displayed);
+        assert_eq!(hash.to_string(), displayed);
+
+        for invalid in ["00", &"zz".repeat(32), &"é".repeat(32)] {
+            assert_eq!(
+                Hash256::from_hex_be(invalid),
+                Err(ParseHashError(invalid.to_string()))
+            );
+        }
+    }
+
+    #[test]
+    fn counting_writer_and_reader_should_count_bytes() {
+        let mut writer = CountingWriter::new(Vec::new());
+        writer.write_all(b"version").unwrap();
+        writer.write_all(&[0; 5]).unwrap();
+        assert_eq!(writer.bytes_written(), 12);
+        assert_eq!(writer.into_inner().len(), 12);
+
+        let bytes = [1u8; 10];
+        let mut reader = CountingReader::new(&bytes[..]);
+        let mut buffer = [0u8; 4];
+        reader.read_exact(&mut buffer).unwrap();
+        assert_eq!(reader.bytes_read(), 4);
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(reader.bytes_read(), 10);
+
+        assert_eq!(PingMessage { nonce: 1 }.encoded_len().unwrap(), 8);
+    }
+
+    #[test]
+    fn decode_bytes_should_report_truncated_input_as_unexpected_eof() {
+        let error = VarInt::decode_bytes([0xfe, 0x01]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::UnexpectedEof)
+        );
+    }
+
+    #[test]
+    fn var_int_encode_should_work() {
+        assert_eq!(VarInt(0xfc).encode().unwrap(), vec![0xfc]);
+        assert_eq!(VarInt(0xfd).encode().unwrap(), vec![0xfd, 0xfd, 0x00]);
+        assert_eq!(
+            VarInt(0x10000).encode().unwrap(),
+            vec![0xfe, 0x00, 0x00, 0x01, 0x00]
+        );
+        assert_eq!(
+            VarInt(0x100000000).encode().unwrap(),
+            vec![0xff, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00]
+        );
+    }
+
+    #[test]
+    fn var_int_decode_should_work() {
+        for value in [
+            0,
+            0xfc,
+            0xfd,
+            0xffff,
+            0x10000,
+            0xffffffff,
+            0x100000000,
+            u64::MAX,
+        ] {
+            let encoded = VarInt(value).encode().unwrap();
+            assert_eq!(VarInt::decode_bytes(encoded).unwrap(), VarInt(value));
+        }
+    }
+
+    #[test]
+    fn decode_bytes_should_accept_owned_buffer() {
+        let encoded: Vec<u8> = vec![0xfd, 0x00, 0x01];
+        assert_eq!(VarInt::decode_bytes(encoded).unwrap(), VarInt(0x100));
+    }
+
+    #[test]
+    fn read_vec_with_cap_should_accept_count_at_cap() {
+        let bytes = [3, 1, 2, 3];
+        let items = read_vec_with_cap(&mut bytes.as_slice(), 3, |reader| {
+            Ok(VarInt::decode(reader)?.0)
+        })
+        .unwrap();
+        assert_eq!(items, vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn read_vec_with_cap_should_reject_count_over_cap() {
+        // Only the count is provided, so the error must be raised before any item is read
+        let bytes = [4];
+        let error = read_vec_with_cap(&mut bytes.as_slice(), 3, |reader| {
+            Ok(VarInt::decode(reader)?.0)
+        })
+        .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::TooManyItems { count: 4, max: 3 })
+        );
+    }
 }
@@ -0,0 +1,1060 @@
+//! Module contains the functionality for reading messages from an async stream
+
+use crate::{
+    constants::{MAX_PAYLOAD_SIZE, MESSAGE_HEADER_SIZE},
+    messages::{
+        codec::{CodecError, Decode, Encode, TrailingBytesPolicy},
+        verify_payload_checksum, CommandName, Message, MessageDecodeError, MessageHeader,
+        RawMessage,
+    },
+};
+use std::{
+    future::Future,
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader, ReadBuf},
+    time::{sleep, Sleep},
+};
+
+/// Default capacity (in bytes) of the read buffer of a [`MessageStream`]
+pub const DEFAULT_READ_BUFFER_SIZE: usize = 8 * 1024;
+
+/// Reads a single message of type `M` from `reader`, ignoring any bytes its decoder leaves in the payload
+///
+/// The 24-byte header is read first and its length field is checked against `max_payload_size`, so that the payload
+/// buffer is only allocated once the declared length is known to be acceptable.
+pub async fn read_framed_message<M, R>(
+    reader: &mut R,
+    max_payload_size: u32,
+) -> crate::Result<Message<M>>
+where
+    M: CommandName + Encode + Decode,
+    R: AsyncRead + Unpin,
+{
+    read_framed_message_with_policy(reader, max_payload_size, TrailingBytesPolicy::Ignore).await
+}
+
+/// Reads a single message of type `M` from `reader`, treating bytes left in the payload according to `trailing_bytes`
+///
+/// Exactly the declared number of payload bytes is consumed and the message is decoded from those alone, so a decoder
+/// that stops early or reads too far can't desynchronise the stream.
+pub async fn read_framed_message_with_policy<M, R>(
+    reader: &mut R,
+    max_payload_size: u32,
+    trailing_bytes: TrailingBytesPolicy,
+) -> crate::Result<Message<M>>
+where
+    M: CommandName + Encode + Decode,
+    R: AsyncRead + Unpin,
+{
+    read_budgeted_message(
+        reader,
+        &mut ReadBudget::default(),
+        max_payload_size,
+        trailing_bytes,
+        true,
+    )
+    .await
+}
+
+/// Reads a single message of any command from `reader`, checking the declared length before allocating the payload
+pub async fn read_raw_message<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    max_payload_size: u32,
+) -> crate::Result<RawMessage> {
+    read_budgeted_raw_message(reader, &mut ReadBudget::default(), max_payload_size, true).await
+}
+
+/// Errors raised once a [`MessageStream`] has read as much as it was allowed to
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum StreamLimitError {
+    #[error("limit of {0} messages reached")]
+    MessageLimitReached(u64),
+    #[error("limit of {0} bytes reached")]
+    ByteLimitReached(u64),
+}
+
+/// Counters of what a [`MessageStream`] has read so far
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct StreamStats {
+    /// Messages read successfully
+    pub messages_ok: u64,
+    /// Frames whose payload did not match the checksum in their header
+    pub checksum_errors: u64,
+    /// Headers that failed to decode or were rejected, e.g. for an unknown magic value or an oversized payload
+    pub header_errors: u64,
+    /// Corrupted frames or headers dropped so that reading could carry on with the next frame
+    pub resyncs: u64,
+    /// Bytes of all frames read, including dropped ones and the bytes skipped while scanning for the next header
+    pub bytes_read: u64,
+}
+
+/// Limits on how much may be read from a stream, along with how much has been read so far
+#[derive(Debug, Default, Clone, Copy)]
+struct ReadBudget {
+    max_messages: Option<u64>,
+    max_bytes: Option<u64>,
+    resync: bool,
+    messages_read: u64,
+    bytes_read: u64,
+    messages_ok: u64,
+    checksum_errors: u64,
+    header_errors: u64,
+    resyncs: u64,
+}
+
+impl ReadBudget {
+    /// Fails if no further message may be read
+    fn check_message(&self) -> Result<(), StreamLimitError> {
+        match self.max_messages {
+            Some(max_messages) if self.messages_read >= max_messages => {
+                Err(StreamLimitError::MessageLimitReached(max_messages))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Accounts for the frame announced by `header`, failing before its payload is read if it exceeds the byte budget
+    fn consume(&mut self, header: &MessageHeader) -> Result<(), StreamLimitError> {
+        let frame_len = MESSAGE_HEADER_SIZE as u64 + header.payload_len as u64;
+        if let Some(max_bytes) = self.max_bytes {
+            if self.bytes_read + frame_len > max_bytes {
+                return Err(StreamLimitError::ByteLimitReached(max_bytes));
+            }
+        }
+        self.messages_read += 1;
+        self.bytes_read += frame_len;
+        Ok(())
+    }
+
+    /// Accounts for a frame whose checksum did not match, returning whether it is dropped to read the next one instead
+    fn checksum_failed(&mut self) -> bool {
+        self.checksum_errors += 1;
+        if self.resync {
+            self.resyncs += 1;
+        }
+        self.resync
+    }
+
+    /// Accounts for a header that was rejected, returning whether the stream is scanned for the next one instead
+    fn header_failed(&mut self) -> bool {
+        self.header_errors += 1;
+        if self.resync {
+            self.resyncs += 1;
+        }
+        self.resync
+    }
+
+    /// Accounts for a byte skipped while scanning for the next header, failing if it exceeds the byte budget
+    fn skip_byte(&mut self) -> Result<(), StreamLimitError> {
+        if let Some(max_bytes) = self.max_bytes {
+            if self.bytes_read >= max_bytes {
+                return Err(StreamLimitError::ByteLimitReached(max_bytes));
+            }
+        }
+        self.bytes_read += 1;
+        Ok(())
+    }
+
+    fn stats(&self) -> StreamStats {
+        StreamStats {
+            messages_ok: self.messages_ok,
+            checksum_errors: self.checksum_errors,
+            header_errors: self.header_errors,
+            resyncs: self.resyncs,
+            bytes_read: self.bytes_read,
+        }
+    }
+}
+
+/// Reads the next header that starts with a known magic value and declares at most `max_payload_size` bytes of payload
+///
+/// Any other header fails the read unless the budget resyncs, in which case the stream is scanned forward one byte at a
+/// time until the next 24 bytes decode into an acceptable header. The command is not checked here, so a well-formed
+/// frame of another command is never scanned through.
+async fn read_header<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    budget: &mut ReadBudget,
+    max_payload_size: u32,
+) -> crate::Result<MessageHeader> {
+    let mut encoded_header = [0u8; MESSAGE_HEADER_SIZE];
+    reader
+        .read_exact(&mut encoded_header)
+        .await
+        .map_err(CodecError::from)?;
+    let mut scanning = false;
+    loop {
+        let error = match MessageHeader::decode_bytes(encoded_header) {
+            Ok(header) if header.payload_len <= max_payload_size => return Ok(header),
+            Ok(_) => MessageDecodeError::PayloadTooBig.into(),
+            Err(error) => error,
+        };
+        if !scanning && !budget.header_failed() {
+            return Err(error);
+        }
+        scanning = true;
+        budget.skip_byte()?;
+        encoded_header.copy_within(1.., 0);
+        reader
+            .read_exact(&mut encoded_header[MESSAGE_HEADER_SIZE - 1..])
+            .await
+            .map_err(CodecError::from)?;
+    }
+}
+
+async fn read_budgeted_message<M, R>(
+    reader: &mut R,
+    budget: &mut ReadBudget,
+    max_payload_size: u32,
+    trailing_bytes: TrailingBytesPolicy,
+    verify_checksum: bool,
+) -> crate::Result<Message<M>>
+where
+    M: CommandName + Encode + Decode,
+    R: AsyncRead + Unpin,
+{
+    loop {
+        budget.check_message()?;
+        let header = read_header(reader, budget, max_payload_size).await?;
+        budget.consume(&header)?;
+
+        let encoded_message = read_payload(reader, header.payload_len).await?;
+        if verify_checksum {
+            if let Err(error) = verify_payload_checksum(&header, &encoded_message) {
+                if budget.checksum_failed() {
+                    continue;
+                }
+                Err(error)?
+            }
+        }
+        // The whole frame was consumed, so reading carries on with the next one after this error
+        Message::<M>::validate_header(&header, max_payload_size)?;
+
+        let message =
+            Message::<M>::decode_payload(&header, &encoded_message, trailing_bytes, false)?;
+        budget.messages_ok += 1;
+        return Ok(message);
+    }
+}
+
+async fn read_budgeted_raw_message<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    budget: &mut ReadBudget,
+    max_payload_size: u32,
+    verify_checksum: bool,
+) -> crate::Result<RawMessage> {
+    loop {
+        budget.check_message()?;
+        let header = read_header(reader, budget, max_payload_size).await?;
+        budget.consume(&header)?;
+
+        let payload = read_payload(reader, header.payload_len).await?;
+
+        let raw_message = RawMessage::new(header, payload);
+        if verify_checksum {
+            if let Err(error) = raw_message.verify_checksum() {
+                if budget.checksum_failed() {
+                    continue;
+                }
+                return Err(error);
+            }
+        }
+        budget.messages_ok += 1;
+        return Ok(raw_message.with_checksum_verified());
+    }
+}
+
+/// Reads exactly `payload_len` bytes, growing the buffer as they arrive
+async fn read_payload<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    payload_len: u32,
+) -> crate::Result<Vec<u8>> {
+    let mut payload = Vec::new();
+    reader
+        .take(payload_len as u64)
+        .read_to_end(&mut payload)
+        .await
+        .map_err(CodecError::from)?;
+    if payload.len() < payload_len as usize {
+        Err(CodecError::UnexpectedEof)?
+    }
+    Ok(payload)
+}
+
+/// Connection over which messages are read through a read buffer and written directly to the inner stream
+pub struct MessageStream<S> {
+    reader: BufReader<S>,
+    /// Capacity of `reader`, which tokio's `BufReader` does not expose
+    capacity: usize,
+    /// Scratch buffer that every written message is encoded into, so that writing doesn't allocate once it has grown
+    write_buffer: Vec<u8>,
+    max_payload_size: u32,
+    trailing_bytes: TrailingBytesPolicy,
+    verify_checksums: bool,
+    budget: ReadBudget,
+}
+
+impl<S: AsyncRead + Unpin> MessageStream<S> {
+    /// Creates a message stream with a read buffer of [`DEFAULT_READ_BUFFER_SIZE`] bytes
+    pub fn new(inner: S) -> Self {
+        Self::with_capacity(DEFAULT_READ_BUFFER_SIZE, inner)
+    }
+
+    /// Creates a message stream with a read buffer of `capacity` bytes
+    ///
+    /// A small buffer saves memory when many connections exchange tiny messages, while a large buffer reduces the
+    /// number of reads needed for big payloads.
+    pub fn with_capacity(capacity: usize, inner: S) -> Self {
+        Self {
+            reader: BufReader::with_capacity(capacity, inner),
+            capacity,
+            write_buffer: Vec::new(),
+            max_payload_size: MAX_PAYLOAD_SIZE,
+            trailing_bytes: TrailingBytesPolicy::default(),
+            verify_checksums: true,
+            budget: ReadBudget::default(),
+        }
+    }
+
+    /// Sets the maximum payload size accepted when reading messages
+    pub fn with_max_payload_size(mut self, max_payload_size: u32) -> Self {
+        self.max_payload_size = max_payload_size;
+        self
+    }
+
+    /// Sets how bytes left in a payload after decoding its message are treated when reading messages
+    pub fn with_trailing_bytes_policy(mut self, trailing_bytes: TrailingBytesPolicy) -> Self {
+        self.trailing_bytes = trailing_bytes;
+        self
+    }
+
+    /// Sets whether the checksum of every message read is verified (the default)
+    ///
+    /// Skipping the double SHA-256 of every payload saves CPU on trusted links, e.g. to a local node over loopback, where
+    /// corruption is not a concern. The checksum bytes are still read.
+    pub fn with_checksum_verification(mut self, verify_checksums: bool) -> Self {
+        self.verify_checksums = verify_checksums;
+        self
+    }
+
+    /// Stops reading with [`StreamLimitError::MessageLimitReached`] once `max_messages` messages were read
+    pub fn with_max_messages(mut self, max_messages: u64) -> Self {
+        self.budget.max_messages = Some(max_messages);
+        self
+    }
+
+    /// Stops reading with [`StreamLimitError::ByteLimitReached`] rather than exceed `max_bytes` bytes read in total
+    pub fn with_max_bytes(mut self, max_bytes: u64) -> Self {
+        self.budget.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Drops corrupted frames and reads the next one instead of failing (off by default)
+    ///
+    /// A frame whose checksum does not match has been consumed whole, so reading carries on right after it. A header
+    /// with an unknown magic value or an oversized payload gives no length to skip, so the stream is scanned forward to
+    /// the next acceptable header. Either way a noisy connection loses only the corrupted messages. A well-formed frame
+    /// of another command than the one read is not corrupted: it is consumed whole and fails the read as before.
+    pub fn with_resync(mut self, resync: bool) -> Self {
+        self.budget.resync = resync;
+        self
+    }
+
+    /// Counters of the messages and bytes read so far
+    pub fn stats(&self) -> StreamStats {
+        self.budget.stats()
+    }
+
+    /// Capacity (in bytes) of the read buffer
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Number of bytes that have been read from the inner stream but not yet consumed
+    pub fn buffered_len(&self) -> usize {
+        self.reader.buffer().len()
+    }
+
+    pub fn get_ref(&self) -> &S {
+        self.reader.get_ref()
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        self.reader.get_mut()
+    }
+
+    pub fn into_inner(self) -> S {
+        self.reader.into_inner()
+    }
+
+    /// Reads the next message, which must be of type `M`
+    pub async fn read_message<M: CommandName + Encode + Decode>(
+        &mut self,
+    ) -> crate::Result<Message<M>> {
+        read_budgeted_message(
+            &mut self.reader,
+            &mut self.budget,
+            self.max_payload_size,
+            self.trailing_bytes,
+            self.verify_checksums,
+        )
+        .await
+    }
+
+    /// Reads the next message regardless of its command
+    pub async fn read_raw_message(&mut self) -> crate::Result<RawMessage> {
+        read_budgeted_raw_message(
+            &mut self.reader,
+            &mut self.budget,
+            self.max_payload_size,
+            self.verify_checksums,
+        )
+        .await
+    }
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> MessageStream<S> {
+    /// Encodes `message`, writes it to the inner stream and flushes it, so that it doesn't linger in a write buffer
+    pub async fn write_message(&mut self, message: &impl Encode) -> crate::Result<()> {
+        self.write_buffer.clear();
+        message.encode_into(&mut self.write_buffer)?;
+        let writer = self.reader.get_mut();
+        writer.write_all(&self.write_buffer).await?;
+        writer.flush().await?;
+        Ok(())
+    }
+
+    /// Writes (and flushes) `message` while concurrently reading the next message, which must be of type `M`
+    ///
+    /// Neither side waits for the other, so a peer that only reads once it has written (or whose receive window is
+    /// smaller than our message) can't stall the exchange.
+    pub async fn write_and_read_message<M: CommandName + Encode + Decode>(
+        &mut self,
+        message: &impl Encode,
+    ) -> crate::Result<Message<M>> {
+        let raw_message = self.write_and_read_raw_message(message).await?;
+        Message::<M>::validate_header(&raw_message.header, self.max_payload_size)?;
+        // The checksum was already verified (if enabled) when the raw message was read
+        Message::<M>::decode_payload(
+            &raw_message.header,
+            &raw_message.payload,
+            self.trailing_bytes,
+            false,
+        )
+    }
+
+    /// Same as [`MessageStream::write_and_read_message`], but reads the next message regardless of its command
+    pub async fn write_and_read_raw_message(
+        &mut self,
+        message: &impl Encode,
+    ) -> crate::Result<RawMessage> {
+        self.write_and_read_raw_message_with(message, || ()).await
+    }
+
+    /// Same as [`MessageStream::write_and_read_raw_message`], but calls `on_written` as soon as `message` was written
+    /// and flushed, even while the next message is still being read
+    pub async fn write_and_read_raw_message_with(
+        &mut self,
+        message: &impl Encode,
+        on_written: impl FnOnce(),
+    ) -> crate::Result<RawMessage> {
+        self.write_buffer.clear();
+        message.encode_into(&mut self.write_buffer)?;
+        let encoded_message = &self.write_buffer;
+        if self.buffered_len() > 0 {
+            // The reply may already be (partly) buffered, so it has to be read through the buffer
+            let writer = self.reader.get_mut();
+            writer.write_all(encoded_message).await?;
+            writer.flush().await?;
+            on_written();
+            return self.read_raw_message().await;
+        }
+
+        // Nothing is buffered, so reading exactly one frame straight from the inner stream loses no bytes
+        let (max_payload_size, verify_checksums) = (self.max_payload_size, self.verify_checksums);
+        let (mut reader, mut writer) = tokio::io::split(self.reader.get_mut());
+        let (_, received_message) = tokio::try_join!(
+            async {
+                writer.write_all(encoded_message).await?;
+                writer.flush().await?;
+                on_written();
+                crate::Result::Ok(())
+            },
+            read_budgeted_raw_message(
+                &mut reader,
+                &mut self.budget,
+                max_payload_size,
+                verify_checksums
+            ),
+        )?;
+        Ok(received_message)
+    }
+}
+
+/// Wraps an async stream and counts the bytes read from and written to it
+pub struct CountingStream<S> {
+    inner: S,
+    bytes_read: u64,
+    bytes_written: u64,
+}
+
+impl<S> CountingStream<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            bytes_read: 0,
+            bytes_written: 0,
+        }
+    }
+
+    /// Total number of bytes read from the inner stream
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// Total number of bytes written to the inner stream
+    pub fn bytes_written(&self) -> u64 {
+        self.bytes_written
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for CountingStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let filled_before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = poll {
+            self.bytes_read += (buf.filled().len() - filled_before) as u64;
+        }
+        poll
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for CountingStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let poll = Pin::new(&mut self.inner).poll_write(cx, buf);
+        if let Poll::Ready(Ok(written)) = poll {
+            self.bytes_written += written as u64;
+        }
+        poll
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+/// Wraps an async stream and fails any read or write that makes no progress for `timeout`
+///
+/// Every read and write gets its own deadline, so a peer that trickles a few bytes and then stalls is detected long
+/// before a timeout around a whole exchange would fire. Without a timeout the inner stream is used as is.
+pub struct TimeoutStream<S> {
+    inner: S,
+    timeout: Option<Duration>,
+    read_deadline: Option<Pin<Box<Sleep>>>,
+    write_deadline: Option<Pin<Box<Sleep>>>,
+}
+
+impl<S> TimeoutStream<S> {
+    pub fn new(inner: S, timeout: Option<Duration>) -> Self {
+        Self {
+            inner,
+            timeout,
+            read_deadline: None,
+            write_deadline: None,
+        }
+    }
+
+    pub fn into_inner(self) -> S {
+        self.inner
+    }
+}
+
+/// Passes on `poll` of an operation, failing it with [`io::ErrorKind::TimedOut`] once it was pending for `timeout`
+fn poll_with_deadline<T>(
+    poll: Poll<io::Result<T>>,
+    deadline: &mut Option<Pin<Box<Sleep>>>,
+    timeout: Option<Duration>,
+    cx: &mut Context<'_>,
+) -> Poll<io::Result<T>> {
+    let Some(timeout) = timeout else {
+        return poll;
+    };
+    if poll.is_ready() {
+        *deadline = None;
+        return poll;
+    }
+    let expired = deadline
+        .get_or_insert_with(|| Box::pin(sleep(timeout)))
+        .as_mut()
+        .poll(cx)
+        .is_ready();
+    if expired {
+        *deadline = None;
+        return Poll::Ready(Err(io::Error::new(
+            io::ErrorKind::TimedOut,
+            format!("No progress on the stream for {timeout:?}"),
+        )));
+    }
+    Poll::Pending
+}
+
+impl<S: AsyncRead + Unpin> AsyncRead for TimeoutStream<S> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let poll = Pin::new(&mut this.inner).poll_read(cx, buf);
+        poll_with_deadline(poll, &mut this.read_deadline, this.timeout, cx)
+    }
+}
+
+impl<S: AsyncWrite + Unpin> AsyncWrite for TimeoutStream<S> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        let this = &mut *self;
+        let poll = Pin::new(&mut this.inner).poll_write(cx, buf);
+        poll_with_deadline(poll, &mut this.write_deadline, this.timeout, cx)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        let this = &mut *self;
+        let poll = Pin::new(&mut this.inner).poll_flush(cx);
+        poll_with_deadline(poll, &mut this.write_deadline, this.timeout, cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{
+        types::{ping::PingMessage, verack::VerackMessage, version::VersionMessage},
+        Chain,
+    };
+
+    #[tokio::test]
+    async fn read_framed_message_should_work() {
+        let (mut client, mut peer) = tokio::io::duplex(64);
+        let message = Message::new(Chain::Mainnet, VerackMessage);
+        peer.write_all(&message.encode().unwrap()).await.unwrap();
+
+        let received_message =
+            read_framed_message::<VerackMessage, _>(&mut client, MAX_PAYLOAD_SIZE)
+                .await
+                .unwrap();
+        assert_eq!(received_message.chain, Chain::Mainnet);
+        assert_eq!(received_message.message, VerackMessage);
+    }
+
+    #[tokio::test]
+    async fn read_framed_message_should_reject_oversized_payload_before_reading_it() {
+        let (mut client, mut peer) = tokio::io::duplex(64);
+        let header = MessageHeader {
+            chain: Chain::Mainnet,
+            command_name: VerackMessage::command_name(),
+            payload_len: MAX_PAYLOAD_SIZE + 1,
+            checksum: [0u8; 4],
+        };
+        // Only the header is sent, so reaching the payload read would block forever
+        peer.write_all(&header.encode().unwrap()).await.unwrap();
+
+        let error = read_framed_message::<VerackMessage, _>(&mut client, MAX_PAYLOAD_SIZE)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<MessageDecodeError>(),
+            Some(&MessageDecodeError::PayloadTooBig)
+        );
+    }
+
+    #[tokio::test]
+    async fn read_framed_message_should_honor_configured_max_payload_size() {
+        let (mut client, mut peer) = tokio::io::duplex(64);
+        let header = MessageHeader {
+            chain: Chain::Mainnet,
+            command_name: VerackMessage::command_name(),
+            payload_len: 17,
+            checksum: [0u8; 4],
+        };
+        peer.write_all(&header.encode().unwrap()).await.unwrap();
+
+        let error = read_framed_message::<VerackMessage, _>(&mut client, 16)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<MessageDecodeError>(),
+            Some(&MessageDecodeError::PayloadTooBig)
+        );
+    }
+
+    #[tokio::test]
+    async fn read_framed_message_should_report_truncated_header_as_unexpected_eof() {
+        let (mut client, mut peer) = tokio::io::duplex(64);
+        let message = Message::new(Chain::Mainnet, VerackMessage);
+        peer.write_all(&message.encode().unwrap()[..10])
+            .await
+            .unwrap();
+        drop(peer);
+
+        let error = read_framed_message::<VerackMessage, _>(&mut client, MAX_PAYLOAD_SIZE)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::UnexpectedEof)
+        );
+    }
+
+    #[tokio::test]
+    async fn read_raw_message_should_report_truncated_payload_as_unexpected_eof() {
+        let (mut client, mut peer) = tokio::io::duplex(256);
+        let header = MessageHeader {
+            chain: Chain::Mainnet,
+            command_name: *b"unknown\x00\x00\x00\x00\x00",
+            payload_len: 100,
+            checksum: [0u8; 4],
+        };
+        peer.write_all(&header.encode().unwrap()).await.unwrap();
+        peer.write_all(&[1u8; 50]).await.unwrap();
+        drop(peer);
+
+        let error = read_raw_message(&mut client, MAX_PAYLOAD_SIZE)
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::UnexpectedEof)
+        );
+    }
+
+    #[tokio::test]
+    async fn read_framed_message_should_apply_trailing_bytes_policy() {
+        // Hexdump example of version message taken from https://developer.bitcoin.org/reference/p2p_networking.html#version
+        let mut payload = hex::decode("721101000100000000000000bc8f5e5400000000010000000000000000000000000000000000ffffc61b6409208d010000000000000000000000000000000000ffffcb0071c0208d128035cbc97953f80f2f5361746f7368693a302e392e332fcf05050001").unwrap();
+        payload.extend([0xaa, 0xbb, 0xcc]);
+        let frame = RawMessage::new(
+            MessageHeader {
+                chain: Chain::Mainnet,
+                command_name: VersionMessage::command_name(),
+                payload_len: payload.len() as u32,
+                checksum: crate::crypto::checksum(&payload),
+            },
+            payload,
+        )
+        .encode()
+        .unwrap();
+        let verack = Message::new(Chain::Mainnet, VerackMessage)
+            .encode()
+            .unwrap();
+
+        let (client, mut peer) = tokio::io::duplex(1024);
+        for _ in 0..2 {
+            peer.write_all(&frame).await.unwrap();
+            peer.write_all(&verack).await.unwrap();
+        }
+        let mut message_stream = MessageStream::new(client);
+
+        // Tolerant: the extra bytes are skipped and the following frame is read intact
+        let version = message_stream
+            .read_message::<VersionMessage>()
+            .await
+            .unwrap();
+        assert_eq!(version.message.user_agent, "/Satoshi:0.9.3/");
+        message_stream
+            .read_message::<VerackMessage>()
+            .await
+            .unwrap();
+
+        let mut message_stream =
+            message_stream.with_trailing_bytes_policy(TrailingBytesPolicy::Reject);
+        let error = message_stream
+            .read_message::<VersionMessage>()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<MessageDecodeError>(),
+            Some(&MessageDecodeError::TrailingBytes(3))
+        );
+        // The whole payload was consumed regardless, so the stream is still aligned on frame boundaries
+        message_stream
+            .read_message::<VerackMessage>()
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn message_stream_should_skip_checksums_when_verification_is_disabled() {
+        let mut frame = Message::new(Chain::Mainnet, PingMessage { nonce: 7 })
+            .encode()
+            .unwrap();
+        // A checksum that no payload hashes to, so decoding only succeeds if the payload is never hashed
+        frame[20..MESSAGE_HEADER_SIZE].copy_from_slice(&[0; 4]);
+
+        let (client, mut peer) = tokio::io::duplex(1024);
+        peer.write_all(&frame).await.unwrap();
+        let error = MessageStream::new(client)
+            .read_raw_message()
+            .await
+            .unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MessageDecodeError>(),
+            Some(MessageDecodeError::CheksumIsInvalid { .. })
+        ));
+
+        let (client, mut peer) = tokio::io::duplex(1024);
+        peer.write_all(&frame).await.unwrap();
+        peer.write_all(&frame).await.unwrap();
+        let mut message_stream = MessageStream::new(client).with_checksum_verification(false);
+        let message = message_stream.read_message::<PingMessage>().await.unwrap();
+        assert_eq!(message.message, PingMessage { nonce: 7 });
+        let raw_message = message_stream.read_raw_message().await.unwrap();
+        assert_eq!(raw_message.header.checksum, [0; 4]);
+        assert_eq!(
+            raw_message.into_message::<PingMessage>().unwrap().message,
+            PingMessage { nonce: 7 }
+        );
+    }
+
+    #[tokio::test]
+    async fn message_stream_should_resync_past_corrupt_frame_and_count_it() {
+        let frame = |nonce| {
+            Message::new(Chain::Mainnet, PingMessage { nonce })
+                .encode()
+                .unwrap()
+        };
+        let mut corrupt_frame = frame(2);
+        corrupt_frame[MESSAGE_HEADER_SIZE] ^= 0xff;
+
+        let (client, mut peer) = tokio::io::duplex(1024);
+        for frame in [frame(1), corrupt_frame, frame(3)] {
+            peer.write_all(&frame).await.unwrap();
+        }
+        let mut message_stream = MessageStream::new(client).with_resync(true);
+        let message = message_stream.read_message::<PingMessage>().await.unwrap();
+        assert_eq!(message.message, PingMessage { nonce: 1 });
+        let message = message_stream.read_message::<PingMessage>().await.unwrap();
+        assert_eq!(message.message, PingMessage { nonce: 3 });
+
+        assert_eq!(
+            message_stream.stats(),
+            StreamStats {
+                messages_ok: 2,
+                checksum_errors: 1,
+                header_errors: 0,
+                resyncs: 1,
+                bytes_read: 3 * frame(0).len() as u64,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn message_stream_should_resync_past_corrupt_headers_to_next_magic() {
+        let frame = |nonce| {
+            Message::new(Chain::Mainnet, PingMessage { nonce })
+                .encode()
+                .unwrap()
+        };
+        let mut unknown_magic = frame(2);
+        unknown_magic[..4].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        let mut garbage_length = frame(3);
+        garbage_length[16..20].copy_from_slice(&u32::MAX.to_le_bytes());
+
+        let (client, mut peer) = tokio::io::duplex(1024);
+        for frame in [frame(1), unknown_magic, garbage_length, frame(4), frame(5)] {
+            peer.write_all(&frame).await.unwrap();
+        }
+        let mut message_stream = MessageStream::new(client).with_resync(true);
+        let message = message_stream.read_message::<PingMessage>().await.unwrap();
+        assert_eq!(message.message, PingMessage { nonce: 1 });
+        let message = message_stream.read_message::<PingMessage>().await.unwrap();
+        assert_eq!(message.message, PingMessage { nonce: 4 });
+        let raw_message = message_stream.read_raw_message().await.unwrap();
+        assert!(raw_message.is::<PingMessage>());
+
+        // Both corrupted headers are passed over by the same scan for the next magic value
+        assert_eq!(
+            message_stream.stats(),
+            StreamStats {
+                messages_ok: 3,
+                checksum_errors: 0,
+                header_errors: 1,
+                resyncs: 1,
+                bytes_read: 5 * frame(0).len() as u64,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn message_stream_should_not_scan_through_frame_of_other_command_when_resyncing() {
+        let ping_frame = Message::new(Chain::Mainnet, PingMessage { nonce: 7 })
+            .encode()
+            .unwrap();
+        let verack_frame = Message::new(Chain::Mainnet, VerackMessage)
+            .encode()
+            .unwrap();
+        let mut corrupt_frame = ping_frame.clone();
+        corrupt_frame[..4].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+
+        let (client, mut peer) = tokio::io::duplex(1024);
+        for frame in [&corrupt_frame, &ping_frame, &verack_frame] {
+            peer.write_all(frame).await.unwrap();
+        }
+        let mut message_stream = MessageStream::new(client).with_resync(true);
+        // The scan stops at the ping, which is consumed whole rather than searched for a header
+        let error = message_stream
+            .read_message::<VerackMessage>()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<MessageDecodeError>(),
+            Some(&MessageDecodeError::CommandNameUnkown)
+        );
+        let message = message_stream
+            .read_message::<VerackMessage>()
+            .await
+            .unwrap();
+        assert_eq!(message.message, VerackMessage);
+
+        assert_eq!(
+            message_stream.stats(),
+            StreamStats {
+                messages_ok: 1,
+                checksum_errors: 0,
+                header_errors: 1,
+                resyncs: 1,
+                bytes_read: (2 * ping_frame.len() + verack_frame.len()) as u64,
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn message_stream_should_stop_after_max_messages() {
+        let (client, mut peer) = tokio::io::duplex(1024);
+        let message = Message::new(Chain::Mainnet, VerackMessage);
+        for _ in 0..5 {
+            peer.write_all(&message.encode().unwrap()).await.unwrap();
+        }
+
+        let mut message_stream = MessageStream::new(client).with_max_messages(3);
+        for _ in 0..3 {
+            message_stream
+                .read_message::<VerackMessage>()
+                .await
+                .unwrap();
+        }
+        let error = message_stream.read_raw_message().await.unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<StreamLimitError>(),
+            Some(&StreamLimitError::MessageLimitReached(3))
+        );
+    }
+
+    #[tokio::test]
+    async fn message_stream_should_stop_before_exceeding_max_bytes() {
+        let (client, mut peer) = tokio::io::duplex(1024);
+        let message = Message::new(Chain::Mainnet, VerackMessage);
+        for _ in 0..3 {
+            peer.write_all(&message.encode().unwrap()).await.unwrap();
+        }
+
+        let mut message_stream =
+            MessageStream::new(client).with_max_bytes(2 * MESSAGE_HEADER_SIZE as u64 + 1);
+        for _ in 0..2 {
+            message_stream.read_raw_message().await.unwrap();
+        }
+        let error = message_stream
+            .read_message::<VerackMessage>()
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<StreamLimitError>(),
+            Some(&StreamLimitError::ByteLimitReached(
+                2 * MESSAGE_HEADER_SIZE as u64 + 1
+            ))
+        );
+    }
+
+    #[tokio::test]
+    async fn message_stream_should_honor_configured_read_buffer_size() {
+        let (client, mut peer) = tokio::io::duplex(1024);
+        let message = Message::new(Chain::Mainnet, VerackMessage);
+        peer.write_all(&message.encode().unwrap()).await.unwrap();
+        peer.write_all(&message.encode().unwrap()).await.unwrap();
+
+        let mut message_stream = MessageStream::with_capacity(64 * 1024, client);
+        assert_eq!(message_stream.capacity(), 64 * 1024);
+
+        let raw_message = message_stream.read_raw_message().await.unwrap();
+        assert!(raw_message.is::<VerackMessage>());
+        // Both frames fit in the buffer, so the second one was read along with the first
+        assert_eq!(message_stream.buffered_len(), MESSAGE_HEADER_SIZE);
+        let received_message = message_stream
+            .read_message::<VerackMessage>()
+            .await
+            .unwrap();
+        assert_eq!(received_message, message);
+    }
+
+    #[tokio::test]
+    async fn message_stream_should_read_messages_larger_than_read_buffer() {
+        let (client, mut peer) = tokio::io::duplex(1024);
+        let raw_message = RawMessage::new(
+            MessageHeader {
+                chain: Chain::Mainnet,
+                command_name: *b"unknown\x00\x00\x00\x00\x00",
+                payload_len: 100,
+                checksum: crate::crypto::checksum(&[1u8; 100]),
+            },
+            vec![1u8; 100],
+        );
+        let verack = Message::new(Chain::Mainnet, VerackMessage);
+        peer.write_all(&raw_message.encode().unwrap())
+            .await
+            .unwrap();
+        peer.write_all(&verack.encode().unwrap()).await.unwrap();
+
+        let mut message_stream = MessageStream::with_capacity(16, client);
+        assert_eq!(message_stream.capacity(), 16);
+        assert_eq!(
+            message_stream.read_raw_message().await.unwrap(),
+            raw_message
+        );
+        // The frame was read through the buffer in chunks, never holding more than its capacity
+        assert!(message_stream.buffered_len() <= 16);
+        assert_eq!(
+            message_stream
+                .read_message::<VerackMessage>()
+                .await
+                .unwrap(),
+            verack
+        );
+    }
+}
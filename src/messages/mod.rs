@@ -2,63 +2,218 @@
 
 use crate::{
     constants::{
-        MAINNET_MAGIC_VALUE, NAMECOIN_MAGIC_VALUE, REGNET_MAGIC_VALUE, SIGNET_MAGIC_VALUE,
-        TESTNET3_MAGIC_VALUE,
+        MAINNET_MAGIC_VALUE, MAINNET_PORT_NUMBER, NAMECOIN_MAGIC_VALUE, NAMECOIN_PORT_NUMBER,
+        REGNET_MAGIC_VALUE, REGNET_PORT_NUMBER, SIGNET_MAGIC_VALUE, SIGNET_PORT_NUMBER,
+        TESTNET3_MAGIC_VALUE, TESTNET3_PORT_NUMBER,
     },
     crypto::checksum,
 };
-use anyhow::anyhow;
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::str::FromStr;
 use std::{
-    fmt::Debug,
+    fmt::{Debug, Display},
     io::{Read, Write},
 };
 
+pub mod capture;
 pub mod codec;
+pub mod framed;
 pub mod types;
-use crate::constants::MAX_PAYLOAD_SIZE;
-use codec::{Decode, Encode};
+use crate::constants::{MAX_PAYLOAD_SIZE, MESSAGE_HEADER_SIZE};
+use codec::{read_exact_len, CodecError, Decode, Encode, TrailingBytesPolicy};
+use types::version::Services;
 
 pub trait CommandName {
-    fn command_name() -> [u8; 12];
+    /// Command that messages of this type are sent with
+    fn command() -> Command;
+
+    /// Command name as it appears in the message header, padded with null bytes
+    fn command_name() -> [u8; 12] {
+        Self::command().to_bytes()
+    }
+}
+
+/// Pads `name` with null bytes to the 12 bytes of the command name field
+const fn pad_command_name(name: &str) -> [u8; 12] {
+    let mut bytes = [0u8; 12];
+    let mut i = 0;
+    while i < name.len() {
+        bytes[i] = name.as_bytes()[i];
+        i += 1;
+    }
+    bytes
+}
+
+/// Defines [`Command`] with a variant per `Variant => "name"` pair, in addition to [`Command::Unknown`]
+macro_rules! commands {
+    ($($variant:ident => $name:literal),* $(,)?) => {
+        /// Command carried in a message header, identifying the type of the payload
+        #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+        pub enum Command {
+            $(
+                #[doc = concat!("The `", $name, "` message")]
+                $variant,
+            )*
+            /// Any command this crate has no message type for, with its raw command name
+            Unknown([u8; 12]),
+        }
+
+        impl Command {
+            /// Every command with a message type in this crate
+            pub const KNOWN: &'static [Command] = &[$(Command::$variant),*];
+
+            /// Command name as it appears in the message header, padded with null bytes
+            pub fn to_bytes(&self) -> [u8; 12] {
+                match self {
+                    $(Command::$variant => pad_command_name($name),)*
+                    Command::Unknown(bytes) => *bytes,
+                }
+            }
+
+            /// Command of a message header's command name, [`Command::Unknown`] if no message type matches it
+            pub fn from_bytes(bytes: [u8; 12]) -> Self {
+                Self::KNOWN
+                    .iter()
+                    .copied()
+                    .find(|command| command.to_bytes() == bytes)
+                    .unwrap_or(Command::Unknown(bytes))
+            }
+        }
+    };
+}
+
+commands! {
+    Version => "version",
+    Verack => "verack",
+    Ping => "ping",
+    Pong => "pong",
+    Addr => "addr",
+    AddrV2 => "addrv2",
+    SendAddrV2 => "sendaddrv2",
+    Inv => "inv",
+    GetData => "getdata",
+    GetHeaders => "getheaders",
+    Headers => "headers",
+    SendHeaders => "sendheaders",
+    SendCmpct => "sendcmpct",
+    FeeFilter => "feefilter",
+    MerkleBlock => "merkleblock",
+    Reject => "reject",
+    FilterAdd => "filteradd",
+    FilterClear => "filterclear",
+    GetBlockTxn => "getblocktxn",
+    BlockTxn => "blocktxn",
+    CmpctBlock => "cmpctblock",
+    WtxidRelay => "wtxidrelay",
+    GetUtxos => "getutxos",
+    Utxos => "utxos",
+}
+
+/// Formats the command name without its null padding, e.g. `version`
+impl Display for Command {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bytes = self.to_bytes();
+        let name = String::from_utf8_lossy(&bytes);
+        f.write_str(name.trim_end_matches('\0'))
+    }
 }
 
 /// Different Bitcoin Networks
 ///
 /// Source: https://en.bitcoin.it/wiki/Protocol_documentation#M_structure
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub enum Chain {
     Mainnet,
-    Regnet,
+    /// Local regression test network, which is called `regtest` throughout the Bitcoin ecosystem
+    Regtest,
     Testnet3,
     Signet,
     Namecoin,
 }
 
+impl Chain {
+    /// Former name of [`Chain::Regtest`]
+    #[deprecated(note = "renamed to `Chain::Regtest`")]
+    #[allow(non_upper_case_globals)]
+    pub const Regnet: Chain = Chain::Regtest;
+
+    /// Port that peers on this chain listen on by default
+    pub fn default_port(&self) -> u16 {
+        match self {
+            Chain::Mainnet => MAINNET_PORT_NUMBER,
+            Chain::Regtest => REGNET_PORT_NUMBER,
+            Chain::Testnet3 => TESTNET3_PORT_NUMBER,
+            Chain::Signet => SIGNET_PORT_NUMBER,
+            Chain::Namecoin => NAMECOIN_PORT_NUMBER,
+        }
+    }
+
+    /// Chain whose default port is `port`, if any
+    ///
+    /// Nodes can listen on any port, so this is only a best-effort guess, e.g. to point out a likely mistake.
+    pub fn from_default_port(port: u16) -> Option<Chain> {
+        match port {
+            MAINNET_PORT_NUMBER => Some(Chain::Mainnet),
+            REGNET_PORT_NUMBER => Some(Chain::Regtest),
+            TESTNET3_PORT_NUMBER => Some(Chain::Testnet3),
+            SIGNET_PORT_NUMBER => Some(Chain::Signet),
+            NAMECOIN_PORT_NUMBER => Some(Chain::Namecoin),
+            _ => None,
+        }
+    }
+
+    /// Services that a pure handshake client should advertise on this chain
+    ///
+    /// This crate never serves blocks or transactions, so no service bit is set: advertising e.g. `NODE_NETWORK` would
+    /// make peers ask us for data we can't provide. The suggestion is currently the same for every chain.
+    pub fn suggested_handshake_services(&self) -> Services {
+        Services::UNNAMED
+    }
+}
+
+/// Error returned when parsing a [`Chain`] from an unknown name
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+#[error("cannot convert {0:?} to a chain")]
+pub struct ParseChainError(String);
+
 impl FromStr for Chain {
-    type Err = anyhow::Error;
+    type Err = ParseChainError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         match s {
             "mainnet" => Ok(Chain::Mainnet),
-            "regnet" => Ok(Chain::Regnet),
+            // `regnet` is still accepted, as it used to be the only name of the chain
+            "regtest" | "regnet" => Ok(Chain::Regtest),
             "testnet3" => Ok(Chain::Testnet3),
             "signet" => Ok(Chain::Signet),
             "namecoin" => Ok(Chain::Namecoin),
-            _ => return Err(anyhow!("Cannot convert string to chain")),
+            _ => Err(ParseChainError(s.to_string())),
         }
     }
 }
 
+impl Display for Chain {
+    /// Formats the chain using the same name that is accepted by `FromStr`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Chain::Mainnet => "mainnet",
+            Chain::Regtest => "regtest",
+            Chain::Testnet3 => "testnet3",
+            Chain::Signet => "signet",
+            Chain::Namecoin => "namecoin",
+        };
+        f.write_str(name)
+    }
+}
+
 impl Encode for Chain {
     /// Magic value indicating message origin network, and used to seek to next message when stream state is unknown
     ///
     /// Source: https://en.bitcoin.it/wiki/Protocol_documentation#M_structure
-    fn encode(&self) -> anyhow::Result<Vec<u8>> {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
         match self {
             Chain::Mainnet => Ok(MAINNET_MAGIC_VALUE.to_vec()),
-            Chain::Regnet => Ok(REGNET_MAGIC_VALUE.to_vec()),
+            Chain::Regtest => Ok(REGNET_MAGIC_VALUE.to_vec()),
             Chain::Testnet3 => Ok(TESTNET3_MAGIC_VALUE.to_vec()),
             Chain::Signet => Ok(SIGNET_MAGIC_VALUE.to_vec()),
             Chain::Namecoin => Ok(NAMECOIN_MAGIC_VALUE.to_vec()),
@@ -66,98 +221,784 @@ impl Encode for Chain {
     }
 }
 
-impl Decode for Chain {
-    fn decode(bytes: &mut impl Read) -> anyhow::Result<Self> {
-        let mut magic_value = [0u8; 4];
-        bytes.read_exact(&mut magic_value)?;
+impl TryFrom<[u8; 4]> for Chain {
+    type Error = CodecError;
+
+    /// Chain whose messages start with `magic_value`
+    fn try_from(magic_value: [u8; 4]) -> Result<Self, Self::Error> {
         match magic_value {
             MAINNET_MAGIC_VALUE => Ok(Chain::Mainnet),
-            REGNET_MAGIC_VALUE => Ok(Chain::Regnet),
+            REGNET_MAGIC_VALUE => Ok(Chain::Regtest),
             TESTNET3_MAGIC_VALUE => Ok(Chain::Testnet3),
             SIGNET_MAGIC_VALUE => Ok(Chain::Signet),
             NAMECOIN_MAGIC_VALUE => Ok(Chain::Namecoin),
-            _ => return Err(anyhow!("Unknown Magic Value: {:?}", magic_value)),
+            _ => Err(CodecError::InvalidMagic(magic_value)),
         }
     }
 }
 
+impl TryFrom<&[u8; 4]> for Chain {
+    type Error = CodecError;
+
+    fn try_from(magic_value: &[u8; 4]) -> Result<Self, Self::Error> {
+        Chain::try_from(*magic_value)
+    }
+}
+
+impl Decode for Chain {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let mut magic_value = [0u8; 4];
+        bytes.read_exact(&mut magic_value)?;
+        Ok(Chain::try_from(magic_value)?)
+    }
+}
+
 /// Struct represents a message on the Bitcoin p2p network protocol
-pub struct Message<M: CommandName + Encode + Decode> {
+#[derive(Debug, PartialEq)]
+pub struct Message<M: CommandName + Encode> {
     pub chain: Chain,
     pub message: M,
 }
 
-impl<M: CommandName + Encode + Decode> Message<M> {
+impl<M: CommandName + Encode> Message<M> {
     pub fn new(chain: Chain, message: M) -> Self {
         Self { chain, message }
     }
+
+    /// Same as [`Encode::encode`], but encodes into `scratch` (clearing it first) so that a single buffer can be reused
+    /// across messages
+    pub fn encode_reusing<'a>(&self, scratch: &'a mut Vec<u8>) -> crate::Result<&'a [u8]> {
+        scratch.clear();
+        self.encode_into(scratch)?;
+        Ok(scratch)
+    }
+}
+
+/// Header that precedes the payload of every message on the Bitcoin p2p network protocol
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#message-headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageHeader {
+    /// Network the message originates from
+    pub chain: Chain,
+    /// Command name of the message, padded with null bytes
+    pub command_name: [u8; 12],
+    /// Number of bytes in the payload
+    pub payload_len: u32,
+    /// First 4 bytes of SHA256(SHA256(payload))
+    pub checksum: [u8; 4],
+}
+
+impl Encode for MessageHeader {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(MESSAGE_HEADER_SIZE);
+
+        buffer.write_all(&self.chain.encode()?)?;
+        buffer.write_all(&self.command_name)?;
+        buffer.write_u32::<LittleEndian>(self.payload_len)?;
+        buffer.write_all(&self.checksum)?;
+
+        Ok(buffer)
+    }
+}
+
+impl Decode for MessageHeader {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let mut magic_number = [0u8; 4];
+        bytes.read_exact(&mut magic_number)?;
+        let chain = Chain::decode_bytes(magic_number)?;
+
+        let mut command_name = [0u8; 12];
+        bytes.read_exact(&mut command_name)?;
+
+        let payload_len = bytes.read_u32::<LittleEndian>()?;
+
+        let mut checksum = [0u8; 4];
+        bytes.read_exact(&mut checksum)?;
+
+        Ok(Self {
+            chain,
+            command_name,
+            payload_len,
+            checksum,
+        })
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
-enum MessageEncodeError {
+pub enum MessageEncodeError {
     #[error("payload too big")]
     PayloadTooBig,
 }
-#[derive(Debug, thiserror::Error)]
-enum MessageDecodeError {
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum MessageDecodeError {
     #[error("payload too big")]
     PayloadTooBig,
     #[error("command name unknown")]
     CommandNameUnkown,
-    #[error("checksum is invalid")]
-    CheksumIsInvalid,
+    /// The payload does not hash to the checksum claimed in its header, which is kept as it arrived for inspection
+    #[error(
+        "checksum is invalid: {} message of {} bytes claims checksum {:02x?} but hashes to {computed:02x?}",
+        Command::from_bytes(.header.command_name),
+        .header.payload_len,
+        .header.checksum
+    )]
+    CheksumIsInvalid {
+        header: MessageHeader,
+        computed: [u8; 4],
+    },
+    #[error("{0} bytes left in the payload after decoding the message")]
+    TrailingBytes(usize),
 }
 
-impl<M: CommandName + Encode + Decode> Encode for Message<M> {
-    fn encode(&self) -> anyhow::Result<Vec<u8>> {
-        let encoded_message = self.message.encode()?;
-        let encoded_message_len = encoded_message.len() as u32;
-        if encoded_message_len > MAX_PAYLOAD_SIZE {
-            Err(MessageEncodeError::PayloadTooBig)?
+/// Checks that `payload` hashes to the checksum claimed in `header`
+pub(crate) fn verify_payload_checksum(
+    header: &MessageHeader,
+    payload: &[u8],
+) -> Result<(), MessageDecodeError> {
+    let computed = checksum(payload);
+    if header.checksum != computed {
+        Err(MessageDecodeError::CheksumIsInvalid {
+            header: *header,
+            computed,
+        })?
+    }
+    Ok(())
+}
+
+impl<M: CommandName + Encode + Decode> Message<M> {
+    /// Checks that `header` belongs to a message of type `M` whose payload does not exceed `max_payload_size`
+    ///
+    /// This is done before the payload is read so that an oversized length is rejected before any allocation.
+    pub(crate) fn validate_header(
+        header: &MessageHeader,
+        max_payload_size: u32,
+    ) -> crate::Result<()> {
+        if header.command_name != M::command_name() {
+            Err(MessageDecodeError::CommandNameUnkown)?
         }
-        let checksum = checksum(&encoded_message);
+        if header.payload_len > max_payload_size {
+            Err(MessageDecodeError::PayloadTooBig)?
+        }
+        Ok(())
+    }
 
-        let mut buffer = Vec::with_capacity(24 + encoded_message.len());
+    /// Verifies the checksum of `payload` against `header` (unless `verify_checksum` is false) and decodes it into a
+    /// message
+    ///
+    /// The message is decoded from `payload` alone, so it can never read into the next frame. Bytes it leaves unread
+    /// are handled according to `trailing_bytes`.
+    pub(crate) fn decode_payload(
+        header: &MessageHeader,
+        payload: &[u8],
+        trailing_bytes: TrailingBytesPolicy,
+        verify_checksum: bool,
+    ) -> crate::Result<Self> {
+        if verify_checksum {
+            verify_payload_checksum(header, payload)?;
+        }
 
-        buffer.write_all(&self.chain.encode()?)?;
-        buffer.write_all(&M::command_name())?;
-        buffer.write_u32::<LittleEndian>(encoded_message_len)?;
-        buffer.write_all(&checksum)?;
-        buffer.write_all(&encoded_message)?;
+        let mut remaining = payload;
+        let message = M::decode(&mut remaining).map_err(crate::Error::into_codec_error)?;
+        if !remaining.is_empty() {
+            match trailing_bytes {
+                TrailingBytesPolicy::Ignore => tracing::debug!(
+                    command = %Command::from_bytes(header.command_name),
+                    trailing_bytes = remaining.len(),
+                    "Ignoring bytes left in the payload"
+                ),
+                TrailingBytesPolicy::Reject => {
+                    Err(MessageDecodeError::TrailingBytes(remaining.len()))?
+                }
+            }
+        }
+
+        Ok(Self {
+            chain: header.chain,
+            message,
+        })
+    }
+}
 
+impl<M: CommandName + Encode> Encode for Message<M> {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::new();
+        self.encode_into(&mut buffer)?;
         Ok(buffer)
     }
+
+    /// Appends the frame to `buffer`, encoding the payload right behind the header rather than into a buffer of its own
+    fn encode_into(&self, buffer: &mut Vec<u8>) -> crate::Result<()> {
+        // The header depends on the payload, so it is filled in once the payload was encoded behind it
+        let header_start = buffer.len();
+        let payload_start = header_start + MESSAGE_HEADER_SIZE;
+        buffer.resize(payload_start, 0);
+        self.message.encode_into(buffer)?;
+
+        if buffer.len() - payload_start > MAX_PAYLOAD_SIZE as usize {
+            buffer.truncate(header_start);
+            Err(MessageEncodeError::PayloadTooBig)?
+        }
+        let payload = &buffer[payload_start..];
+        let header = MessageHeader {
+            chain: self.chain,
+            command_name: M::command_name(),
+            payload_len: payload.len() as u32,
+            checksum: checksum(payload),
+        };
+        buffer[header_start..payload_start].copy_from_slice(&header.encode()?);
+        Ok(())
+    }
+}
+
+/// Length of the frame at the start of `buf`, or `None` if `buf` does not hold all of it yet
+///
+/// A header that fails to decode gives no length to skip, so `buf` is cleared instead of being left in front of it.
+#[cfg(feature = "bytes")]
+fn complete_frame_len(buf: &mut bytes::BytesMut) -> crate::Result<Option<(MessageHeader, usize)>> {
+    let Some(encoded_header) = buf.get(..MESSAGE_HEADER_SIZE) else {
+        return Ok(None);
+    };
+    let header = MessageHeader::decode_bytes(encoded_header)
+        .and_then(|header| {
+            if header.payload_len > MAX_PAYLOAD_SIZE {
+                Err(MessageDecodeError::PayloadTooBig)?
+            }
+            Ok(header)
+        })
+        .inspect_err(|_| buf.clear())?;
+    let frame_len = MESSAGE_HEADER_SIZE + header.payload_len as usize;
+    Ok((buf.len() >= frame_len).then_some((header, frame_len)))
+}
+
+#[cfg(feature = "bytes")]
+impl<M: CommandName + Encode + Decode> Message<M> {
+    /// Decodes the frame at the start of `buf` and advances `buf` past it, or returns `None` while the frame is
+    /// incomplete
+    ///
+    /// The payload is decoded in place, without being copied out of `buf` first. A frame whose payload fails to decode
+    /// is still consumed, so that the next call starts at the following frame. A header that fails to decode leaves no
+    /// way to find the following frame, so `buf` is cleared and the stream it was read from should be dropped.
+    pub fn decode_bytes_buf(buf: &mut bytes::BytesMut) -> crate::Result<Option<Self>> {
+        let Some((header, frame_len)) = complete_frame_len(buf)? else {
+            return Ok(None);
+        };
+        let frame = buf.split_to(frame_len);
+        Self::validate_header(&header, MAX_PAYLOAD_SIZE)?;
+        Self::decode_payload(
+            &header,
+            &frame[MESSAGE_HEADER_SIZE..],
+            TrailingBytesPolicy::default(),
+            true,
+        )
+        .map(Some)
+    }
 }
 
 impl<M: CommandName + Encode + Decode> Decode for Message<M> {
-    fn decode(bytes: &mut impl Read) -> anyhow::Result<Self> {
-        let mut magic_number = [0u8; 4];
-        bytes.read_exact(&mut magic_number)?;
-        let chain = Chain::decode(&mut magic_number.as_slice())?;
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let header = MessageHeader::decode(bytes)?;
+        Self::validate_header(&header, MAX_PAYLOAD_SIZE)?;
 
-        let mut command_name = [0u8; 12];
-        bytes.read_exact(&mut command_name)?;
-        if command_name != M::command_name() {
-            Err(MessageDecodeError::CommandNameUnkown)?
+        let encoded_message = read_exact_len(bytes, header.payload_len as u64)?;
+
+        Self::decode_payload(
+            &header,
+            &encoded_message,
+            TrailingBytesPolicy::default(),
+            true,
+        )
+    }
+}
+
+/// Message whose payload has been framed and checksummed but not yet decoded into a concrete type
+///
+/// This allows reading messages whose command is not known in advance. A message read from a
+/// [`MessageStream`](framed::MessageStream) or decoded from bytes had its checksum verified then, so it isn't computed
+/// again when the payload is decoded. Any other raw message is verified by [`RawMessage::into_message`].
+#[derive(Debug, Clone)]
+pub struct RawMessage {
+    pub header: MessageHeader,
+    pub payload: Vec<u8>,
+    /// Whether the checksum was already verified, or deliberately left unverified by the stream it was read from
+    checksum_verified: bool,
+}
+
+impl PartialEq for RawMessage {
+    fn eq(&self, other: &Self) -> bool {
+        self.header == other.header && self.payload == other.payload
+    }
+}
+
+impl RawMessage {
+    /// Creates a raw message whose checksum is verified once it is decoded
+    pub fn new(header: MessageHeader, payload: Vec<u8>) -> Self {
+        Self {
+            header,
+            payload,
+            checksum_verified: false,
         }
+    }
+
+    /// Marks the checksum as handled by whoever read the message, so that decoding it doesn't verify it again
+    pub(crate) fn with_checksum_verified(mut self) -> Self {
+        self.checksum_verified = true;
+        self
+    }
+
+    /// Checks that the payload matches the checksum in the header
+    pub(crate) fn verify_checksum(&self) -> crate::Result<()> {
+        Ok(verify_payload_checksum(&self.header, &self.payload)?)
+    }
+
+    /// Command carried in the header of the message
+    pub fn command(&self) -> Command {
+        Command::from_bytes(self.header.command_name)
+    }
+
+    /// Returns whether the message carries the command of `M`
+    pub fn is<M: CommandName>(&self) -> bool {
+        self.header.command_name == M::command_name()
+    }
+
+    /// Decodes the payload into a message of type `M`, verifying its checksum unless that already happened when it was
+    /// read
+    pub fn into_message<M: CommandName + Encode + Decode>(self) -> crate::Result<Message<M>> {
+        Message::<M>::validate_header(&self.header, MAX_PAYLOAD_SIZE)?;
+        Message::<M>::decode_payload(
+            &self.header,
+            &self.payload,
+            TrailingBytesPolicy::default(),
+            !self.checksum_verified,
+        )
+    }
+}
 
-        let encoded_message_len = bytes.read_u32::<LittleEndian>()?;
-        if encoded_message_len > MAX_PAYLOAD_SIZE {
+#[cfg(feature = "bytes")]
+impl RawMessage {
+    /// Same as [`Message::decode_bytes_buf`], but decodes a message of any command
+    pub fn decode_bytes_buf(buf: &mut bytes::BytesMut) -> crate::Result<Option<Self>> {
+        let Some((header, frame_len)) = complete_frame_len(buf)? else {
+            return Ok(None);
+        };
+        let mut frame = buf.split_to(frame_len);
+        let raw_message = Self::new(header, frame.split_off(MESSAGE_HEADER_SIZE).to_vec());
+        raw_message.verify_checksum()?;
+        Ok(Some(raw_message.with_checksum_verified()))
+    }
+}
+
+impl Encode for RawMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(MESSAGE_HEADER_SIZE + self.payload.len());
+        buffer.write_all(&self.header.encode()?)?;
+        buffer.write_all(&self.payload)?;
+        Ok(buffer)
+    }
+}
+
+impl Decode for RawMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let header = MessageHeader::decode(bytes)?;
+        if header.payload_len > MAX_PAYLOAD_SIZE {
             Err(MessageDecodeError::PayloadTooBig)?
         }
 
-        let mut received_checksum = [0u8; 4];
-        bytes.read_exact(&mut received_checksum)?;
+        let payload = read_exact_len(bytes, header.payload_len as u64)?;
+
+        let raw_message = Self::new(header, payload);
+        raw_message.verify_checksum()?;
+        Ok(raw_message.with_checksum_verified())
+    }
+}
+
+/// Differences between two encoded frames `a` and `b`, see [`diff_frames`]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameDiff {
+    /// Offsets within the bytes that both frames have at which they differ, along with the byte of `a` and of `b`
+    pub bytes: Vec<(usize, u8, u8)>,
+    /// Length of `a`
+    pub len_a: usize,
+    /// Length of `b`
+    pub len_b: usize,
+}
+
+impl FrameDiff {
+    /// Whether the frames are identical, i.e. have the same length and no differing byte
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty() && self.len_a == self.len_b
+    }
+
+    /// Bytes of the longer frame past the end of the shorter one, starting at offset `min(len_a, len_b)`
+    pub fn tail<'a>(&self, a: &'a [u8], b: &'a [u8]) -> &'a [u8] {
+        let longer = if self.len_a > self.len_b { a } else { b };
+        &longer[self.len_a.min(self.len_b)..]
+    }
+}
+
+/// Byte offsets at which two encoded frames differ, along with the byte of `a` and the byte of `b` at each offset
+///
+/// The bytes are compared up to the end of the shorter frame, and the lengths of both frames are reported as well, so
+/// that a truncated frame or one with extra bytes never looks identical to the other.
+pub fn diff_frames(a: &[u8], b: &[u8]) -> FrameDiff {
+    FrameDiff {
+        bytes: a
+            .iter()
+            .zip(b)
+            .enumerate()
+            .filter(|(_, (a, b))| a != b)
+            .map(|(offset, (a, b))| (offset, *a, *b))
+            .collect(),
+        len_a: a.len(),
+        len_b: b.len(),
+    }
+}
+
+/// Name of the part of a frame that the byte at `offset` belongs to, e.g. to annotate the output of [`diff_frames`]
+pub fn frame_field_at(offset: usize) -> &'static str {
+    match offset {
+        0..=3 => "magic",
+        4..=15 => "command",
+        16..=19 => "payload length",
+        20..=23 => "checksum",
+        _ => "payload",
+    }
+}
 
-        let mut encoded_message = vec![0u8; encoded_message_len as usize];
-        bytes.read_exact(&mut encoded_message)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::types::{
+        ping::PingMessage, verack::VerackMessage, version::VersionMessage,
+    };
 
-        if received_checksum != checksum(&encoded_message) {
-            Err(MessageDecodeError::CheksumIsInvalid)?
+    #[test]
+    fn suggested_handshake_services_should_not_advertise_serving() {
+        for chain in [Chain::Mainnet, Chain::Testnet3, Chain::Signet] {
+            assert_eq!(chain.suggested_handshake_services(), Services::UNNAMED);
+            assert!(!chain
+                .suggested_handshake_services()
+                .contains(Services::NODE_NETWORK));
         }
+    }
+
+    #[test]
+    fn encode_reusing_should_match_encode() {
+        use types::{ping::PingMessage, sendcmpct::SendCmpctMessage};
+
+        let ping = Message::new(Chain::Mainnet, PingMessage { nonce: 7 });
+        let verack = Message::new(Chain::Testnet3, VerackMessage);
+        let sendcmpct = Message::new(
+            Chain::Signet,
+            SendCmpctMessage {
+                announce: true,
+                version: 2,
+            },
+        );
+
+        let mut scratch = Vec::new();
+        assert_eq!(
+            ping.encode_reusing(&mut scratch).unwrap(),
+            ping.encode().unwrap()
+        );
+        assert_eq!(
+            verack.encode_reusing(&mut scratch).unwrap(),
+            verack.encode().unwrap()
+        );
+        assert_eq!(
+            sendcmpct.encode_reusing(&mut scratch).unwrap(),
+            sendcmpct.encode().unwrap()
+        );
+        // The buffer keeps the capacity of the largest message, so encoding a smaller one doesn't reallocate
+        let capacity = scratch.capacity();
+        ping.encode_reusing(&mut scratch).unwrap();
+        assert_eq!(scratch.capacity(), capacity);
+
+        // Frames encoded into the same buffer are appended one after the other
+        let mut frames = Vec::new();
+        ping.encode_into(&mut frames).unwrap();
+        sendcmpct.encode_into(&mut frames).unwrap();
+        assert_eq!(
+            frames,
+            [ping.encode().unwrap(), sendcmpct.encode().unwrap()].concat()
+        );
+    }
+
+    #[test]
+    fn command_should_round_trip_through_bytes() {
+        for &command in Command::KNOWN {
+            assert_eq!(Command::from_bytes(command.to_bytes()), command);
+        }
+        assert_eq!(Command::from_bytes(*b"version\0\0\0\0\0"), Command::Version);
+        assert_eq!(VerackMessage::command_name(), *b"verack\0\0\0\0\0\0");
+        assert_eq!(Command::Version.to_string(), "version");
+
+        let unknown = *b"unknown\0\0\0\0\0";
+        assert_eq!(Command::from_bytes(unknown), Command::Unknown(unknown));
+        assert_eq!(Command::Unknown(unknown).to_bytes(), unknown);
+        assert_eq!(Command::Unknown(unknown).to_string(), "unknown");
+    }
+
+    #[test]
+    fn from_default_port_should_invert_default_port() {
+        for chain in [
+            Chain::Mainnet,
+            Chain::Regtest,
+            Chain::Testnet3,
+            Chain::Signet,
+            Chain::Namecoin,
+        ] {
+            assert_eq!(Chain::from_default_port(chain.default_port()), Some(chain));
+        }
+        assert_eq!(Chain::from_default_port(18333), Some(Chain::Testnet3));
+        assert_eq!(Chain::from_default_port(12345), None);
+    }
+
+    #[test]
+    fn chain_display_should_round_trip_through_from_str() {
+        for chain in [
+            Chain::Mainnet,
+            Chain::Regtest,
+            Chain::Testnet3,
+            Chain::Signet,
+            Chain::Namecoin,
+        ] {
+            assert_eq!(Chain::from_str(&chain.to_string()).unwrap(), chain);
+        }
+    }
+
+    #[test]
+    fn chain_should_convert_from_magic_value() {
+        assert_eq!(Chain::try_from(MAINNET_MAGIC_VALUE), Ok(Chain::Mainnet));
+        assert_eq!(Chain::try_from(&SIGNET_MAGIC_VALUE), Ok(Chain::Signet));
+        assert_eq!(
+            Chain::try_from([1, 2, 3, 4]),
+            Err(CodecError::InvalidMagic([1, 2, 3, 4]))
+        );
+
+        let error = Chain::decode_bytes([1, 2, 3, 4]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::InvalidMagic([1, 2, 3, 4]))
+        );
+    }
+
+    #[test]
+    fn chain_should_parse_regtest_and_legacy_regnet_name() {
+        assert_eq!(Chain::from_str("regtest").unwrap(), Chain::Regtest);
+        assert_eq!(Chain::from_str("regnet").unwrap(), Chain::Regtest);
+        assert_eq!(Chain::Regtest.to_string(), "regtest");
+        #[allow(deprecated)]
+        let regnet = Chain::Regnet;
+        assert_eq!(regnet, Chain::Regtest);
+    }
+
+    #[test]
+    fn decoded_messages_should_compare_equal() {
+        let version_message = VersionMessage::new(
+            70015,
+            Services::NODE_NETWORK,
+            1415483324,
+            Services::NODE_NETWORK,
+            "1.2.3.4:8333".parse().unwrap(),
+            "5.6.7.8:8333".parse().unwrap(),
+            Services::NODE_NETWORK,
+            42,
+            "/Satoshi:0.9.3/".to_string(),
+            329167,
+            true,
+        );
+        let message = Message::new(Chain::Mainnet, version_message);
+        let bytes = message.encode().unwrap();
+
+        let first = Message::<VersionMessage>::decode_bytes(&bytes).unwrap();
+        let second = Message::<VersionMessage>::decode_bytes(&bytes).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(first, message);
+    }
+
+    #[test]
+    fn diff_frames_should_locate_differing_nonce() {
+        let version_frame = |nonce| {
+            let version_message = VersionMessage::new(
+                70015,
+                Services::NODE_NETWORK,
+                1415483324,
+                Services::NODE_NETWORK,
+                "1.2.3.4:8333".parse().unwrap(),
+                "5.6.7.8:8333".parse().unwrap(),
+                Services::NODE_NETWORK,
+                nonce,
+                "/Satoshi:0.9.3/".to_string(),
+                329167,
+                true,
+            );
+            Message::new(Chain::Mainnet, version_message)
+                .encode()
+                .unwrap()
+        };
+        let (a, b) = (version_frame(0x0102), version_frame(0x0103));
+
+        // The nonce follows the version, services, timestamp and both network addresses of the payload
+        let nonce_offset = MESSAGE_HEADER_SIZE + 4 + 8 + 8 + 26 + 26;
+        let diff = diff_frames(&a, &b);
+        assert_eq!((diff.len_a, diff.len_b), (a.len(), b.len()));
+        assert!(diff.bytes.contains(&(nonce_offset, 0x02, 0x03)));
+        assert!(
+            diff.bytes
+                .iter()
+                .all(|(offset, _, _)| *offset == nonce_offset
+                    || frame_field_at(*offset) == "checksum")
+        );
+        assert_eq!(frame_field_at(nonce_offset), "payload");
+        assert!(diff_frames(&a, &a).is_empty());
+    }
+
+    #[test]
+    fn diff_frames_should_report_frames_of_unequal_length() {
+        let frame = Message::new(Chain::Mainnet, PingMessage { nonce: 7 })
+            .encode()
+            .unwrap();
+        let mut extended = frame.clone();
+        extended.extend_from_slice(&[0xaa, 0xbb]);
+        let truncated = &frame[..frame.len() - 1];
+
+        let diff = diff_frames(&frame, &extended);
+        assert!(diff.bytes.is_empty());
+        assert!(!diff.is_empty());
+        assert_eq!((diff.len_a, diff.len_b), (frame.len(), frame.len() + 2));
+        assert_eq!(diff.tail(&frame, &extended), [0xaa, 0xbb]);
+
+        let diff = diff_frames(&frame, truncated);
+        assert!(!diff.is_empty());
+        assert_eq!(diff.tail(&frame, truncated), &frame[frame.len() - 1..]);
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn decode_bytes_buf_should_wait_for_complete_frame() {
+        use types::ping::PingMessage;
+
+        let message = Message::new(Chain::Mainnet, PingMessage { nonce: 7 });
+        let frame = message.encode().unwrap();
+        let (first_half, second_half) = frame.split_at(MESSAGE_HEADER_SIZE + 3);
+
+        let mut buf = bytes::BytesMut::from(first_half);
+        assert_eq!(
+            Message::<PingMessage>::decode_bytes_buf(&mut buf).unwrap(),
+            None
+        );
+        assert_eq!(RawMessage::decode_bytes_buf(&mut buf).unwrap(), None);
+        assert_eq!(buf.len(), first_half.len());
+
+        buf.extend_from_slice(second_half);
+        buf.extend_from_slice(&frame);
+        assert_eq!(
+            Message::<PingMessage>::decode_bytes_buf(&mut buf).unwrap(),
+            Some(message)
+        );
+        let raw_message = RawMessage::decode_bytes_buf(&mut buf).unwrap().unwrap();
+        assert!(raw_message.is::<PingMessage>());
+        assert!(buf.is_empty());
+    }
+
+    #[cfg(feature = "bytes")]
+    #[test]
+    fn decode_bytes_buf_should_clear_buf_on_header_error() {
+        use types::ping::PingMessage;
+
+        let frame = Message::new(Chain::Mainnet, PingMessage { nonce: 7 })
+            .encode()
+            .unwrap();
+        let mut unknown_magic = frame.clone();
+        unknown_magic[..4].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
+        let mut oversized = frame.clone();
+        oversized[16..20].copy_from_slice(&(MAX_PAYLOAD_SIZE + 1).to_le_bytes());
+
+        for corrupted in [unknown_magic, oversized] {
+            let mut buf = bytes::BytesMut::from(&corrupted[..]);
+            buf.extend_from_slice(&frame);
+            assert!(Message::<PingMessage>::decode_bytes_buf(&mut buf).is_err());
+            assert!(buf.is_empty());
+
+            let mut buf = bytes::BytesMut::from(&corrupted[..]);
+            assert!(RawMessage::decode_bytes_buf(&mut buf).is_err());
+            assert!(buf.is_empty());
+        }
+    }
+
+    #[test]
+    fn raw_message_should_decode_into_concrete_message() {
+        let message = Message::new(Chain::Signet, VerackMessage);
+        let bytes = message.encode().unwrap();
+
+        let raw_message = RawMessage::decode_bytes(&bytes).unwrap();
+        assert!(raw_message.is::<VerackMessage>());
+        assert!(!raw_message.is::<VersionMessage>());
+        assert_eq!(raw_message.encode().unwrap(), bytes);
+        assert_eq!(
+            raw_message.into_message::<VerackMessage>().unwrap(),
+            message
+        );
+    }
+
+    #[test]
+    fn into_message_should_verify_checksum_of_built_raw_message() {
+        let message = Message::new(Chain::Mainnet, PingMessage { nonce: 1 });
+        let raw_message = RawMessage::decode_bytes(message.encode().unwrap()).unwrap();
+        let mut built = RawMessage::new(raw_message.header, raw_message.payload);
+        assert_eq!(
+            built.clone().into_message::<PingMessage>().unwrap(),
+            message
+        );
+
+        built.payload[0] ^= 1;
+        let error = built.into_message::<PingMessage>().unwrap_err();
+        assert!(matches!(
+            error.downcast_ref::<MessageDecodeError>(),
+            Some(MessageDecodeError::CheksumIsInvalid { .. })
+        ));
+    }
+
+    #[test]
+    fn decode_should_reject_truncated_payload_without_allocating_declared_length() {
+        let header = MessageHeader {
+            chain: Chain::Mainnet,
+            command_name: *b"unknown\x00\x00\x00\x00\x00",
+            payload_len: MAX_PAYLOAD_SIZE,
+            checksum: [0u8; 4],
+        };
+        let mut bytes = header.encode().unwrap();
+        bytes.extend([1u8; 10]);
+
+        let error = RawMessage::decode_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<codec::CodecError>(),
+            Some(&codec::CodecError::UnexpectedEof)
+        );
+    }
 
-        let message = M::decode(&mut encoded_message.as_slice())?;
+    #[test]
+    fn decode_should_expose_header_on_checksum_mismatch() {
+        let mut bytes = Message::new(Chain::Mainnet, PingMessage { nonce: 7 })
+            .encode()
+            .unwrap();
+        let payload_checksum = checksum(&bytes[MESSAGE_HEADER_SIZE..]);
+        bytes[20..MESSAGE_HEADER_SIZE].copy_from_slice(&[0xde, 0xad, 0xbe, 0xef]);
 
-        Ok(Self { chain, message })
+        let error = Message::<PingMessage>::decode_bytes(&bytes).unwrap_err();
+        let Some(MessageDecodeError::CheksumIsInvalid { header, computed }) =
+            error.downcast_ref::<MessageDecodeError>()
+        else {
+            panic!("unexpected error {error}");
+        };
+        assert_eq!(Command::from_bytes(header.command_name), Command::Ping);
+        assert_eq!(header.payload_len, 8);
+        assert_eq!(header.checksum, [0xde, 0xad, 0xbe, 0xef]);
+        assert_eq!(*computed, payload_checksum);
+        assert!(
+            error.to_string().contains("ping message of 8 bytes"),
+            "{error}"
+        );
     }
 }
@@ -0,0 +1,216 @@
+use crate::{
+    constants::MAX_ADDR_ENTRIES,
+    messages::{
+        codec::{read_items_with_cap_or, read_vec_with_cap_or, CodecError, VarInt},
+        types::version::NetworkAddress,
+        Command, CommandName, Decode, Encode,
+    },
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    io::{Read, Write},
+    net::SocketAddr,
+    time::{Duration, SystemTime},
+};
+
+/// Network address of a node together with the last time it was seen
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#addr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AddrEntry {
+    /// Unix time at which the node was last seen
+    pub time: u32,
+    /// Network address of the node
+    pub address: NetworkAddress,
+}
+
+impl AddrEntry {
+    /// Time the node was last seen along with the socket address to reconnect to it at, if it has a connectable one
+    pub fn to_peer(&self) -> Option<(SystemTime, SocketAddr)> {
+        let last_seen = SystemTime::UNIX_EPOCH + Duration::from_secs(self.time as u64);
+        Some((last_seen, self.address.to_socket_addr()?))
+    }
+}
+
+impl Encode for AddrEntry {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(30);
+        buffer.write_u32::<LittleEndian>(self.time)?;
+        buffer.write_all(&self.address.encode()?)?;
+        Ok(buffer)
+    }
+}
+
+impl Decode for AddrEntry {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let time = bytes.read_u32::<LittleEndian>()?;
+        let address = NetworkAddress::decode(bytes)?;
+        Ok(Self { time, address })
+    }
+}
+
+/// The “addr” message relays connection information for peers on the network.
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#addr
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddrMessage {
+    /// Addresses of other nodes on the network (at most 1000 entries)
+    pub addresses: Vec<AddrEntry>,
+}
+
+impl CommandName for AddrMessage {
+    fn command() -> Command {
+        Command::Addr
+    }
+}
+
+impl AddrMessage {
+    /// Decodes the entries of an `addr` payload one at a time, without collecting them
+    ///
+    /// The count is capped at [`MAX_ADDR_ENTRIES`] like in [`AddrMessage::decode`].
+    pub fn decode_items(reader: impl Read) -> impl Iterator<Item = crate::Result<AddrEntry>> {
+        read_items_with_cap_or(
+            reader,
+            MAX_ADDR_ENTRIES,
+            |count, _| CodecError::TooManyAddresses(count),
+            AddrEntry::decode,
+        )
+    }
+
+    /// Iterates over the connectable peers of the message, see [`AddrEntry::to_peer`]
+    pub fn iter(&self) -> impl Iterator<Item = (SystemTime, SocketAddr)> + '_ {
+        self.addresses.iter().filter_map(AddrEntry::to_peer)
+    }
+}
+
+impl IntoIterator for AddrMessage {
+    type Item = (SystemTime, SocketAddr);
+    type IntoIter = std::iter::FilterMap<
+        std::vec::IntoIter<AddrEntry>,
+        fn(AddrEntry) -> Option<(SystemTime, SocketAddr)>,
+    >;
+
+    /// Turns the message into its connectable peers, skipping entries without a usable address
+    fn into_iter(self) -> Self::IntoIter {
+        self.addresses
+            .into_iter()
+            .filter_map(|entry| entry.to_peer())
+    }
+}
+
+impl Encode for AddrMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(9 + 30 * self.addresses.len());
+        buffer.write_all(&VarInt(self.addresses.len() as u64).encode()?)?;
+        for address in &self.addresses {
+            buffer.write_all(&address.encode()?)?;
+        }
+        Ok(buffer)
+    }
+}
+
+impl Decode for AddrMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let addresses = read_vec_with_cap_or(
+            bytes,
+            MAX_ADDR_ENTRIES,
+            |count, _| CodecError::TooManyAddresses(count),
+            AddrEntry::decode,
+        )?;
+        Ok(Self { addresses })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{codec::CodecError, types::version::Services};
+    use std::net::{Ipv4Addr, Ipv6Addr};
+
+    #[test]
+    fn decode_should_work() {
+        // Hexdump example of addr message taken from https://en.bitcoin.it/wiki/Protocol_documentation#addr
+        let bytes =
+            hex::decode("01E215104D010000000000000000000000000000000000FFFF0A000001208D").unwrap();
+
+        assert_eq!(
+            AddrMessage::decode_bytes(&bytes).unwrap(),
+            AddrMessage {
+                addresses: vec![AddrEntry {
+                    time: 1292899810,
+                    address: NetworkAddress {
+                        services: Services::NODE_NETWORK,
+                        ip_address: Ipv6Addr::from([
+                            0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255, 10, 0, 0, 1
+                        ]),
+                        port: 8333,
+                    },
+                }],
+            }
+        );
+    }
+
+    #[test]
+    fn into_iter_should_yield_connectable_socket_addresses() {
+        let entry = |time, ip_address, port| AddrEntry {
+            time,
+            address: NetworkAddress::from_ipv6(Services::NODE_NETWORK, ip_address, port),
+        };
+        let message = AddrMessage {
+            addresses: vec![
+                entry(
+                    1292899810,
+                    Ipv4Addr::new(10, 0, 0, 1).to_ipv6_mapped(),
+                    8333,
+                ),
+                entry(1292899811, "2001:db8::1".parse().unwrap(), 18333),
+                // Advertised by nodes that don't reveal where they listen
+                entry(1292899812, Ipv4Addr::UNSPECIFIED.to_ipv6_mapped(), 0),
+            ],
+        };
+
+        let expected = vec![
+            (
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1292899810),
+                "10.0.0.1:8333".parse().unwrap(),
+            ),
+            (
+                SystemTime::UNIX_EPOCH + Duration::from_secs(1292899811),
+                "[2001:db8::1]:18333".parse().unwrap(),
+            ),
+        ];
+        assert_eq!(message.iter().collect::<Vec<_>>(), expected);
+        assert_eq!(message.into_iter().collect::<Vec<_>>(), expected);
+    }
+
+    #[test]
+    fn decode_items_should_stop_at_first_error() {
+        // Claims two entries but only carries one
+        let bytes =
+            hex::decode("02E215104D010000000000000000000000000000000000FFFF0A000001208D").unwrap();
+
+        let mut items = AddrMessage::decode_items(bytes.as_slice());
+        assert_eq!(items.next().unwrap().unwrap().time, 1292899810);
+        assert_eq!(
+            items
+                .next()
+                .unwrap()
+                .unwrap_err()
+                .downcast_ref::<CodecError>(),
+            Some(&CodecError::UnexpectedEof)
+        );
+        assert!(items.next().is_none());
+    }
+
+    #[test]
+    fn decode_should_reject_too_many_addresses() {
+        // Only the count is provided, so the error must be raised before any entry is read
+        let bytes = VarInt(MAX_ADDR_ENTRIES + 1).encode().unwrap();
+
+        let error = AddrMessage::decode_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::TooManyAddresses(MAX_ADDR_ENTRIES + 1))
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use crate::{
+    constants::{MAX_ADDRV2_ADDRESS_SIZE, MAX_ADDR_ENTRIES},
+    messages::{
+        codec::{read_exact_len, read_vec_with_cap_or, CodecError, VarInt},
+        types::version::Services,
+        Command, CommandName, Decode, Encode,
+    },
+};
+use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::{
+    io::{Read, Write},
+    net::{Ipv4Addr, Ipv6Addr},
+};
+
+/// Address of a node on one of the networks supported by `addrv2`
+///
+/// Source: https://github.com/bitcoin/bips/blob/master/bip-0155.mediawiki
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetworkV2Address {
+    Ipv4(Ipv4Addr),
+    Ipv6(Ipv6Addr),
+    /// Tor v2 onion service (deprecated)
+    TorV2([u8; 10]),
+    /// Tor v3 onion service public key
+    TorV3([u8; 32]),
+    /// SHA256 hash of the I2P destination
+    I2p([u8; 32]),
+    /// Cjdns overlay network address
+    Cjdns(Ipv6Addr),
+    /// Address on a network this crate doesn't know about, which BIP155 requires to be skipped rather than rejected
+    Unknown {
+        network_id: u8,
+        address: Vec<u8>,
+    },
+}
+
+impl NetworkV2Address {
+    /// Network id and address bytes as they appear on the wire
+    fn to_parts(&self) -> (u8, Vec<u8>) {
+        match self {
+            NetworkV2Address::Ipv4(address) => (1, address.octets().to_vec()),
+            NetworkV2Address::Ipv6(address) => (2, address.octets().to_vec()),
+            NetworkV2Address::TorV2(address) => (3, address.to_vec()),
+            NetworkV2Address::TorV3(address) => (4, address.to_vec()),
+            NetworkV2Address::I2p(address) => (5, address.to_vec()),
+            NetworkV2Address::Cjdns(address) => (6, address.octets().to_vec()),
+            NetworkV2Address::Unknown {
+                network_id,
+                address,
+            } => (*network_id, address.clone()),
+        }
+    }
+
+    fn from_parts(network_id: u8, address: Vec<u8>) -> crate::Result<Self> {
+        Ok(match network_id {
+            1 => NetworkV2Address::Ipv4(fixed_len(network_id, &address)?.into()),
+            2 => NetworkV2Address::Ipv6(fixed_len(network_id, &address)?.into()),
+            3 => NetworkV2Address::TorV2(fixed_len(network_id, &address)?),
+            4 => NetworkV2Address::TorV3(fixed_len(network_id, &address)?),
+            5 => NetworkV2Address::I2p(fixed_len(network_id, &address)?),
+            6 => NetworkV2Address::Cjdns(fixed_len(network_id, &address)?.into()),
+            _ => NetworkV2Address::Unknown {
+                network_id,
+                address,
+            },
+        })
+    }
+}
+
+/// Checks that an address of network `network_id` has the length mandated for it by BIP155
+fn fixed_len<const N: usize>(network_id: u8, address: &[u8]) -> Result<[u8; N], CodecError> {
+    address
+        .try_into()
+        .map_err(|_| CodecError::InvalidAddressLength {
+            network_id,
+            len: address.len() as u64,
+        })
+}
+
+/// Address of a node together with the last time it was seen and the services it supports, in the `addrv2` layout
+///
+/// Unlike [`crate::messages::types::addr::AddrEntry`], the services are encoded as a CompactSize and the address is
+/// prefixed by its network id and length.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddrV2Entry {
+    /// Unix time at which the node was last seen
+    pub time: u32,
+    /// Services supported by the node
+    pub services: Services,
+    /// Address of the node
+    pub addr: NetworkV2Address,
+    /// Port number of the node
+    pub port: u16,
+}
+
+impl Encode for AddrV2Entry {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let (network_id, address) = self.addr.to_parts();
+        let mut buffer = Vec::with_capacity(4 + 9 + 1 + 3 + address.len() + 2);
+        buffer.write_u32::<LittleEndian>(self.time)?;
+        buffer.write_all(&VarInt(self.services.bits()).encode()?)?;
+        buffer.write_u8(network_id)?;
+        buffer.write_all(&VarInt(address.len() as u64).encode()?)?;
+        buffer.write_all(&address)?;
+        buffer.write_u16::<BigEndian>(self.port)?;
+        Ok(buffer)
+    }
+}
+
+impl Decode for AddrV2Entry {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let time = bytes.read_u32::<LittleEndian>()?;
+        let VarInt(services) = VarInt::decode(bytes)?;
+        let network_id = bytes.read_u8()?;
+        let VarInt(address_len) = VarInt::decode(bytes)?;
+        if address_len > MAX_ADDRV2_ADDRESS_SIZE {
+            Err(CodecError::InvalidAddressLength {
+                network_id,
+                len: address_len,
+            })?
+        }
+        let address = read_exact_len(bytes, address_len)?;
+        let port = bytes.read_u16::<BigEndian>()?;
+
+        Ok(Self {
+            time,
+            services: Services::from_bits_truncate(services),
+            addr: NetworkV2Address::from_parts(network_id, address)?,
+            port,
+        })
+    }
+}
+
+/// The “addrv2” message relays connection information for peers on the network, including ones that are not reachable
+/// over IPv4 or IPv6. It is only sent to peers that sent `sendaddrv2` before their verack.
+///
+/// Source: https://github.com/bitcoin/bips/blob/master/bip-0155.mediawiki
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AddrV2Message {
+    /// Addresses of other nodes on the network (at most 1000 entries)
+    pub addresses: Vec<AddrV2Entry>,
+}
+
+impl CommandName for AddrV2Message {
+    fn command() -> Command {
+        Command::AddrV2
+    }
+}
+
+impl Encode for AddrV2Message {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(9 + 30 * self.addresses.len());
+        buffer.write_all(&VarInt(self.addresses.len() as u64).encode()?)?;
+        for address in &self.addresses {
+            buffer.write_all(&address.encode()?)?;
+        }
+        Ok(buffer)
+    }
+}
+
+impl Decode for AddrV2Message {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let addresses = read_vec_with_cap_or(
+            bytes,
+            MAX_ADDR_ENTRIES,
+            |count, _| CodecError::TooManyAddresses(count),
+            AddrV2Entry::decode,
+        )?;
+        Ok(Self { addresses })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_should_work_with_mixed_networks() {
+        let bytes = hex::decode(concat!(
+            "03",
+            // IPv4 entry: time, services (NODE_NETWORK | NODE_WITNESS), network id, address, port 8333
+            "5a5e8b65",
+            "09",
+            "01",
+            "04",
+            "01020304",
+            "208d",
+            // IPv6 entry with services encoded on 3 bytes (NODE_NETWORK_LIMITED | NODE_WITNESS)
+            "5b5e8b65",
+            "fd0804",
+            "02",
+            "10",
+            "20010db8000000000000000000000001",
+            "479d",
+            // Tor v3 entry without services
+            "5c5e8b65",
+            "00",
+            "04",
+            "20",
+            "0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20",
+            "208d",
+        ))
+        .unwrap();
+
+        let message = AddrV2Message::decode_bytes(&bytes).unwrap();
+        assert_eq!(
+            message.addresses,
+            vec![
+                AddrV2Entry {
+                    time: 0x658b5e5a,
+                    services: Services::NODE_NETWORK | Services::NODE_WITNESS,
+                    addr: NetworkV2Address::Ipv4(Ipv4Addr::new(1, 2, 3, 4)),
+                    port: 8333,
+                },
+                AddrV2Entry {
+                    time: 0x658b5e5b,
+                    services: Services::NODE_NETWORK_LIMITED | Services::NODE_WITNESS,
+                    addr: NetworkV2Address::Ipv6("2001:db8::1".parse().unwrap()),
+                    port: 18333,
+                },
+                AddrV2Entry {
+                    time: 0x658b5e5c,
+                    services: Services::UNNAMED,
+                    addr: NetworkV2Address::TorV3(std::array::from_fn(|i| i as u8 + 1)),
+                    port: 8333,
+                },
+            ]
+        );
+        assert_eq!(message.encode().unwrap(), bytes);
+    }
+
+    #[test]
+    fn decode_should_reject_address_length_not_matching_network() {
+        // IPv4 network id with a 5-byte address
+        let bytes = hex::decode("01000000000001050102030405208d").unwrap();
+        let error = AddrV2Message::decode_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::InvalidAddressLength {
+                network_id: 1,
+                len: 5
+            })
+        );
+    }
+
+    /// Encodes a single-entry `addrv2` payload with `len` address bytes declared for network `network_id`
+    fn single_entry(network_id: u8, len: usize) -> Vec<u8> {
+        let mut bytes = hex::decode("010000000000").unwrap();
+        bytes.push(network_id);
+        bytes.extend(VarInt(len as u64).encode().unwrap());
+        bytes.extend(vec![0xab; len]);
+        bytes.extend([0x20, 0x8d]);
+        bytes
+    }
+
+    #[test]
+    fn decode_should_validate_address_length_of_every_known_network() {
+        for (network_id, expected_len) in [(1, 4), (2, 16), (3, 10), (4, 32), (5, 32), (6, 16)] {
+            let message = AddrV2Message::decode_bytes(single_entry(network_id, expected_len))
+                .unwrap_or_else(|error| panic!("network {network_id}: {error}"));
+            assert_eq!(
+                message.addresses[0].addr.to_parts(),
+                (network_id, vec![0xab; expected_len])
+            );
+
+            for len in [0, expected_len - 1, expected_len + 1] {
+                let error = AddrV2Message::decode_bytes(single_entry(network_id, len)).unwrap_err();
+                assert_eq!(
+                    error.downcast_ref::<CodecError>(),
+                    Some(&CodecError::InvalidAddressLength {
+                        network_id,
+                        len: len as u64
+                    }),
+                    "network {network_id} with {len} bytes"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decode_should_keep_unknown_network_address_up_to_size_cap() {
+        let len = MAX_ADDRV2_ADDRESS_SIZE as usize;
+        let message = AddrV2Message::decode_bytes(single_entry(42, len)).unwrap();
+        assert_eq!(
+            message.addresses[0].addr,
+            NetworkV2Address::Unknown {
+                network_id: 42,
+                address: vec![0xab; len]
+            }
+        );
+        assert_eq!(message.addresses[0].port, 8333);
+
+        let error = AddrV2Message::decode_bytes(single_entry(42, len + 1)).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::InvalidAddressLength {
+                network_id: 42,
+                len: len as u64 + 1
+            })
+        );
+    }
+}
@@ -0,0 +1,63 @@
+use crate::messages::{codec::Hash256, Command, CommandName, Decode, Encode};
+use std::io::{Read, Write};
+
+/// The “blocktxn” message sends the transactions requested by a `getblocktxn` message. Defined in
+/// [BIP152](https://github.com/bitcoin/bips/blob/master/bip-0152.mediawiki).
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#blocktxn
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockTxnMessage {
+    /// Hash of the block the transactions belong to
+    pub block_hash: Hash256,
+    /// CompactSize-prefixed list of the requested transactions, kept in their raw serialized form
+    pub txs_raw: Vec<u8>,
+}
+
+impl CommandName for BlockTxnMessage {
+    fn command() -> Command {
+        Command::BlockTxn
+    }
+}
+
+impl Encode for BlockTxnMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(32 + self.txs_raw.len());
+        buffer.write_all(&self.block_hash.0)?;
+        buffer.write_all(&self.txs_raw)?;
+        Ok(buffer)
+    }
+}
+
+impl Decode for BlockTxnMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let block_hash = Hash256::decode(bytes)?;
+
+        let mut txs_raw = Vec::new();
+        bytes.read_to_end(&mut txs_raw)?;
+
+        Ok(Self {
+            block_hash,
+            txs_raw,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_should_round_trip() {
+        let block_txn_message = BlockTxnMessage {
+            block_hash: Hash256([9u8; 32]),
+            txs_raw: vec![1, 0xde, 0xad, 0xbe, 0xef],
+        };
+
+        let bytes = block_txn_message.encode().unwrap();
+        assert_eq!(bytes.len(), 37);
+        assert_eq!(
+            BlockTxnMessage::decode_bytes(&bytes).unwrap(),
+            block_txn_message
+        );
+    }
+}
@@ -0,0 +1,234 @@
+use crate::{
+    constants::MAX_PAYLOAD_SIZE,
+    messages::{
+        codec::{read_vec_with_cap, CodecError, VarInt},
+        types::headers::BlockHeader,
+        Command, CommandName, Decode, Encode,
+    },
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{self, Read, Write};
+
+/// The “cmpctblock” message announces a block as a compact block, from which the receiving node reconstructs the block
+/// out of the transactions in its mempool. Defined in [BIP152](https://github.com/bitcoin/bips/blob/master/bip-0152.mediawiki).
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#cmpctblock
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CmpctBlockMessage {
+    /// Header of the block
+    pub header: BlockHeader,
+    /// Nonce salting the short transaction IDs
+    pub nonce: u64,
+    /// Short IDs of the transactions that the receiving node is expected to have, in block order
+    pub short_ids: Vec<[u8; 6]>,
+    /// Strictly increasing indexes within the block of the transactions sent in full (usually only the coinbase),
+    /// along with the raw serialized transactions
+    ///
+    /// On the wire, every index after the first is encoded as its difference to the previous index minus one.
+    pub prefilled_txs: Vec<(u64, Vec<u8>)>,
+}
+
+impl CommandName for CmpctBlockMessage {
+    fn command() -> Command {
+        Command::CmpctBlock
+    }
+}
+
+impl Encode for CmpctBlockMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let prefilled_len: usize = self.prefilled_txs.iter().map(|(_, tx)| 9 + tx.len()).sum();
+        let mut buffer =
+            Vec::with_capacity(80 + 8 + 9 + 6 * self.short_ids.len() + 9 + prefilled_len);
+        buffer.write_all(&self.header.encode()?)?;
+        buffer.write_u64::<LittleEndian>(self.nonce)?;
+        buffer.write_all(&VarInt(self.short_ids.len() as u64).encode()?)?;
+        for short_id in &self.short_ids {
+            buffer.write_all(short_id)?;
+        }
+
+        buffer.write_all(&VarInt(self.prefilled_txs.len() as u64).encode()?)?;
+        let mut previous_index: Option<u64> = None;
+        for (index, tx) in &self.prefilled_txs {
+            let delta = match previous_index {
+                None => *index,
+                Some(previous_index) if *index > previous_index => index - previous_index - 1,
+                Some(_) => Err(CodecError::IndexesNotIncreasing)?,
+            };
+            buffer.write_all(&VarInt(delta).encode()?)?;
+            buffer.write_all(tx)?;
+            previous_index = Some(*index);
+        }
+
+        Ok(buffer)
+    }
+}
+
+impl Decode for CmpctBlockMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let header = BlockHeader::decode(bytes)?;
+        let nonce = bytes.read_u64::<LittleEndian>()?;
+        let short_ids = read_vec_with_cap(bytes, MAX_PAYLOAD_SIZE as u64 / 6, |bytes| {
+            let mut short_id = [0u8; 6];
+            bytes.read_exact(&mut short_id)?;
+            Ok(short_id)
+        })?;
+
+        let mut previous_index: Option<u64> = None;
+        let prefilled_txs = read_vec_with_cap(bytes, MAX_PAYLOAD_SIZE as u64, |bytes| {
+            let VarInt(delta) = VarInt::decode(bytes)?;
+            let index = match previous_index {
+                None => delta,
+                Some(previous_index) => previous_index
+                    .checked_add(delta)
+                    .and_then(|index| index.checked_add(1))
+                    .ok_or(CodecError::IndexOverflow)?,
+            };
+            previous_index = Some(index);
+            Ok((index, read_raw_transaction(bytes)?))
+        })?;
+
+        Ok(Self {
+            header,
+            nonce,
+            short_ids,
+            prefilled_txs,
+        })
+    }
+}
+
+/// Reader that keeps a copy of every byte read through it
+struct RecordingReader<'a, R> {
+    inner: &'a mut R,
+    recorded: Vec<u8>,
+}
+
+impl<R: Read> Read for RecordingReader<'_, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.recorded.extend_from_slice(&buf[..read]);
+        Ok(read)
+    }
+}
+
+/// Reads a serialized transaction without interpreting it, returning its raw bytes
+///
+/// Transactions aren't length-prefixed, so the structure has to be walked to know where one ends: version, inputs,
+/// outputs, the witnesses of the inputs if the segwit marker is present, and lock time.
+fn read_raw_transaction(bytes: &mut impl Read) -> crate::Result<Vec<u8>> {
+    let mut reader = RecordingReader {
+        inner: bytes,
+        recorded: Vec::new(),
+    };
+    skip(&mut reader, 4)?;
+    let VarInt(mut input_count) = VarInt::decode(&mut reader)?;
+    let has_witness = input_count == 0;
+    if has_witness {
+        // The zero input count was the segwit marker, which is followed by the flag and the actual input count
+        match reader.read_u8()? {
+            1 => {}
+            flag => Err(CodecError::InvalidSegwitFlag(flag))?,
+        }
+        VarInt(input_count) = VarInt::decode(&mut reader)?;
+    }
+    for _ in 0..input_count {
+        // Previous output, followed by the signature script and the sequence number
+        skip(&mut reader, 36)?;
+        skip_var_bytes(&mut reader)?;
+        skip(&mut reader, 4)?;
+    }
+    let VarInt(output_count) = VarInt::decode(&mut reader)?;
+    for _ in 0..output_count {
+        // Value, followed by the public key script
+        skip(&mut reader, 8)?;
+        skip_var_bytes(&mut reader)?;
+    }
+    if has_witness {
+        for _ in 0..input_count {
+            let VarInt(item_count) = VarInt::decode(&mut reader)?;
+            for _ in 0..item_count {
+                skip_var_bytes(&mut reader)?;
+            }
+        }
+    }
+    skip(&mut reader, 4)?;
+    Ok(reader.recorded)
+}
+
+/// Reads and discards `len` bytes
+fn skip(reader: &mut impl Read, len: u64) -> crate::Result<()> {
+    if io::copy(&mut reader.take(len), &mut io::sink())? < len {
+        Err(CodecError::UnexpectedEof)?
+    }
+    Ok(())
+}
+
+/// Reads and discards CompactSize-prefixed bytes, such as a script
+fn skip_var_bytes(reader: &mut impl Read) -> crate::Result<()> {
+    let VarInt(len) = VarInt::decode(reader)?;
+    skip(reader, len)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Header of the genesis block
+    const GENESIS_HEADER_HEX: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+
+    /// Coinbase transaction of the genesis block
+    const GENESIS_COINBASE_HEX: &str = "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d04ffff001d0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e206272696e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a01000000434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000";
+
+    /// Segwit transaction spending a null outpoint with a single 2-byte witness item
+    const SEGWIT_TX_HEX: &str = concat!(
+        "02000000",
+        "0001",
+        "01",
+        "0000000000000000000000000000000000000000000000000000000000000000ffffffff",
+        "00",
+        "ffffffff",
+        "01",
+        "0000000000000000",
+        "00",
+        "0102abcd",
+        "00000000",
+    );
+
+    #[test]
+    fn decode_should_work() {
+        let encoded = hex::decode(format!(
+            "{GENESIS_HEADER_HEX}0807060504030201020102030405060a0b0c0d0e0f0200{GENESIS_COINBASE_HEX}01{SEGWIT_TX_HEX}"
+        ))
+        .unwrap();
+
+        let cmpct_block = CmpctBlockMessage::decode_bytes(&encoded).unwrap();
+        assert_eq!(
+            cmpct_block.header.hash().unwrap().to_hex_be(),
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+        assert_eq!(cmpct_block.nonce, 0x0102030405060708);
+        assert_eq!(
+            cmpct_block.short_ids,
+            vec![[1, 2, 3, 4, 5, 6], [0xa, 0xb, 0xc, 0xd, 0xe, 0xf]]
+        );
+        assert_eq!(
+            cmpct_block.prefilled_txs,
+            vec![
+                (0, hex::decode(GENESIS_COINBASE_HEX).unwrap()),
+                (2, hex::decode(SEGWIT_TX_HEX).unwrap()),
+            ]
+        );
+        assert_eq!(cmpct_block.encode().unwrap(), encoded);
+    }
+
+    #[test]
+    fn decode_should_reject_invalid_segwit_flag() {
+        let mut tx = hex::decode(SEGWIT_TX_HEX).unwrap();
+        tx[5] = 2;
+
+        let error = read_raw_transaction(&mut tx.as_slice()).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::InvalidSegwitFlag(2))
+        );
+    }
+}
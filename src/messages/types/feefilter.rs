@@ -0,0 +1,53 @@
+use crate::messages::{Command, CommandName, Decode, Encode};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+
+/// The “feefilter” message asks the receiving peer not to announce transactions whose fee rate is below the given one.
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#feefilter
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeeFilterMessage {
+    /// Minimum fee rate (in satoshis per kilobyte) of the transactions that should be announced
+    pub fee_rate: u64,
+}
+
+impl CommandName for FeeFilterMessage {
+    fn command() -> Command {
+        Command::FeeFilter
+    }
+}
+
+impl Encode for FeeFilterMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(8);
+        self.encode_to(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn encode_to(&self, writer: &mut dyn Write) -> crate::Result<()> {
+        writer.write_u64::<LittleEndian>(self.fee_rate)?;
+        Ok(())
+    }
+}
+
+impl Decode for FeeFilterMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        Ok(Self {
+            fee_rate: bytes.read_u64::<LittleEndian>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode_should_round_trip() {
+        // Hexdump example of feefilter message taken from https://developer.bitcoin.org/reference/p2p_networking.html#feefilter
+        let encoded = hex::decode("7cbd000000000000").unwrap();
+        let message = FeeFilterMessage::decode_bytes(&encoded).unwrap();
+        assert_eq!(message, FeeFilterMessage { fee_rate: 48508 });
+        assert_eq!(message.encode().unwrap(), encoded);
+    }
+}
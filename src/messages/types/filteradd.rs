@@ -0,0 +1,103 @@
+use crate::{
+    constants::MAX_FILTER_ADD_DATA_SIZE,
+    messages::{
+        codec::{CodecError, VarInt},
+        Command, CommandName, Decode, Encode,
+    },
+};
+use std::io::{Read, Write};
+
+/// The “filteradd” message tells the receiving peer to add a single element to a previously-set bloom filter.
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#filteradd
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilterAddMessage {
+    /// Element to add to the current filter (at most 520 bytes)
+    pub data: Vec<u8>,
+}
+
+impl CommandName for FilterAddMessage {
+    fn command() -> Command {
+        Command::FilterAdd
+    }
+}
+
+impl Encode for FilterAddMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let data_len = self.data.len() as u64;
+        if data_len > MAX_FILTER_ADD_DATA_SIZE {
+            Err(CodecError::FilterAddDataTooBig(data_len))?
+        }
+
+        let mut buffer = Vec::with_capacity(3 + self.data.len());
+        buffer.write_all(&VarInt(data_len).encode()?)?;
+        buffer.write_all(&self.data)?;
+        Ok(buffer)
+    }
+}
+
+impl Decode for FilterAddMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let VarInt(data_len) = VarInt::decode(bytes)?;
+        if data_len > MAX_FILTER_ADD_DATA_SIZE {
+            Err(CodecError::FilterAddDataTooBig(data_len))?
+        }
+
+        let mut data = vec![0u8; data_len as usize];
+        bytes.read_exact(&mut data)?;
+        Ok(Self { data })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_should_work() {
+        // Hexdump example of filteradd message taken from https://developer.bitcoin.org/reference/p2p_networking.html#filteradd
+        let filter_add_message = FilterAddMessage {
+            data: hex::decode("fdacf9b3eb077412e7a968d2e4f11b9a9dee312d666187ed77ee7d26af16cb0b")
+                .unwrap(),
+        };
+        assert_eq!(
+            filter_add_message.encode().unwrap(),
+            hex::decode("20fdacf9b3eb077412e7a968d2e4f11b9a9dee312d666187ed77ee7d26af16cb0b")
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn decode_should_work() {
+        let bytes =
+            hex::decode("20fdacf9b3eb077412e7a968d2e4f11b9a9dee312d666187ed77ee7d26af16cb0b")
+                .unwrap();
+        assert_eq!(
+            FilterAddMessage::decode_bytes(&bytes).unwrap().data.len(),
+            32
+        );
+    }
+
+    #[test]
+    fn encode_and_decode_should_reject_oversized_data() {
+        let filter_add_message = FilterAddMessage {
+            data: vec![0u8; MAX_FILTER_ADD_DATA_SIZE as usize + 1],
+        };
+        let error = filter_add_message.encode().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::FilterAddDataTooBig(
+                MAX_FILTER_ADD_DATA_SIZE + 1
+            ))
+        );
+
+        let bytes = VarInt(MAX_FILTER_ADD_DATA_SIZE + 1).encode().unwrap();
+        let error = FilterAddMessage::decode_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::FilterAddDataTooBig(
+                MAX_FILTER_ADD_DATA_SIZE + 1
+            ))
+        );
+    }
+}
@@ -0,0 +1,35 @@
+empty_message!(
+    /// The “filterclear” message tells the receiving peer to remove a previously-set bloom filter.
+    ///
+    /// Source: https://developer.bitcoin.org/reference/p2p_networking.html#filterclear
+    FilterClearMessage,
+    FilterClear
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{codec::CodecError, Decode, Encode};
+
+    #[test]
+    fn encode_should_work() {
+        assert_eq!(FilterClearMessage.encode().unwrap(), vec![])
+    }
+
+    #[test]
+    fn decode_should_work() {
+        assert_eq!(
+            FilterClearMessage::decode_bytes([]).unwrap(),
+            FilterClearMessage
+        );
+    }
+
+    #[test]
+    fn decode_should_reject_non_empty_payload() {
+        let error = FilterClearMessage::decode_bytes([0u8]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::NonEmptyPayload)
+        );
+    }
+}
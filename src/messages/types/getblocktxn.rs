@@ -0,0 +1,133 @@
+use crate::messages::{
+    codec::{CodecError, Hash256, VarInt},
+    Command, CommandName, Decode, Encode,
+};
+use std::io::{Read, Write};
+
+/// The “getblocktxn” message requests the transactions of a compact block that the receiving node could not
+/// reconstruct from its mempool. Defined in [BIP152](https://github.com/bitcoin/bips/blob/master/bip-0152.mediawiki).
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#getblocktxn
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetBlockTxnMessage {
+    /// Hash of the block whose transactions are requested
+    pub block_hash: Hash256,
+    /// Strictly increasing indexes of the requested transactions within the block
+    ///
+    /// On the wire, every index after the first is encoded as its difference to the previous index minus one.
+    pub indexes: Vec<u64>,
+}
+
+impl CommandName for GetBlockTxnMessage {
+    fn command() -> Command {
+        Command::GetBlockTxn
+    }
+}
+
+impl Encode for GetBlockTxnMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(32 + 9 + self.indexes.len());
+        buffer.write_all(&self.block_hash.0)?;
+        buffer.write_all(&VarInt(self.indexes.len() as u64).encode()?)?;
+
+        let mut previous_index: Option<u64> = None;
+        for &index in &self.indexes {
+            let delta = match previous_index {
+                None => index,
+                Some(previous_index) if index > previous_index => index - previous_index - 1,
+                Some(_) => Err(CodecError::IndexesNotIncreasing)?,
+            };
+            buffer.write_all(&VarInt(delta).encode()?)?;
+            previous_index = Some(index);
+        }
+
+        Ok(buffer)
+    }
+}
+
+impl Decode for GetBlockTxnMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let block_hash = Hash256::decode(bytes)?;
+
+        let VarInt(count) = VarInt::decode(bytes)?;
+        let mut indexes = Vec::new();
+        let mut previous_index: Option<u64> = None;
+        for _ in 0..count {
+            let VarInt(delta) = VarInt::decode(bytes)?;
+            let index = match previous_index {
+                None => delta,
+                Some(previous_index) => previous_index
+                    .checked_add(delta)
+                    .and_then(|index| index.checked_add(1))
+                    .ok_or(CodecError::IndexOverflow)?,
+            };
+            indexes.push(index);
+            previous_index = Some(index);
+        }
+
+        Ok(Self {
+            block_hash,
+            indexes,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_should_differentially_encode_indexes() {
+        let get_block_txn_message = GetBlockTxnMessage {
+            block_hash: Hash256([7u8; 32]),
+            indexes: vec![1, 2, 5, 300],
+        };
+
+        let bytes = get_block_txn_message.encode().unwrap();
+        assert_eq!(&bytes[..32], &[7u8; 32]);
+        // 300 - 5 - 1 = 294 needs the 3-byte CompactSize form
+        assert_eq!(&bytes[32..], &[4, 1, 0, 2, 0xfd, 0x26, 0x01]);
+    }
+
+    #[test]
+    fn encode_decode_should_round_trip() {
+        let get_block_txn_message = GetBlockTxnMessage {
+            block_hash: Hash256([7u8; 32]),
+            indexes: vec![0, 1, 2, 10, 11, 70000],
+        };
+
+        let bytes = get_block_txn_message.encode().unwrap();
+        assert_eq!(
+            GetBlockTxnMessage::decode_bytes(&bytes).unwrap(),
+            get_block_txn_message
+        );
+    }
+
+    #[test]
+    fn encode_should_reject_indexes_that_are_not_increasing() {
+        let get_block_txn_message = GetBlockTxnMessage {
+            block_hash: Hash256([7u8; 32]),
+            indexes: vec![3, 3],
+        };
+
+        let error = get_block_txn_message.encode().unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::IndexesNotIncreasing)
+        );
+    }
+
+    #[test]
+    fn decode_should_reject_overflowing_index() {
+        let mut bytes = vec![0u8; 32];
+        bytes.push(2);
+        bytes.extend_from_slice(&VarInt(u64::MAX).encode().unwrap());
+        bytes.push(0);
+
+        let error = GetBlockTxnMessage::decode_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::IndexOverflow)
+        );
+    }
+}
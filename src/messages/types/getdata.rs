@@ -0,0 +1,58 @@
+use crate::messages::{
+    types::inv::{decode_inventory, encode_inventory, encode_inventory_to, InventoryVector},
+    Command, CommandName, Decode, Encode,
+};
+use std::io::{Read, Write};
+
+/// The “getdata” message requests one or more data objects from another node.
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#getdata
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetDataMessage {
+    /// Inventory vectors of the requested objects (at most 50000 entries)
+    pub inventory: Vec<InventoryVector>,
+}
+
+impl CommandName for GetDataMessage {
+    fn command() -> Command {
+        Command::GetData
+    }
+}
+
+impl Encode for GetDataMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        encode_inventory(&self.inventory)
+    }
+
+    fn encode_to(&self, writer: &mut dyn Write) -> crate::Result<()> {
+        encode_inventory_to(&self.inventory, writer)
+    }
+}
+
+impl Decode for GetDataMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        Ok(Self {
+            inventory: decode_inventory(bytes)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        constants::MAX_INV_ENTRIES,
+        messages::codec::{CodecError, VarInt},
+    };
+
+    #[test]
+    fn decode_should_reject_too_many_inventory_vectors() {
+        let bytes = VarInt(MAX_INV_ENTRIES + 1).encode().unwrap();
+
+        let error = GetDataMessage::decode_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::TooManyInventoryVectors(MAX_INV_ENTRIES + 1))
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use crate::{
+    constants::MAX_LOCATOR_HASHES,
+    messages::{
+        codec::{read_vec_with_cap, Hash256, VarInt},
+        Command, CommandName, Decode, Encode,
+    },
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+
+/// The “getheaders” message requests a `headers` message that provides block headers starting from a particular
+/// point in the block chain.
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#getheaders
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetHeadersMessage {
+    /// Protocol version of the transmitting node
+    pub version: u32,
+    /// Hashes of blocks known to the transmitting node, from the tip of its chain backwards
+    pub block_locator_hashes: Vec<Hash256>,
+    /// Hash of the last header to return, or all zeroes to request as many headers as possible
+    pub hash_stop: Hash256,
+}
+
+impl CommandName for GetHeadersMessage {
+    fn command() -> Command {
+        Command::GetHeaders
+    }
+}
+
+impl Encode for GetHeadersMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(4 + 9 + 32 * (self.block_locator_hashes.len() + 1));
+        buffer.write_u32::<LittleEndian>(self.version)?;
+        buffer.write_all(&VarInt(self.block_locator_hashes.len() as u64).encode()?)?;
+        for hash in &self.block_locator_hashes {
+            buffer.write_all(&hash.0)?;
+        }
+        buffer.write_all(&self.hash_stop.0)?;
+        Ok(buffer)
+    }
+}
+
+impl Decode for GetHeadersMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let version = bytes.read_u32::<LittleEndian>()?;
+        let block_locator_hashes = read_vec_with_cap(bytes, MAX_LOCATOR_HASHES, Hash256::decode)?;
+        let hash_stop = Hash256::decode(bytes)?;
+
+        Ok(Self {
+            version,
+            block_locator_hashes,
+            hash_stop,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::codec::CodecError;
+
+    #[test]
+    fn encode_decode_should_round_trip() {
+        let get_headers_message = GetHeadersMessage {
+            version: 70015,
+            block_locator_hashes: vec![Hash256([1u8; 32]), Hash256([2u8; 32])],
+            hash_stop: Hash256::default(),
+        };
+
+        let bytes = get_headers_message.encode().unwrap();
+        assert_eq!(bytes.len(), 4 + 1 + 3 * 32);
+        assert_eq!(
+            GetHeadersMessage::decode_bytes(&bytes).unwrap(),
+            get_headers_message
+        );
+    }
+
+    #[test]
+    fn decode_should_reject_too_many_locator_hashes() {
+        let mut bytes = 70015u32.to_le_bytes().to_vec();
+        bytes.extend_from_slice(&VarInt(MAX_LOCATOR_HASHES + 1).encode().unwrap());
+
+        let error = GetHeadersMessage::decode_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::TooManyItems {
+                count: MAX_LOCATOR_HASHES + 1,
+                max: MAX_LOCATOR_HASHES
+            })
+        );
+    }
+}
@@ -0,0 +1,88 @@
+use crate::{
+    constants::MAX_GETUTXOS_OUTPOINTS,
+    messages::{
+        codec::{read_vec_with_cap, CodecError, Hash256, VarInt},
+        Command, CommandName, Decode, Encode,
+    },
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+
+/// The “getutxos” message asks a peer advertising `NODE_GETUTXO` whether the given outputs are unspent.
+///
+/// Source: https://github.com/bitcoin/bips/blob/master/bip-0064.mediawiki
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GetUtxosMessage {
+    /// Whether outputs created and spent by transactions in the peer's mempool should be taken into account
+    pub check_mempool: bool,
+    /// Outputs to look up, each as the txid and the index of the output in that transaction
+    pub outpoints: Vec<(Hash256, u32)>,
+}
+
+impl CommandName for GetUtxosMessage {
+    fn command() -> Command {
+        Command::GetUtxos
+    }
+}
+
+impl Encode for GetUtxosMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(10 + 36 * self.outpoints.len());
+        buffer.write_u8(self.check_mempool.into())?;
+        buffer.write_all(&VarInt(self.outpoints.len() as u64).encode()?)?;
+        for (txid, index) in &self.outpoints {
+            buffer.write_all(&txid.0)?;
+            buffer.write_u32::<LittleEndian>(*index)?;
+        }
+        Ok(buffer)
+    }
+}
+
+impl Decode for GetUtxosMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let check_mempool = match bytes.read_u8()? {
+            0 => false,
+            1 => true,
+            value => Err(CodecError::InvalidBool(value))?,
+        };
+        let outpoints = read_vec_with_cap(bytes, MAX_GETUTXOS_OUTPOINTS, |bytes| {
+            let txid = Hash256::decode(bytes)?;
+            Ok((txid, bytes.read_u32::<LittleEndian>()?))
+        })?;
+        Ok(Self {
+            check_mempool,
+            outpoints,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode_should_round_trip() {
+        let message = GetUtxosMessage {
+            check_mempool: true,
+            outpoints: vec![(Hash256([0x11; 32]), 0), (Hash256([0x22; 32]), 3)],
+        };
+        let encoded = message.encode().unwrap();
+        assert_eq!(
+            hex::encode(&encoded),
+            format!("0102{}00000000{}03000000", "11".repeat(32), "22".repeat(32))
+        );
+        assert_eq!(GetUtxosMessage::decode_bytes(&encoded).unwrap(), message);
+    }
+
+    #[test]
+    fn decode_should_reject_too_many_outpoints() {
+        let error = GetUtxosMessage::decode_bytes([0x00, 0x65]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::TooManyItems {
+                count: 101,
+                max: MAX_GETUTXOS_OUTPOINTS
+            })
+        );
+    }
+}
@@ -0,0 +1,161 @@
+use crate::{
+    constants::MAX_HEADERS_ENTRIES,
+    crypto::double_sha256,
+    messages::{
+        codec::{read_vec_with_cap, CodecError, Hash256, VarInt},
+        Command, CommandName, Decode, Encode,
+    },
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+
+/// Header of a block, which commits to the block's transactions and links it to the previous block
+///
+/// Source: https://developer.bitcoin.org/reference/block_chain.html#block-headers
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BlockHeader {
+    /// Block version number indicating which set of validation rules to follow
+    pub version: i32,
+    /// Hash of the previous block's header
+    pub prev_block_hash: Hash256,
+    /// Merkle root of the block's transactions
+    pub merkle_root: Hash256,
+    /// Unix time at which the miner started hashing the header
+    pub timestamp: u32,
+    /// Target threshold of the header hash encoded in compact form
+    pub bits: u32,
+    /// Arbitrary number miners change to produce a hash below the target threshold
+    pub nonce: u32,
+}
+
+impl BlockHeader {
+    /// Hash of the header (see [`Hash256::to_hex_be`] for the form usually displayed by block explorers)
+    pub fn hash(&self) -> crate::Result<Hash256> {
+        Ok(Hash256(double_sha256(&self.encode()?)))
+    }
+}
+
+impl Encode for BlockHeader {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(80);
+        buffer.write_i32::<LittleEndian>(self.version)?;
+        buffer.write_all(&self.prev_block_hash.0)?;
+        buffer.write_all(&self.merkle_root.0)?;
+        buffer.write_u32::<LittleEndian>(self.timestamp)?;
+        buffer.write_u32::<LittleEndian>(self.bits)?;
+        buffer.write_u32::<LittleEndian>(self.nonce)?;
+        Ok(buffer)
+    }
+}
+
+impl Decode for BlockHeader {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let version = bytes.read_i32::<LittleEndian>()?;
+        let prev_block_hash = Hash256::decode(bytes)?;
+        let merkle_root = Hash256::decode(bytes)?;
+        let timestamp = bytes.read_u32::<LittleEndian>()?;
+        let bits = bytes.read_u32::<LittleEndian>()?;
+        let nonce = bytes.read_u32::<LittleEndian>()?;
+
+        Ok(Self {
+            version,
+            prev_block_hash,
+            merkle_root,
+            timestamp,
+            bits,
+            nonce,
+        })
+    }
+}
+
+/// The “headers” message sends block headers to a node which previously requested certain headers with a
+/// `getheaders` message.
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#headers
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HeadersMessage {
+    /// Block headers (at most 2000 entries)
+    pub headers: Vec<BlockHeader>,
+}
+
+impl CommandName for HeadersMessage {
+    fn command() -> Command {
+        Command::Headers
+    }
+}
+
+impl Encode for HeadersMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(9 + 81 * self.headers.len());
+        buffer.write_all(&VarInt(self.headers.len() as u64).encode()?)?;
+        for header in &self.headers {
+            buffer.write_all(&header.encode()?)?;
+            // Every header is followed by a transaction count, which is always 0
+            buffer.write_u8(0)?;
+        }
+        Ok(buffer)
+    }
+}
+
+impl Decode for HeadersMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let headers = read_vec_with_cap(bytes, MAX_HEADERS_ENTRIES, |bytes| {
+            let header = BlockHeader::decode(bytes)?;
+            if VarInt::decode(bytes)? != VarInt(0) {
+                Err(CodecError::HeadersContainTransactions)?
+            }
+            Ok(header)
+        })?;
+        Ok(Self { headers })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::codec::CodecError;
+
+    // Header of the genesis block
+    const GENESIS_BLOCK_HEADER: &str = "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c";
+
+    #[test]
+    fn block_header_hash_should_work() {
+        let header = BlockHeader::decode_bytes(hex::decode(GENESIS_BLOCK_HEADER).unwrap()).unwrap();
+        let hash = header.hash().unwrap();
+
+        assert_eq!(header.timestamp, 1231006505);
+        assert_eq!(
+            hash.to_hex_be(),
+            "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f"
+        );
+    }
+
+    #[test]
+    fn encode_decode_should_round_trip() {
+        let header = BlockHeader::decode_bytes(hex::decode(GENESIS_BLOCK_HEADER).unwrap()).unwrap();
+        let headers_message = HeadersMessage {
+            headers: vec![header, header],
+        };
+
+        let bytes = headers_message.encode().unwrap();
+        assert_eq!(bytes.len(), 1 + 2 * 81);
+        assert_eq!(
+            HeadersMessage::decode_bytes(&bytes).unwrap(),
+            headers_message
+        );
+    }
+
+    #[test]
+    fn decode_should_reject_too_many_headers() {
+        let bytes = VarInt(MAX_HEADERS_ENTRIES + 1).encode().unwrap();
+
+        let error = HeadersMessage::decode_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::TooManyItems {
+                count: MAX_HEADERS_ENTRIES + 1,
+                max: MAX_HEADERS_ENTRIES
+            })
+        );
+    }
+}
@@ -0,0 +1,259 @@
+use crate::{
+    constants::MAX_INV_ENTRIES,
+    messages::{
+        codec::{read_items_with_cap_or, read_vec_with_cap_or, CodecError, Hash256, VarInt},
+        Command, CommandName, Decode, Encode,
+    },
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+
+/// Type of the object identified by an inventory vector
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#data-messages
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InvType {
+    /// Any data with this number may be ignored
+    Error = 0,
+    /// Hash of a transaction
+    Tx = 1,
+    /// Hash of a block header
+    Block = 2,
+    /// Hash of a block header, used in `getdata` to request a `merkleblock` message
+    FilteredBlock = 3,
+    /// Hash of a block header, used in `getdata` to request a `cmpctblock` message
+    CmpctBlock = 4,
+    /// Witness txid of a transaction, announced and requested once both peers sent `wtxidrelay` (BIP339)
+    WitnessTxid = 5,
+    /// Hash of a transaction with witness data
+    WitnessTx = 0x40000001,
+    /// Hash of a block with witness data
+    WitnessBlock = 0x40000002,
+    /// Hash of a block with witness data, used in `getdata` to request a `merkleblock` message
+    FilteredWitnessBlock = 0x40000003,
+}
+
+impl TryFrom<u32> for InvType {
+    type Error = CodecError;
+
+    fn try_from(code: u32) -> Result<Self, CodecError> {
+        match code {
+            0 => Ok(InvType::Error),
+            1 => Ok(InvType::Tx),
+            2 => Ok(InvType::Block),
+            3 => Ok(InvType::FilteredBlock),
+            4 => Ok(InvType::CmpctBlock),
+            5 => Ok(InvType::WitnessTxid),
+            0x40000001 => Ok(InvType::WitnessTx),
+            0x40000002 => Ok(InvType::WitnessBlock),
+            0x40000003 => Ok(InvType::FilteredWitnessBlock),
+            unknown => Err(CodecError::UnknownInventoryType(unknown)),
+        }
+    }
+}
+
+/// Identifies a transaction or block by its type and hash
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#inv
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InventoryVector {
+    /// Type of the object being identified
+    pub inv_type: InvType,
+    /// Hash of the object
+    pub hash: Hash256,
+}
+
+impl Encode for InventoryVector {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(36);
+        self.encode_to(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn encode_to(&self, writer: &mut dyn Write) -> crate::Result<()> {
+        writer.write_u32::<LittleEndian>(self.inv_type as u32)?;
+        writer.write_all(&self.hash.0)?;
+        Ok(())
+    }
+}
+
+impl Decode for InventoryVector {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let inv_type = InvType::try_from(bytes.read_u32::<LittleEndian>()?)?;
+        let hash = Hash256::decode(bytes)?;
+        Ok(Self { inv_type, hash })
+    }
+}
+
+/// Encodes a CompactSize-prefixed list of inventory vectors, as used by `inv`, `getdata` and `notfound`
+pub(crate) fn encode_inventory(inventory: &[InventoryVector]) -> crate::Result<Vec<u8>> {
+    let mut buffer = Vec::with_capacity(9 + 36 * inventory.len());
+    encode_inventory_to(inventory, &mut buffer)?;
+    Ok(buffer)
+}
+
+/// Same as [`encode_inventory`], but writes the list to `writer`
+pub(crate) fn encode_inventory_to(
+    inventory: &[InventoryVector],
+    writer: &mut dyn Write,
+) -> crate::Result<()> {
+    writer.write_all(&VarInt(inventory.len() as u64).encode()?)?;
+    for inventory_vector in inventory {
+        inventory_vector.encode_to(writer)?;
+    }
+    Ok(())
+}
+
+/// Decodes a CompactSize-prefixed list of inventory vectors, rejecting counts above [`MAX_INV_ENTRIES`]
+pub(crate) fn decode_inventory(bytes: &mut impl Read) -> crate::Result<Vec<InventoryVector>> {
+    read_vec_with_cap_or(
+        bytes,
+        MAX_INV_ENTRIES,
+        |count, _| CodecError::TooManyInventoryVectors(count),
+        InventoryVector::decode,
+    )
+}
+
+/// The “inv” message transmits one or more inventories of objects known to the transmitting peer.
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#inv
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InvMessage {
+    /// Inventory vectors of the announced objects (at most 50000 entries)
+    pub inventory: Vec<InventoryVector>,
+}
+
+impl CommandName for InvMessage {
+    fn command() -> Command {
+        Command::Inv
+    }
+}
+
+impl InvMessage {
+    /// Decodes the inventory vectors of an `inv` payload one at a time, without collecting them
+    ///
+    /// The count is capped at [`MAX_INV_ENTRIES`] like in [`InvMessage::decode`].
+    pub fn decode_items(reader: impl Read) -> impl Iterator<Item = crate::Result<InventoryVector>> {
+        read_items_with_cap_or(
+            reader,
+            MAX_INV_ENTRIES,
+            |count, _| CodecError::TooManyInventoryVectors(count),
+            InventoryVector::decode,
+        )
+    }
+}
+
+impl Encode for InvMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        encode_inventory(&self.inventory)
+    }
+
+    fn encode_to(&self, writer: &mut dyn Write) -> crate::Result<()> {
+        encode_inventory_to(&self.inventory, writer)
+    }
+}
+
+impl Decode for InvMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        Ok(Self {
+            inventory: decode_inventory(bytes)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_decode_should_round_trip() {
+        let inv_message = InvMessage {
+            inventory: vec![
+                InventoryVector {
+                    inv_type: InvType::Tx,
+                    hash: Hash256([1u8; 32]),
+                },
+                InventoryVector {
+                    inv_type: InvType::WitnessBlock,
+                    hash: Hash256([2u8; 32]),
+                },
+            ],
+        };
+
+        let bytes = inv_message.encode().unwrap();
+        assert_eq!(bytes.len(), 1 + 2 * 36);
+        assert_eq!(InvMessage::decode_bytes(&bytes).unwrap(), inv_message);
+    }
+
+    #[test]
+    fn decode_items_should_yield_inventory_vectors_lazily() {
+        let mut bytes = VarInt(2).encode().unwrap();
+        for (inv_type, hash) in [
+            (InvType::Tx, Hash256([1u8; 32])),
+            (InvType::Block, Hash256([2u8; 32])),
+        ] {
+            bytes.extend(InventoryVector { inv_type, hash }.encode().unwrap());
+        }
+
+        let mut items = InvMessage::decode_items(bytes.as_slice());
+        let first = items.next().unwrap().unwrap();
+        assert_eq!(
+            (first.inv_type, first.hash),
+            (InvType::Tx, Hash256([1u8; 32]))
+        );
+        let second = items.next().unwrap().unwrap();
+        assert_eq!(
+            (second.inv_type, second.hash),
+            (InvType::Block, Hash256([2u8; 32]))
+        );
+        assert!(items.next().is_none());
+
+        let bytes = VarInt(MAX_INV_ENTRIES + 1).encode().unwrap();
+        let mut items = InvMessage::decode_items(bytes.as_slice());
+        assert!(matches!(
+            items
+                .next()
+                .unwrap()
+                .unwrap_err()
+                .downcast_ref::<CodecError>(),
+            Some(CodecError::TooManyInventoryVectors(_))
+        ));
+        assert!(items.next().is_none());
+    }
+
+    #[test]
+    fn decode_should_reject_too_many_inventory_vectors() {
+        // Only the count is provided, so the error must be raised before any entry is read
+        let bytes = VarInt(MAX_INV_ENTRIES + 1).encode().unwrap();
+
+        let error = InvMessage::decode_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::TooManyInventoryVectors(MAX_INV_ENTRIES + 1))
+        );
+    }
+
+    #[test]
+    fn decode_should_reject_unknown_inventory_type() {
+        let mut bytes = VarInt(1).encode().unwrap();
+        bytes.extend_from_slice(&6u32.to_le_bytes());
+        bytes.extend_from_slice(&[0u8; 32]);
+
+        let error = InvMessage::decode_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::UnknownInventoryType(6))
+        );
+    }
+
+    #[test]
+    fn inv_type_try_from_should_map_codes() {
+        assert_eq!(InvType::try_from(2), Ok(InvType::Block));
+        assert_eq!(InvType::try_from(5), Ok(InvType::WitnessTxid));
+        assert_eq!(InvType::try_from(0x40000001), Ok(InvType::WitnessTx));
+        assert_eq!(
+            InvType::try_from(6),
+            Err(CodecError::UnknownInventoryType(6))
+        );
+    }
+}
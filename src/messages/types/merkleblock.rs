@@ -0,0 +1,89 @@
+use crate::{
+    constants::MAX_PAYLOAD_SIZE,
+    messages::{
+        codec::{read_vec_with_cap, Hash256, VarInt},
+        types::headers::BlockHeader,
+        Command, CommandName, Decode, Encode,
+    },
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+
+/// The “merkleblock” message is a reply to a “getdata” message which requested a block using the inventory type
+/// `MSG_MERKLEBLOCK`. It is only part of the reply: if any matching transactions are found, they will be sent
+/// separately as “tx” messages.
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#merkleblock
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MerkleBlockMessage {
+    /// Header of the block
+    pub header: BlockHeader,
+    /// Number of transactions in the block (including ones that don't match the filter)
+    pub total_transactions: u32,
+    /// Transaction and merkle node hashes (at most one per transaction in the block)
+    pub hashes: Vec<Hash256>,
+    /// Sequence of bits, packed eight per byte, describing how to walk the partial merkle tree
+    pub flags: Vec<u8>,
+}
+
+impl CommandName for MerkleBlockMessage {
+    fn command() -> Command {
+        Command::MerkleBlock
+    }
+}
+
+impl Encode for MerkleBlockMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer =
+            Vec::with_capacity(80 + 4 + 9 + 32 * self.hashes.len() + 9 + self.flags.len());
+        buffer.write_all(&self.header.encode()?)?;
+        buffer.write_u32::<LittleEndian>(self.total_transactions)?;
+        buffer.write_all(&VarInt(self.hashes.len() as u64).encode()?)?;
+        for hash in &self.hashes {
+            buffer.write_all(&hash.0)?;
+        }
+        buffer.write_all(&VarInt(self.flags.len() as u64).encode()?)?;
+        buffer.write_all(&self.flags)?;
+        Ok(buffer)
+    }
+}
+
+impl Decode for MerkleBlockMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let header = BlockHeader::decode(bytes)?;
+        let total_transactions = bytes.read_u32::<LittleEndian>()?;
+        let hashes = read_vec_with_cap(bytes, total_transactions as u64, Hash256::decode)?;
+        let flags =
+            read_vec_with_cap(bytes, MAX_PAYLOAD_SIZE as u64, |bytes| Ok(bytes.read_u8()?))?;
+
+        Ok(Self {
+            header,
+            total_transactions,
+            hashes,
+            flags,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Hexdump example of merkleblock message taken from https://developer.bitcoin.org/reference/p2p_networking.html#merkleblock
+    const MERKLE_BLOCK_HEX: &str = "0100000082bb869cf3a793432a66e826e05a6fc37469f8efb7421dc880670100000000007f16c5962e8bd963659c793ce370d95f093bc7e367117b3c30c1f8fdd0d9728776381b4d4c86041b554b852907000000043612262624047ee87660be1a707519a443b1c1ce3d248cbfc6c15870f6c5daa2019f5b01d4195ecbc9398fbf3c3b1fa9bb3183301d7a1fb3bd174fcfa40a2b6541ed70551dd7e841883ab8f0b16bf04176b7d1480e4f0af9f3d4c3595768d06820d2a7bc994987302e5b1ac80fc425fe25f8b63169ea78e68fbaaefa59379bbf011d";
+
+    #[test]
+    fn decode_should_work() {
+        let encoded = hex::decode(MERKLE_BLOCK_HEX).unwrap();
+        let merkle_block = MerkleBlockMessage::decode_bytes(&encoded).unwrap();
+
+        assert_eq!(
+            merkle_block.header.hash().unwrap().to_hex_be(),
+            "000000000000b731f2eef9e8c63173adfb07e41bd53eb0ef0a6b720d6cb6dea4"
+        );
+        assert_eq!(merkle_block.total_transactions, 7);
+        assert_eq!(merkle_block.hashes.len(), 4);
+        assert_eq!(merkle_block.flags, vec![0x1d]);
+        assert_eq!(merkle_block.encode().unwrap(), encoded);
+    }
+}
@@ -1,4 +1,57 @@
 //! Module contains all the different types of messages
 
+/// Defines a message type that carries no payload
+macro_rules! empty_message {
+    ($(#[$meta:meta])* $name:ident, $command:ident) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+        pub struct $name;
+
+        impl $crate::messages::CommandName for $name {
+            fn command() -> $crate::messages::Command {
+                $crate::messages::Command::$command
+            }
+        }
+
+        impl $crate::messages::codec::Encode for $name {
+            fn encode(&self) -> crate::Result<Vec<u8>> {
+                Ok(vec![])
+            }
+        }
+
+        impl $crate::messages::codec::Decode for $name {
+            fn decode(bytes: &mut impl std::io::Read) -> crate::Result<Self> {
+                let mut buffer = [0u8; 1];
+                if bytes.read(&mut buffer)? != 0 {
+                    Err($crate::messages::codec::CodecError::NonEmptyPayload)?
+                }
+                Ok($name)
+            }
+        }
+    };
+}
+
+pub mod addr;
+pub mod addrv2;
+pub mod blocktxn;
+pub mod cmpctblock;
+pub mod feefilter;
+pub mod filteradd;
+pub mod filterclear;
+pub mod getblocktxn;
+pub mod getdata;
+pub mod getheaders;
+pub mod getutxos;
+pub mod headers;
+pub mod inv;
+pub mod merkleblock;
+pub mod ping;
+pub mod pong;
+pub mod reject;
+pub mod sendaddrv2;
+pub mod sendcmpct;
+pub mod sendheaders;
+pub mod utxos;
 pub mod verack;
 pub mod version;
+pub mod wtxidrelay;
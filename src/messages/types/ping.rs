@@ -0,0 +1,83 @@
+use crate::messages::{codec::CodecError, Command, CommandName, Decode, Encode};
+use byteorder::{LittleEndian, WriteBytesExt};
+use std::io::{Read, Write};
+
+/// The “ping” message helps confirm that the receiving peer is still connected.
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#ping
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PingMessage {
+    /// Random nonce that the peer echoes back in its pong message
+    ///
+    /// A nonce of 0 also stands for the legacy ping without a nonce, which nodes older than protocol version 60001
+    /// send (see [BIP31](https://github.com/bitcoin/bips/blob/master/bip-0031.mediawiki)).
+    pub nonce: u64,
+}
+
+impl CommandName for PingMessage {
+    fn command() -> Command {
+        Command::Ping
+    }
+}
+
+impl Encode for PingMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(8);
+        self.encode_to(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn encode_to(&self, writer: &mut dyn Write) -> crate::Result<()> {
+        writer.write_u64::<LittleEndian>(self.nonce)?;
+        Ok(())
+    }
+}
+
+impl Decode for PingMessage {
+    /// Accepts an empty payload as a legacy ping, decoded with a nonce of 0
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let mut nonce = Vec::with_capacity(8);
+        bytes.take(8).read_to_end(&mut nonce)?;
+        let nonce = match <[u8; 8]>::try_from(nonce.as_slice()) {
+            Ok(nonce) => u64::from_le_bytes(nonce),
+            Err(_) if nonce.is_empty() => 0,
+            Err(_) => Err(CodecError::UnexpectedEof)?,
+        };
+        Ok(Self { nonce })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode_should_round_trip() {
+        let ping_message = PingMessage {
+            nonce: 0x0123456789ABCDEF,
+        };
+        let encoded = ping_message.encode().unwrap();
+        assert_eq!(encoded, hex::decode("EFCDAB8967452301").unwrap());
+        assert_eq!(PingMessage::decode_bytes(encoded).unwrap(), ping_message);
+    }
+
+    #[test]
+    fn decode_should_accept_legacy_ping_without_nonce() {
+        assert_eq!(
+            PingMessage::decode_bytes([]).unwrap(),
+            PingMessage { nonce: 0 }
+        );
+        assert_eq!(
+            PingMessage::decode_bytes([0; 8]).unwrap(),
+            PingMessage { nonce: 0 }
+        );
+        // A zero nonce is still sent in the modern 8-byte form
+        assert_eq!(PingMessage { nonce: 0 }.encode().unwrap(), vec![0; 8]);
+
+        let error = PingMessage::decode_bytes([1, 2, 3]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::UnexpectedEof)
+        );
+    }
+}
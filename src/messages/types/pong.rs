@@ -0,0 +1,64 @@
+use crate::messages::{Command, CommandName, Decode, Encode};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+
+/// The “pong” message replies to a “ping” message, proving to the pinging node that the ponging node is still alive.
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#pong
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PongMessage {
+    /// Nonce of the ping message being replied to
+    pub nonce: u64,
+}
+
+impl CommandName for PongMessage {
+    fn command() -> Command {
+        Command::Pong
+    }
+}
+
+impl Encode for PongMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(8);
+        self.encode_to(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn encode_to(&self, writer: &mut dyn Write) -> crate::Result<()> {
+        writer.write_u64::<LittleEndian>(self.nonce)?;
+        Ok(())
+    }
+}
+
+impl Decode for PongMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        Ok(Self {
+            nonce: bytes.read_u64::<LittleEndian>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::codec::CodecError;
+
+    #[test]
+    fn encode_and_decode_should_round_trip() {
+        let pong_message = PongMessage {
+            nonce: 0x0123456789ABCDEF,
+        };
+        let encoded = pong_message.encode().unwrap();
+        assert_eq!(encoded, hex::decode("EFCDAB8967452301").unwrap());
+        assert_eq!(PongMessage::decode_bytes(encoded).unwrap(), pong_message);
+    }
+
+    #[test]
+    fn decode_should_reject_truncated_nonce() {
+        let error = PongMessage::decode_bytes([1, 2, 3]).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::UnexpectedEof)
+        );
+    }
+}
@@ -0,0 +1,190 @@
+use crate::{
+    constants::{MAX_REJECT_MESSAGE_LENGTH, MAX_REJECT_REASON_LENGTH},
+    messages::{
+        codec::{read_var_string, CodecError, VarInt},
+        Command, CommandName, Decode, Encode,
+    },
+};
+use byteorder::{ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+
+/// Code describing why a message was rejected
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#reject
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectCode {
+    /// Message could not be decoded
+    Malformed = 0x01,
+    /// Block or transaction is invalid
+    Invalid = 0x10,
+    /// Block version or message is no longer supported
+    Obsolete = 0x11,
+    /// Transaction is a duplicate of one already seen
+    Duplicate = 0x12,
+    /// Transaction is valid but not standard
+    Nonstandard = 0x40,
+    /// Output is below the dust threshold
+    Dust = 0x41,
+    /// Fee is too low to be relayed or mined
+    InsufficientFee = 0x42,
+    /// Block conflicts with a checkpoint
+    Checkpoint = 0x43,
+}
+
+impl TryFrom<u8> for RejectCode {
+    type Error = CodecError;
+
+    fn try_from(code: u8) -> Result<Self, Self::Error> {
+        match code {
+            0x01 => Ok(RejectCode::Malformed),
+            0x10 => Ok(RejectCode::Invalid),
+            0x11 => Ok(RejectCode::Obsolete),
+            0x12 => Ok(RejectCode::Duplicate),
+            0x40 => Ok(RejectCode::Nonstandard),
+            0x41 => Ok(RejectCode::Dust),
+            0x42 => Ok(RejectCode::InsufficientFee),
+            0x43 => Ok(RejectCode::Checkpoint),
+            unknown => Err(CodecError::UnknownRejectCode(unknown)),
+        }
+    }
+}
+
+/// The “reject” message informs the receiving node that one of its previous messages has been rejected.
+///
+/// Source: https://developer.bitcoin.org/reference/p2p_networking.html#reject
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RejectMessage {
+    /// Command name of the rejected message
+    pub message: String,
+    /// Code describing why the message was rejected
+    pub ccode: RejectCode,
+    /// Human-readable reason for the rejection
+    pub reason: String,
+    /// Extra data such as the hash of the rejected block or transaction (may be empty)
+    pub data: Vec<u8>,
+}
+
+impl CommandName for RejectMessage {
+    fn command() -> Command {
+        Command::Reject
+    }
+}
+
+impl Encode for RejectMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(
+            9 + self.message.len() + 1 + 9 + self.reason.len() + self.data.len(),
+        );
+        buffer.write_all(&VarInt(self.message.len() as u64).encode()?)?;
+        buffer.write_all(self.message.as_bytes())?;
+        buffer.write_u8(self.ccode as u8)?;
+        buffer.write_all(&VarInt(self.reason.len() as u64).encode()?)?;
+        buffer.write_all(self.reason.as_bytes())?;
+        buffer.write_all(&self.data)?;
+        Ok(buffer)
+    }
+}
+
+impl Decode for RejectMessage {
+    /// Fails if `message` is longer than [`MAX_REJECT_MESSAGE_LENGTH`] or `reason` is longer than
+    /// [`MAX_REJECT_REASON_LENGTH`]
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let message = read_var_string(bytes, MAX_REJECT_MESSAGE_LENGTH)?;
+        let ccode = RejectCode::try_from(bytes.read_u8()?)?;
+        let reason = read_var_string(bytes, MAX_REJECT_REASON_LENGTH)?;
+        let mut data = Vec::new();
+        bytes.read_to_end(&mut data)?;
+
+        Ok(Self {
+            message,
+            ccode,
+            reason,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_should_work() {
+        // Hexdump example of reject message taken from https://developer.bitcoin.org/reference/p2p_networking.html#reject
+        let bytes = hex::decode(
+            "02747812156261642d74786e732d696e707574732d7370656e74394c8b8ac0b3e9d47a2f3a7c8ae17c44cf2a11c9db6fd5ded1b3a26d0b1d7ac1",
+        )
+        .unwrap();
+
+        let reject_message = RejectMessage::decode_bytes(&bytes).unwrap();
+        assert_eq!(reject_message.message, "tx");
+        assert_eq!(reject_message.ccode, RejectCode::Duplicate);
+        assert_eq!(reject_message.reason, "bad-txns-inputs-spent");
+        assert_eq!(reject_message.data.len(), 32);
+        assert_eq!(reject_message.encode().unwrap(), bytes);
+    }
+
+    #[test]
+    fn decode_should_read_reason_longer_than_u8_up_to_cap() {
+        let reject_message = |reason_len: u64| RejectMessage {
+            message: "tx".to_string(),
+            ccode: RejectCode::Invalid,
+            reason: "r".repeat(reason_len as usize),
+            data: vec![],
+        };
+
+        let long = reject_message(MAX_REJECT_REASON_LENGTH);
+        let bytes = long.encode().unwrap();
+        // The reason length needs the 3-byte CompactSize form
+        assert_eq!(bytes[4], 0xfd);
+        assert_eq!(RejectMessage::decode_bytes(&bytes).unwrap(), long);
+
+        let bytes = reject_message(MAX_REJECT_REASON_LENGTH + 1)
+            .encode()
+            .unwrap();
+        let error = RejectMessage::decode_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::StringTooLong {
+                len: MAX_REJECT_REASON_LENGTH + 1,
+                max: MAX_REJECT_REASON_LENGTH
+            })
+        );
+    }
+
+    #[test]
+    fn decode_should_cap_message_at_command_size() {
+        let reject_message = |message_len: u64| RejectMessage {
+            message: "m".repeat(message_len as usize),
+            ccode: RejectCode::Malformed,
+            reason: "bad".to_string(),
+            data: vec![],
+        };
+
+        let longest = reject_message(MAX_REJECT_MESSAGE_LENGTH);
+        let bytes = longest.encode().unwrap();
+        assert_eq!(RejectMessage::decode_bytes(&bytes).unwrap(), longest);
+
+        let bytes = reject_message(MAX_REJECT_MESSAGE_LENGTH + 1)
+            .encode()
+            .unwrap();
+        let error = RejectMessage::decode_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::StringTooLong {
+                len: MAX_REJECT_MESSAGE_LENGTH + 1,
+                max: MAX_REJECT_MESSAGE_LENGTH
+            })
+        );
+    }
+
+    #[test]
+    fn reject_code_try_from_should_map_codes() {
+        assert_eq!(RejectCode::try_from(0x01), Ok(RejectCode::Malformed));
+        assert_eq!(RejectCode::try_from(0x11), Ok(RejectCode::Obsolete));
+        assert_eq!(
+            RejectCode::try_from(0x02),
+            Err(CodecError::UnknownRejectCode(0x02))
+        );
+    }
+}
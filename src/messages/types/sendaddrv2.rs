@@ -0,0 +1,24 @@
+empty_message!(
+    /// The “sendaddrv2” message signals support for receiving `addrv2` messages. Defined in
+    /// [BIP155](https://github.com/bitcoin/bips/blob/master/bip-0155.mediawiki).
+    ///
+    /// Source: https://github.com/bitcoin/bips/blob/master/bip-0155.mediawiki#signaling-support-and-relay
+    SendAddrV2Message,
+    SendAddrV2
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{CommandName, Decode, Encode};
+
+    #[test]
+    fn encode_decode_should_work() {
+        assert!(SendAddrV2Message::command_name().starts_with(b"sendaddrv2\x00"));
+        assert_eq!(SendAddrV2Message.encode().unwrap(), vec![]);
+        assert_eq!(
+            SendAddrV2Message::decode_bytes([]).unwrap(),
+            SendAddrV2Message
+        );
+    }
+}
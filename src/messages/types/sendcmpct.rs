@@ -0,0 +1,64 @@
+use crate::messages::{codec::CodecError, Command, CommandName, Decode, Encode};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+
+/// The “sendcmpct” message tells the receiving peer whether and in which version compact blocks should be announced.
+///
+/// Source: https://github.com/bitcoin/bips/blob/master/bip-0152.mediawiki
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SendCmpctMessage {
+    /// Whether new blocks should be announced by sending a `cmpctblock` message rather than an `inv` or `headers`
+    pub announce: bool,
+    /// Version of the compact blocks protocol (2 when witnesses are included)
+    pub version: u64,
+}
+
+impl CommandName for SendCmpctMessage {
+    fn command() -> Command {
+        Command::SendCmpct
+    }
+}
+
+impl Encode for SendCmpctMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(9);
+        self.encode_to(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    fn encode_to(&self, writer: &mut dyn Write) -> crate::Result<()> {
+        writer.write_u8(self.announce.into())?;
+        writer.write_u64::<LittleEndian>(self.version)?;
+        Ok(())
+    }
+}
+
+impl Decode for SendCmpctMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let announce = match bytes.read_u8()? {
+            0 => false,
+            1 => true,
+            value => Err(CodecError::InvalidBool(value))?,
+        };
+        Ok(Self {
+            announce,
+            version: bytes.read_u64::<LittleEndian>()?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_and_decode_should_round_trip() {
+        let message = SendCmpctMessage {
+            announce: false,
+            version: 2,
+        };
+        let encoded = message.encode().unwrap();
+        assert_eq!(encoded, hex::decode("000200000000000000").unwrap());
+        assert_eq!(SendCmpctMessage::decode_bytes(encoded).unwrap(), message);
+    }
+}
@@ -0,0 +1,24 @@
+empty_message!(
+    /// The “sendheaders” message tells the receiving peer to send new block announcements using a `headers` message
+    /// rather than an `inv` message.
+    ///
+    /// Source: https://developer.bitcoin.org/reference/p2p_networking.html#sendheaders
+    SendHeadersMessage,
+    SendHeaders
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{CommandName, Decode, Encode};
+
+    #[test]
+    fn encode_decode_should_work() {
+        assert!(SendHeadersMessage::command_name().starts_with(b"sendheaders\x00"));
+        assert_eq!(SendHeadersMessage.encode().unwrap(), vec![]);
+        assert_eq!(
+            SendHeadersMessage::decode_bytes([]).unwrap(),
+            SendHeadersMessage
+        );
+    }
+}
@@ -0,0 +1,62 @@
+use crate::{
+    constants::MAX_GETUTXOS_OUTPOINTS,
+    messages::{
+        codec::{read_vec_with_cap, Hash256, VarInt},
+        Command, CommandName, Decode, Encode,
+    },
+};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use std::io::{Read, Write};
+
+/// The “utxos” message answers a `getutxos` message with the outputs that were found unspent.
+///
+/// The results are kept as raw bytes, since decoding them requires parsing transaction outputs.
+///
+/// Source: https://github.com/bitcoin/bips/blob/master/bip-0064.mediawiki
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UtxosMessage {
+    /// Height of the peer's best block when the lookup was done
+    pub chain_height: i32,
+    /// Hash of the peer's best block
+    pub chain_tip_hash: Hash256,
+    /// Bitmap with one bit per requested outpoint (least significant bit first), set if the output is unspent
+    pub hit_bitmap: Vec<u8>,
+    /// CompactSize-prefixed list of the unspent outputs, one for every bit set in [`Self::hit_bitmap`]
+    pub results: Vec<u8>,
+}
+
+impl CommandName for UtxosMessage {
+    fn command() -> Command {
+        Command::Utxos
+    }
+}
+
+impl Encode for UtxosMessage {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
+        let mut buffer = Vec::with_capacity(45 + self.hit_bitmap.len() + self.results.len());
+        buffer.write_i32::<LittleEndian>(self.chain_height)?;
+        buffer.write_all(&self.chain_tip_hash.0)?;
+        buffer.write_all(&VarInt(self.hit_bitmap.len() as u64).encode()?)?;
+        buffer.write_all(&self.hit_bitmap)?;
+        buffer.write_all(&self.results)?;
+        Ok(buffer)
+    }
+}
+
+impl Decode for UtxosMessage {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let chain_height = bytes.read_i32::<LittleEndian>()?;
+        let chain_tip_hash = Hash256::decode(bytes)?;
+        let hit_bitmap = read_vec_with_cap(bytes, MAX_GETUTXOS_OUTPOINTS.div_ceil(8), |bytes| {
+            Ok(bytes.read_u8()?)
+        })?;
+        let mut results = Vec::new();
+        bytes.read_to_end(&mut results)?;
+        Ok(Self {
+            chain_height,
+            chain_tip_hash,
+            hit_bitmap,
+            results,
+        })
+    }
+}
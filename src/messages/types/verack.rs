@@ -1,27 +1,34 @@
-use crate::messages::{CommandName, Decode, Encode};
-use std::io::Read;
+use crate::messages::{codec::CodecError, Command, CommandName, Decode, Encode};
+use std::io::{Read, Write};
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct VerackMessage;
 
 impl CommandName for VerackMessage {
-    fn command_name() -> [u8; 12] {
-        *b"verack\x00\x00\x00\x00\x00\x00"
+    fn command() -> Command {
+        Command::Verack
     }
 }
 
 impl Encode for VerackMessage {
-    fn encode(&self) -> anyhow::Result<Vec<u8>> {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
         Ok(vec![])
     }
+
+    fn encode_to(&self, _writer: &mut dyn Write) -> crate::Result<()> {
+        Ok(())
+    }
 }
 impl Decode for VerackMessage {
-    fn decode(bytes: &mut impl Read) -> anyhow::Result<Self> {
+    /// Succeeds only if `bytes` is empty, i.e. a single read into a 1-byte buffer yields nothing
+    ///
+    /// The reader is expected to be bounded by the payload length, which is 0 for a verack.
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
         let mut buffer = [0u8; 1];
-        if bytes.read(&mut buffer)? != 0 {
-            return Err(anyhow::anyhow!("Invalid Encoding"));
+        match bytes.read(&mut buffer)? {
+            0 => Ok(VerackMessage),
+            _ => Err(CodecError::NonEmptyPayload)?,
         }
-        Ok(VerackMessage)
     }
 }
 
@@ -37,9 +44,15 @@ mod tests {
 
     #[test]
     fn decode_should_work() {
+        assert_eq!(VerackMessage::decode_bytes([]).unwrap(), VerackMessage);
+    }
+
+    #[test]
+    fn decode_should_reject_non_empty_payload() {
+        let error = VerackMessage::decode_bytes([0]).unwrap_err();
         assert_eq!(
-            VerackMessage::decode(&mut vec![].as_slice()).unwrap(),
-            VerackMessage
+            error.downcast_ref::<CodecError>(),
+            Some(&CodecError::NonEmptyPayload)
         );
     }
 }
@@ -1,10 +1,17 @@
-use crate::messages::{CommandName, Decode, Encode};
-use anyhow::anyhow;
+use crate::{
+    constants::MAX_USER_AGENT_LENGTH,
+    messages::{
+        codec::{decode_field, read_exact_len, CodecError, VarInt},
+        Command, CommandName, Decode, Encode,
+    },
+};
 use bitflags::bitflags;
 use byteorder::{BigEndian, LittleEndian, ReadBytesExt, WriteBytesExt};
 use std::{
+    fmt::{self, Debug, Display, Formatter},
     io::{Read, Write},
-    net::{IpAddr, Ipv6Addr, SocketAddr},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    str::FromStr,
 };
 
 bitflags! {
@@ -29,9 +36,177 @@ bitflags! {
         const NODE_COMPACT_FILTERS = 64;
         /// This is the same as NODE_NETWORK but the node has at least the last 288 blocks (last 2 days)
         const NODE_NETWORK_LIMITED = 1024;
+        /// This node supports the encrypted v2 transport protocol, see [BIP 0324](https://github.com/bitcoin/bips/blob/master/bip-0324.mediawiki)
+        const NODE_P2P_V2 = 2048;
+    }
+}
+
+/// Name and description of every named service bit, as documented on [`Services`]
+pub const SERVICE_DESCRIPTIONS: [(Services, &str, &str); 9] = [
+    (
+        Services::UNNAMED,
+        "UNNAMED",
+        "This node is not a full node. It may not be able to provide any data except for the transactions it originates.",
+    ),
+    (
+        Services::NODE_NETWORK,
+        "NODE_NETWORK",
+        "This node can be asked for full blocks instead of just headers.",
+    ),
+    (
+        Services::NODE_GETUTXO,
+        "NODE_GETUTXO",
+        "This is a full node capable of responding to the getutxo protocol request. This is not supported by any currently-maintained Bitcoin node.",
+    ),
+    (
+        Services::NODE_BLOOM,
+        "NODE_BLOOM",
+        "This is a full node capable and willing to handle bloom-filtered connections",
+    ),
+    (
+        Services::NODE_WITNESS,
+        "NODE_WITNESS",
+        "This is a full node that can be asked for blocks and transactions including witness data",
+    ),
+    (
+        Services::NODE_XTHIN,
+        "NODE_XTHIN",
+        "This is a full node that supports Xtreme Thinblocks. This is not supported by any currently-maintained Bitcoin node.",
+    ),
+    (
+        Services::NODE_COMPACT_FILTERS,
+        "NODE_COMPACT_FILTERS",
+        "See BIP 0157 (https://github.com/bitcoin/bips/blob/master/bip-0157.mediawiki)",
+    ),
+    (
+        Services::NODE_NETWORK_LIMITED,
+        "NODE_NETWORK_LIMITED",
+        "This is the same as NODE_NETWORK but the node has at least the last 288 blocks (last 2 days)",
+    ),
+    (
+        Services::NODE_P2P_V2,
+        "NODE_P2P_V2",
+        "This node supports the encrypted v2 transport protocol, see BIP 0324 (https://github.com/bitcoin/bips/blob/master/bip-0324.mediawiki)",
+    ),
+];
+
+impl Services {
+    /// Every service bit this crate has a name for
+    pub const ALL_KNOWN: Services = Services::all();
+
+    /// Returns the names of the set service bits, in the order of [`SERVICE_DESCRIPTIONS`]
+    pub fn to_names(&self) -> Vec<&'static str> {
+        self.describe().into_iter().map(|(name, _)| name).collect()
+    }
+
+    /// Combines the service bits with the given names, returning `None` if any name is unknown
+    pub fn from_names<'a>(names: impl IntoIterator<Item = &'a str>) -> Option<Services> {
+        names
+            .into_iter()
+            .try_fold(Services::empty(), |services, name| {
+                Some(services | Services::from_name(name)?)
+            })
+    }
+
+    /// Returns the `(name, description)` pairs of the set service bits
+    ///
+    /// `UNNAMED` is only described when no other bit is set, since its value is 0.
+    pub fn describe(&self) -> Vec<(&'static str, &'static str)> {
+        SERVICE_DESCRIPTIONS
+            .iter()
+            .filter(|(service, _, _)| {
+                if service.is_empty() {
+                    self.is_empty()
+                } else {
+                    self.contains(*service)
+                }
+            })
+            .map(|(_, name, description)| (*name, *description))
+            .collect()
+    }
+}
+
+impl Display for Services {
+    /// Formats the names of the set service bits separated by ` | `, e.g. `NODE_NETWORK | NODE_WITNESS`
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.to_names().join(" | "))
+    }
+}
+
+/// Errors that can occur while parsing [`Services`] from a string
+#[derive(Debug, PartialEq, Eq, thiserror::Error)]
+pub enum ServicesParseError {
+    #[error(
+        "no services given: expected a 64-bit integer or service flag names such as NODE_NETWORK"
+    )]
+    EmptyInput,
+    #[error("invalid services bitfield {0:?}: expected a 64-bit unsigned integer such as 1033")]
+    InvalidInteger(String),
+    #[error(
+        "unknown service flag name {0:?}: expected one of {}",
+        known_service_names()
+    )]
+    UnknownFlagName(String),
+}
+
+fn known_service_names() -> String {
+    SERVICE_DESCRIPTIONS
+        .iter()
+        .map(|(_, name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl FromStr for Services {
+    type Err = ServicesParseError;
+
+    /// Parses either a bitfield given as an integer (unknown bits are dropped) or flag names separated by `|` or `,`
+    ///
+    /// The names are the ones produced by `Display`, so e.g. `NODE_NETWORK | NODE_WITNESS` round-trips.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        if s.is_empty() {
+            return Err(ServicesParseError::EmptyInput);
+        }
+        if s.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '+') {
+            let bits = s
+                .parse()
+                .map_err(|_| ServicesParseError::InvalidInteger(s.to_string()))?;
+            return Ok(Services::from_bits_truncate(bits));
+        }
+        s.split(['|', ',']).map(str::trim).try_fold(
+            Services::empty(),
+            |services, name| match name {
+                "" => Err(ServicesParseError::EmptyInput),
+                name => Services::from_name(name)
+                    .map(|service| services | service)
+                    .ok_or_else(|| ServicesParseError::UnknownFlagName(name.to_string())),
+            },
+        )
     }
 }
 
+/// Deserializes from a string in any form accepted by [`Services::from_str`], e.g. in a config file
+impl<'de> serde::Deserialize<'de> for Services {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let services = String::deserialize(deserializer)?;
+        services.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Form of the IP address held by a [`NetworkAddress`], see [`NetworkAddress::address_kind`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AddressKind {
+    /// IPv4 address mapped into IPv6 (`::ffff:a.b.c.d`), the wire form of IPv4 addresses
+    Ipv4Mapped,
+    /// Plain IPv6 address
+    NativeIpv6,
+    /// Tor v2 onion address encoded in the OnionCat range `fd87:d87e:eb43::/48`
+    OnionCat,
+    /// The unspecified address (`::` or `0.0.0.0`), which reveals nothing about the node
+    Unspecified,
+}
+
 /// Network address of a node
 ///
 /// Source: https://en.bitcoin.it/wiki/Protocol_documentation#version
@@ -46,6 +221,18 @@ pub struct NetworkAddress {
 }
 
 impl NetworkAddress {
+    /// The IPv4-mapped `0.0.0.0:0` address with no services, which reveals nothing about the node
+    pub const UNSPECIFIED: Self = Self::unspecified(Services::empty());
+
+    /// The IPv4-mapped `0.0.0.0:0` address advertising `services`
+    pub const fn unspecified(services: Services) -> Self {
+        Self {
+            services,
+            ip_address: Ipv4Addr::UNSPECIFIED.to_ipv6_mapped(),
+            port: 0,
+        }
+    }
+
     fn new(services: Services, socket_address: SocketAddr) -> Self {
         Self {
             services,
@@ -56,10 +243,51 @@ impl NetworkAddress {
             port: socket_address.port(),
         }
     }
+
+    /// Creates an address holding `ip_address` verbatim, without mapping IPv4 addresses into IPv6
+    pub fn from_ipv6(services: Services, ip_address: Ipv6Addr, port: u16) -> Self {
+        Self {
+            services,
+            ip_address,
+            port,
+        }
+    }
+
+    /// Creates an address from the 16 bytes of its IP address as they appear on the wire
+    pub fn from_octets(services: Services, octets: [u8; 16], port: u16) -> Self {
+        Self::from_ipv6(services, Ipv6Addr::from(octets), port)
+    }
+
+    /// Classifies the form of the IP address, e.g. to tell whether an IPv4 address was mapped into IPv6
+    pub fn address_kind(&self) -> AddressKind {
+        const ONIONCAT_PREFIX: [u8; 6] = [0xfd, 0x87, 0xd8, 0x7e, 0xeb, 0x43];
+        match self.ip_address.to_ipv4_mapped() {
+            Some(ip_address) if ip_address.is_unspecified() => AddressKind::Unspecified,
+            Some(_) => AddressKind::Ipv4Mapped,
+            None if self.ip_address.is_unspecified() => AddressKind::Unspecified,
+            None if self.ip_address.octets().starts_with(&ONIONCAT_PREFIX) => AddressKind::OnionCat,
+            None => AddressKind::NativeIpv6,
+        }
+    }
+
+    /// Socket address that the node can be connected to, with IPv4-mapped addresses turned back into IPv4 ones
+    ///
+    /// Returns `None` for an unspecified IP address or port 0, which nodes advertise when they don't reveal where they
+    /// listen.
+    pub fn to_socket_addr(&self) -> Option<SocketAddr> {
+        let ip_address = match self.ip_address.to_ipv4_mapped() {
+            Some(ip_address) => IpAddr::V4(ip_address),
+            None => IpAddr::V6(self.ip_address),
+        };
+        if ip_address.is_unspecified() || self.port == 0 {
+            return None;
+        }
+        Some(SocketAddr::new(ip_address, self.port))
+    }
 }
 
 impl Encode for NetworkAddress {
-    fn encode(&self) -> anyhow::Result<Vec<u8>> {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
         let mut buffer = Vec::with_capacity(26);
         buffer.write_u64::<LittleEndian>(self.services.bits())?;
         buffer.write_all(&self.ip_address.octets()[..])?;
@@ -69,7 +297,7 @@ impl Encode for NetworkAddress {
 }
 
 impl Decode for NetworkAddress {
-    fn decode(bytes: &mut impl Read) -> anyhow::Result<Self> {
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
         let services = Services::from_bits_truncate(bytes.read_u64::<LittleEndian>()?);
         let ip_address = Ipv6Addr::from(bytes.read_u128::<BigEndian>()?);
         let port = bytes.read_u16::<BigEndian>()?;
@@ -105,6 +333,9 @@ pub struct VersionMessage {
     pub nonce: u64,
     /// User agent as defined by [BIP14](https://github.com/bitcoin/bips/blob/master/bip-0014.mediawiki)
     pub user_agent: String,
+    /// Whether the user agent received wasn't valid UTF-8, so its invalid sequences were replaced with `U+FFFD` and it
+    /// no longer encodes to the bytes that were received
+    pub user_agent_lossy: bool,
     /// Height of the transmitting node’s best block
     pub start_height: i32,
     /// Whether the remote peer should announce relayed transactions or not, see (BIP 0037)[https://github.com/bitcoin/bips/blob/master/bip-0037.mediawiki]
@@ -112,6 +343,7 @@ pub struct VersionMessage {
 }
 
 impl VersionMessage {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         version: i32,
         services: Services,
@@ -136,19 +368,46 @@ impl VersionMessage {
             ),
             nonce,
             user_agent,
+            user_agent_lossy: false,
             start_height,
             relay,
         }
     }
+
+    /// Formats like `Debug`, but with the nonce masked so that logs containing the message can be shared
+    pub fn redacted(&self) -> impl Debug + '_ {
+        RedactedVersionMessage(self)
+    }
+}
+
+/// [`Debug`] wrapper returned by [`VersionMessage::redacted`]
+struct RedactedVersionMessage<'a>(&'a VersionMessage);
+
+impl Debug for RedactedVersionMessage<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let message = self.0;
+        f.debug_struct("VersionMessage")
+            .field("version", &message.version)
+            .field("services", &message.services)
+            .field("timestamp", &message.timestamp)
+            .field("receiving_node", &message.receiving_node)
+            .field("transmitting_node", &message.transmitting_node)
+            .field("nonce", &format_args!("<redacted>"))
+            .field("user_agent", &message.user_agent)
+            .field("user_agent_lossy", &message.user_agent_lossy)
+            .field("start_height", &message.start_height)
+            .field("relay", &message.relay)
+            .finish()
+    }
 }
 
 impl CommandName for VersionMessage {
-    fn command_name() -> [u8; 12] {
-        *b"version\x00\x00\x00\x00\x00"
+    fn command() -> Command {
+        Command::Version
     }
 }
 impl Encode for VersionMessage {
-    fn encode(&self) -> anyhow::Result<Vec<u8>> {
+    fn encode(&self) -> crate::Result<Vec<u8>> {
         let mut buffer = Vec::with_capacity(85 + self.user_agent.len());
 
         buffer.write_i32::<LittleEndian>(self.version)?;
@@ -157,8 +416,12 @@ impl Encode for VersionMessage {
         buffer.write_all(&self.receiving_node.encode()?)?;
         buffer.write_all(&self.transmitting_node.encode()?)?;
         buffer.write_u64::<LittleEndian>(self.nonce)?;
-        buffer.write_u8(self.user_agent.len() as u8)?;
-        buffer.write_all(&self.user_agent.as_bytes())?;
+        let user_agent_len = self.user_agent.len() as u64;
+        if user_agent_len > MAX_USER_AGENT_LENGTH {
+            Err(CodecError::UserAgentTooLong(user_agent_len))?
+        }
+        buffer.write_all(&VarInt(user_agent_len).encode()?)?;
+        buffer.write_all(self.user_agent.as_bytes())?;
         buffer.write_i32::<LittleEndian>(self.start_height)?;
         buffer.write_u8(self.relay.into())?;
 
@@ -166,32 +429,50 @@ impl Encode for VersionMessage {
     }
 }
 impl Decode for VersionMessage {
-    fn decode(bytes: &mut impl Read) -> anyhow::Result<Self> {
-        let version = bytes.read_i32::<LittleEndian>()?;
-        let services = Services::from_bits_truncate(bytes.read_u64::<LittleEndian>()?);
-        let timestamp = bytes.read_i64::<LittleEndian>()?;
+    /// Fails with a [`CodecError::Field`] naming the field that could not be decoded
+    fn decode(bytes: &mut impl Read) -> crate::Result<Self> {
+        let version = decode_field("version", || Ok(bytes.read_i32::<LittleEndian>()?))?;
+        let services = decode_field("services", || {
+            Ok(Services::from_bits_truncate(
+                bytes.read_u64::<LittleEndian>()?,
+            ))
+        })?;
+        let timestamp = decode_field("timestamp", || Ok(bytes.read_i64::<LittleEndian>()?))?;
 
-        let mut encoded_receiving_node = [0u8; 26];
-        bytes.read_exact(&mut encoded_receiving_node)?;
-        let receiving_node = NetworkAddress::decode(&mut encoded_receiving_node.as_slice())?;
+        let receiving_node = decode_field("receiving_node", || {
+            let mut encoded_receiving_node = [0u8; 26];
+            bytes.read_exact(&mut encoded_receiving_node)?;
+            NetworkAddress::decode_bytes(encoded_receiving_node)
+        })?;
 
-        let mut encoded_transmitting_node = [0u8; 26];
-        bytes.read_exact(&mut encoded_transmitting_node)?;
-        let transmitting_node = NetworkAddress::decode(&mut encoded_transmitting_node.as_slice())?;
+        let transmitting_node = decode_field("transmitting_node", || {
+            let mut encoded_transmitting_node = [0u8; 26];
+            bytes.read_exact(&mut encoded_transmitting_node)?;
+            NetworkAddress::decode_bytes(encoded_transmitting_node)
+        })?;
 
-        let nonce = bytes.read_u64::<LittleEndian>()?;
+        let nonce = decode_field("nonce", || Ok(bytes.read_u64::<LittleEndian>()?))?;
 
-        let user_agent_len = bytes.read_u8()?;
-        let mut user_agent_bytes = vec![0u8; user_agent_len as usize];
-        bytes.read_exact(&mut user_agent_bytes)?;
-        let user_agent = String::from_utf8(user_agent_bytes)?;
+        // A peer sending a malformed user agent is still worth talking to, so it is decoded lossily instead of failing
+        let (user_agent, user_agent_lossy) = decode_field("user_agent", || {
+            let VarInt(user_agent_len) = VarInt::decode(bytes)?;
+            if user_agent_len > MAX_USER_AGENT_LENGTH {
+                Err(CodecError::UserAgentTooLong(user_agent_len))?
+            }
+            Ok(
+                match String::from_utf8(read_exact_len(bytes, user_agent_len)?) {
+                    Ok(user_agent) => (user_agent, false),
+                    Err(e) => (String::from_utf8_lossy(e.as_bytes()).into_owned(), true),
+                },
+            )
+        })?;
 
-        let start_height = bytes.read_i32::<LittleEndian>()?;
-        let relay: bool = match bytes.read_u8()? {
-            0 => false,
-            1 => true,
-            _ => return Err(anyhow!("Invalid relay encoding")),
-        };
+        let start_height = decode_field("start_height", || Ok(bytes.read_i32::<LittleEndian>()?))?;
+        let relay = decode_field("relay", || match bytes.read_u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            value => Err(CodecError::InvalidBool(value))?,
+        })?;
 
         Ok(Self {
             version,
@@ -201,6 +482,7 @@ impl Decode for VersionMessage {
             transmitting_node,
             nonce,
             user_agent,
+            user_agent_lossy,
             start_height,
             relay,
         })
@@ -211,6 +493,186 @@ impl Decode for VersionMessage {
 mod tests {
     use super::*;
 
+    #[test]
+    fn describe_should_list_set_service_bits() {
+        assert_eq!(
+            Services::NODE_NETWORK.describe(),
+            vec![(
+                "NODE_NETWORK",
+                "This node can be asked for full blocks instead of just headers."
+            )]
+        );
+        assert_eq!(
+            (Services::NODE_NETWORK | Services::NODE_WITNESS)
+                .describe()
+                .len(),
+            2
+        );
+        assert_eq!(Services::UNNAMED.describe()[0].0, "UNNAMED");
+    }
+
+    #[test]
+    fn services_should_know_node_p2p_v2() {
+        let services = Services::from_bits_truncate(2048);
+        assert_eq!(services, Services::NODE_P2P_V2);
+        assert!(Services::ALL_KNOWN.contains(Services::NODE_P2P_V2));
+        assert_eq!(
+            services.iter_names().collect::<Vec<_>>(),
+            vec![("NODE_P2P_V2", Services::NODE_P2P_V2)]
+        );
+
+        let services = Services::NODE_WITNESS | Services::NODE_P2P_V2;
+        assert_eq!(services.to_names(), vec!["NODE_WITNESS", "NODE_P2P_V2"]);
+        assert_eq!(Services::from_names(services.to_names()), Some(services));
+        assert_eq!(Services::from_names(["NODE_P2P_V3"]), None);
+    }
+
+    #[test]
+    fn services_display_should_join_names() {
+        assert_eq!(
+            (Services::NODE_NETWORK | Services::NODE_WITNESS).to_string(),
+            "NODE_NETWORK | NODE_WITNESS"
+        );
+        assert_eq!(Services::UNNAMED.to_string(), "UNNAMED");
+    }
+
+    #[test]
+    fn services_should_parse_integers_and_flag_names() {
+        assert_eq!(
+            Services::from_str("1033"),
+            Ok(Services::from_bits_truncate(1033))
+        );
+        assert_eq!(
+            Services::from_str("NODE_NETWORK | NODE_WITNESS"),
+            Ok(Services::NODE_NETWORK | Services::NODE_WITNESS)
+        );
+        assert_eq!(
+            Services::from_str("NODE_BLOOM,NODE_P2P_V2"),
+            Ok(Services::NODE_BLOOM | Services::NODE_P2P_V2)
+        );
+        assert_eq!(Services::from_str("UNNAMED"), Ok(Services::UNNAMED));
+    }
+
+    #[test]
+    fn services_should_deserialize_from_string() {
+        use serde::de::{value::Error, Deserialize, IntoDeserializer};
+
+        let deserialize =
+            |s: &str| Services::deserialize(s.into_deserializer()) as Result<_, Error>;
+        assert_eq!(
+            deserialize("NODE_NETWORK,NODE_WITNESS").unwrap(),
+            Services::NODE_NETWORK | Services::NODE_WITNESS
+        );
+        assert_eq!(
+            deserialize("1033").unwrap(),
+            Services::from_bits_truncate(1033)
+        );
+        let error = deserialize("NODE_FAST").unwrap_err();
+        assert!(error.to_string().contains("NODE_FAST"), "{error}");
+    }
+
+    #[test]
+    fn services_parse_errors_should_be_actionable() {
+        assert_eq!(
+            Services::from_str("  "),
+            Err(ServicesParseError::EmptyInput)
+        );
+        assert_eq!(
+            Services::from_str("NODE_NETWORK|"),
+            Err(ServicesParseError::EmptyInput)
+        );
+        assert_eq!(
+            Services::from_str("12abc"),
+            Err(ServicesParseError::InvalidInteger("12abc".to_string()))
+        );
+        assert_eq!(
+            Services::from_str("-1"),
+            Err(ServicesParseError::InvalidInteger("-1".to_string()))
+        );
+
+        let error = Services::from_str("NODE_NETWORK | NODE_FAST").unwrap_err();
+        assert_eq!(
+            error,
+            ServicesParseError::UnknownFlagName("NODE_FAST".to_string())
+        );
+        let message = error.to_string();
+        assert!(message.contains("\"NODE_FAST\""), "{message}");
+        assert!(message.contains("NODE_NETWORK, NODE_GETUTXO"), "{message}");
+    }
+
+    #[test]
+    fn network_address_from_octets_should_encode_verbatim() {
+        // An IPv4-compatible (not IPv4-mapped) address, which `NetworkAddress::new` could never produce
+        let octets = [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 10, 0, 0, 1];
+        let address = NetworkAddress::from_octets(Services::NODE_NETWORK, octets, 8333);
+        assert_eq!(
+            address,
+            NetworkAddress::from_ipv6(Services::NODE_NETWORK, Ipv6Addr::from(octets), 8333)
+        );
+        assert_eq!(
+            address.encode().unwrap(),
+            hex::decode("01000000000000000000000000000000000000000a000001208d").unwrap()
+        );
+    }
+
+    #[test]
+    fn unspecified_network_address_should_encode_zero_ip_and_port() {
+        let encoded = NetworkAddress::UNSPECIFIED.encode().unwrap();
+        assert_eq!(encoded.len(), 26);
+        assert_eq!(
+            encoded,
+            hex::decode("000000000000000000000000000000000000ffff000000000000").unwrap()
+        );
+        assert_eq!(
+            NetworkAddress::unspecified(Services::NODE_NETWORK)
+                .encode()
+                .unwrap()[..8],
+            1u64.to_le_bytes()
+        );
+    }
+
+    #[test]
+    fn address_kind_should_classify_every_form() {
+        let kind = |socket_address: &str| {
+            NetworkAddress::new(Services::empty(), socket_address.parse().unwrap()).address_kind()
+        };
+        assert_eq!(kind("1.2.3.4:8333"), AddressKind::Ipv4Mapped);
+        assert_eq!(kind("[2001:db8::1]:8333"), AddressKind::NativeIpv6);
+        assert_eq!(
+            kind("[fd87:d87e:eb43:edb1:8e4:3588:e546:35ca]:8333"),
+            AddressKind::OnionCat
+        );
+        assert_eq!(kind("0.0.0.0:0"), AddressKind::Unspecified);
+        assert_eq!(kind("[::]:8333"), AddressKind::Unspecified);
+        assert_eq!(
+            NetworkAddress::UNSPECIFIED.address_kind(),
+            AddressKind::Unspecified
+        );
+    }
+
+    #[test]
+    fn redacted_should_mask_nonce() {
+        let version_message = VersionMessage::new(
+            70015,
+            Services::NODE_NETWORK,
+            0,
+            Services::NODE_NETWORK,
+            "1.2.3.4:8333".parse().unwrap(),
+            "127.0.0.1:8333".parse().unwrap(),
+            Services::NODE_NETWORK,
+            0x0123456789abcdef,
+            "/Satoshi:25.0.0/".to_string(),
+            800000,
+            true,
+        );
+        let redacted = format!("{:?}", version_message.redacted());
+        assert!(!redacted.contains(&version_message.nonce.to_string()));
+        assert!(redacted.contains("nonce: <redacted>"));
+        assert!(redacted.contains("user_agent: \"/Satoshi:25.0.0/\""));
+
+        assert!(format!("{version_message:?}").contains(&version_message.nonce.to_string()));
+    }
+
     #[test]
     fn encode_should_work() {
         let verack_message = VersionMessage {
@@ -227,8 +689,9 @@ mod tests {
                 ip_address: Ipv6Addr::from([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255, 0, 0, 0, 0]),
                 port: 0,
             },
-            nonce: 0x6517E68C5DB32E3B as u64,
+            nonce: 0x6517E68C5DB32E3B,
             user_agent: "/Satoshi:0.7.2/".to_string(),
+            user_agent_lossy: false,
             start_height: 212672,
             relay: false,
         };
@@ -242,6 +705,22 @@ mod tests {
         )
     }
 
+    #[test]
+    fn encode_should_work_through_reference() {
+        fn encode_generic(message: impl Encode) -> Vec<u8> {
+            message.encode().unwrap()
+        }
+
+        let hex_string = "721101000100000000000000bc8f5e5400000000010000000000000000000000000000000000ffffc61b6409208d010000000000000000000000000000000000ffffcb0071c0208d128035cbc97953f80f2f5361746f7368693a302e392e332fcf05050001";
+        let version_message =
+            VersionMessage::decode_bytes(hex::decode(hex_string).unwrap()).unwrap();
+
+        assert_eq!(
+            encode_generic(&version_message),
+            hex::decode(hex_string).unwrap()
+        );
+    }
+
     #[test]
     fn decode_should_work() {
         // Hexdump example of version message taken from https://developer.bitcoin.org/reference/p2p_networking.html#version
@@ -249,7 +728,7 @@ mod tests {
         let bytes = hex::decode(hex_string).unwrap();
 
         assert_eq!(
-            VersionMessage::decode(&mut bytes.as_slice()).unwrap(),
+            VersionMessage::decode_bytes(&bytes).unwrap(),
             VersionMessage {
                 version: 70002,
                 services: Services::NODE_NETWORK,
@@ -270,9 +749,60 @@ mod tests {
                 },
                 nonce: 0xf85379c9cb358012,
                 user_agent: "/Satoshi:0.9.3/".to_string(),
+                user_agent_lossy: false,
                 start_height: 329167,
                 relay: true,
             }
         );
     }
+
+    #[test]
+    fn decode_should_replace_invalid_utf8_in_user_agent() {
+        let hex_string = "721101000100000000000000bc8f5e5400000000010000000000000000000000000000000000ffffc61b6409208d010000000000000000000000000000000000ffffcb0071c0208d128035cbc97953f80f2f5361746f7368693a302e392e332fcf05050001";
+        let mut bytes = hex::decode(hex_string).unwrap();
+        // Replaces the `S` of `/Satoshi:0.9.3/`, which follows the nonce and the user agent length
+        bytes[82] = 0xff;
+
+        let version_message = VersionMessage::decode_bytes(&bytes).unwrap();
+        assert_eq!(version_message.user_agent, "/\u{fffd}atoshi:0.9.3/");
+        assert!(version_message.user_agent_lossy);
+        assert_eq!(version_message.start_height, 329167);
+    }
+
+    #[test]
+    fn decode_should_name_the_field_that_failed() {
+        let hex_string = "721101000100000000000000bc8f5e5400000000010000000000000000000000000000000000ffffc61b6409208d010000000000000000000000000000000000ffffcb0071c0208d128035cbc97953f80f2f5361746f7368693a302e392e332fcf05050001";
+        let bytes = hex::decode(hex_string).unwrap();
+
+        // Cut right after the user agent, before the 4 bytes of the start height and the relay flag
+        let error = VersionMessage::decode_bytes(&bytes[..bytes.len() - 5]).unwrap_err();
+        let error = error.downcast_ref::<CodecError>().unwrap();
+        assert_eq!(
+            error,
+            &CodecError::Field {
+                name: "start_height",
+                source: Box::new(CodecError::UnexpectedEof),
+            }
+        );
+        assert_eq!(error.root(), &CodecError::UnexpectedEof);
+        assert_eq!(
+            error.to_string(),
+            "invalid `start_height` field: input ended before the message was complete"
+        );
+    }
+
+    #[test]
+    fn decode_should_reject_huge_user_agent_length_before_reading_it() {
+        let hex_string = "721101000100000000000000bc8f5e5400000000010000000000000000000000000000000000ffffc61b6409208d010000000000000000000000000000000000ffffcb0071c0208d128035cbc97953f80f2f5361746f7368693a302e392e332fcf05050001";
+        // Everything up to the nonce, followed by a length of 1 MiB and no user agent bytes at all, so the length must
+        // be rejected on its own rather than after running out of input
+        let mut bytes = hex::decode(hex_string).unwrap()[..80].to_vec();
+        bytes.extend(VarInt(1 << 20).encode().unwrap());
+
+        let error = VersionMessage::decode_bytes(&bytes).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<CodecError>().map(CodecError::root),
+            Some(&CodecError::UserAgentTooLong(1 << 20))
+        );
+    }
 }
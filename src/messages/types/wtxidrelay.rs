@@ -0,0 +1,24 @@
+empty_message!(
+    /// The “wtxidrelay” message signals that transactions should be announced by wtxid rather than txid. Defined in
+    /// [BIP339](https://github.com/bitcoin/bips/blob/master/bip-0339.mediawiki).
+    ///
+    /// Source: https://github.com/bitcoin/bips/blob/master/bip-0339.mediawiki
+    WtxidRelayMessage,
+    WtxidRelay
+);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::messages::{CommandName, Decode, Encode};
+
+    #[test]
+    fn encode_decode_should_work() {
+        assert!(WtxidRelayMessage::command_name().starts_with(b"wtxidrelay\x00"));
+        assert_eq!(WtxidRelayMessage.encode().unwrap(), vec![]);
+        assert_eq!(
+            WtxidRelayMessage::decode_bytes([]).unwrap(),
+            WtxidRelayMessage
+        );
+    }
+}
@@ -0,0 +1,93 @@
+//! Conformance vectors checking that every message type re-encodes a decoded payload to the same bytes
+
+use bitcoin_p2p::messages::{
+    codec::{Decode, Encode},
+    types::{
+        addr::AddrMessage, addrv2::AddrV2Message, blocktxn::BlockTxnMessage,
+        cmpctblock::CmpctBlockMessage, feefilter::FeeFilterMessage, filteradd::FilterAddMessage,
+        filterclear::FilterClearMessage, getblocktxn::GetBlockTxnMessage, getdata::GetDataMessage,
+        getheaders::GetHeadersMessage, getutxos::GetUtxosMessage, headers::HeadersMessage,
+        inv::InvMessage, merkleblock::MerkleBlockMessage, ping::PingMessage, pong::PongMessage,
+        reject::RejectMessage, sendaddrv2::SendAddrV2Message, sendcmpct::SendCmpctMessage,
+        sendheaders::SendHeadersMessage, utxos::UtxosMessage, verack::VerackMessage,
+        version::VersionMessage, wtxidrelay::WtxidRelayMessage,
+    },
+};
+
+/// Hash of the genesis block in internal byte order
+const GENESIS_BLOCK_HASH: &str = "6fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000";
+
+/// Decodes `hex` as an `M` and asserts that encoding the result gives back the same bytes
+fn round_trip<M: Decode + Encode>(hex: &str) -> anyhow::Result<()> {
+    let bytes = hex::decode(hex)?;
+    let encoded = M::decode_bytes(&bytes)?.encode()?;
+    anyhow::ensure!(
+        encoded == bytes,
+        "re-encoded as {} instead of {hex}",
+        hex::encode(&encoded)
+    );
+    Ok(())
+}
+
+/// Builds the `(type name, round trip check, payload hex)` table from `Type => hex` pairs
+macro_rules! vectors {
+    ($($message:ty => $hex:expr),* $(,)?) => {
+        vec![$((stringify!($message), round_trip::<$message> as fn(&str) -> anyhow::Result<()>, $hex.to_string())),*]
+    };
+}
+
+#[test]
+fn every_message_type_should_re_encode_to_source_bytes() {
+    let vectors = vectors! {
+        // Source: https://developer.bitcoin.org/reference/p2p_networking.html#version
+        VersionMessage => "721101000100000000000000bc8f5e5400000000010000000000000000000000000000000000ffffc61b6409208d010000000000000000000000000000000000ffffcb0071c0208d128035cbc97953f80f2f5361746f7368693a302e392e332fcf05050001",
+        VerackMessage => "",
+        PingMessage => "efcdab8967452301",
+        PongMessage => "efcdab8967452301",
+        // Source: https://en.bitcoin.it/wiki/Protocol_documentation#addr
+        AddrMessage => "01e215104d010000000000000000000000000000000000ffff0a000001208d",
+        AddrV2Message => concat!(
+            "02",
+            "5a5e8b65", "09", "01", "04", "01020304", "208d",
+            "5c5e8b65", "00", "04", "20", "0102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f20", "208d",
+        ),
+        SendAddrV2Message => "",
+        InvMessage => format!("0102000000{GENESIS_BLOCK_HASH}"),
+        GetDataMessage => format!("0101000000{GENESIS_BLOCK_HASH}"),
+        // Version 70015, the genesis block as the only locator and no stop hash
+        GetHeadersMessage => format!("7f11010001{GENESIS_BLOCK_HASH}{}", "00".repeat(32)),
+        // Source: genesis block header, followed by its (empty) transaction count
+        HeadersMessage => "010100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c00",
+        SendHeadersMessage => "",
+        SendCmpctMessage => "000200000000000000",
+        // Source: https://developer.bitcoin.org/reference/p2p_networking.html#feefilter
+        FeeFilterMessage => "7cbd000000000000",
+        // Source: https://developer.bitcoin.org/reference/p2p_networking.html#merkleblock
+        MerkleBlockMessage => "0100000082bb869cf3a793432a66e826e05a6fc37469f8efb7421dc880670100000000007f16c5962e8bd963659c793ce370d95f093bc7e367117b3c30c1f8fdd0d9728776381b4d4c86041b554b852907000000043612262624047ee87660be1a707519a443b1c1ce3d248cbfc6c15870f6c5daa2019f5b01d4195ecbc9398fbf3c3b1fa9bb3183301d7a1fb3bd174fcfa40a2b6541ed70551dd7e841883ab8f0b16bf04176b7d1480e4f0af9f3d4c3595768d06820d2a7bc994987302e5b1ac80fc425fe25f8b63169ea78e68fbaaefa59379bbf011d",
+        // Source: https://developer.bitcoin.org/reference/p2p_networking.html#reject
+        RejectMessage => "02747812156261642d74786e732d696e707574732d7370656e74394c8b8ac0b3e9d47a2f3a7c8ae17c44cf2a11c9db6fd5ded1b3a26d0b1d7ac1",
+        // Source: https://developer.bitcoin.org/reference/p2p_networking.html#filteradd
+        FilterAddMessage => "20fdacf9b3eb077412e7a968d2e4f11b9a9dee312d666187ed77ee7d26af16cb0b",
+        FilterClearMessage => "",
+        // Indexes 0, 2 and 3, differentially encoded as 0, 1 and 0
+        GetBlockTxnMessage => format!("{GENESIS_BLOCK_HASH}03000100"),
+        BlockTxnMessage => format!("{GENESIS_BLOCK_HASH}0100"),
+        // Genesis block header, a nonce, one short ID and no prefilled transactions
+        CmpctBlockMessage => "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c08070605040302010101020304050600",
+        WtxidRelayMessage => "",
+        // Checks the mempool for output 1 of the genesis coinbase transaction
+        GetUtxosMessage => "01013ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a01000000",
+        // One outpoint found unspent: version 1, height 0, 50 BTC to an empty script
+        UtxosMessage => format!("00000000{GENESIS_BLOCK_HASH}01010101000000000000000000f2052a0100000000"),
+    };
+
+    let failures: Vec<_> = vectors
+        .iter()
+        .filter_map(|(name, round_trip, hex)| {
+            round_trip(hex)
+                .err()
+                .map(|error| format!("{name}: {error}"))
+        })
+        .collect();
+    assert!(failures.is_empty(), "{failures:#?}");
+}